@@ -0,0 +1,181 @@
+use kinode_process_lib::{eth::Address as EthAddress, println, Address, Request};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::feed::PriceFeed;
+use crate::helpers::dex::DexRegistry;
+use crate::orders::now_secs;
+use crate::state::State;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertCondition {
+    /// price in ETH per whole token rises above value.
+    Above,
+    Below,
+    /// price moves value percent either way from the reference price.
+    PctChange,
+}
+
+/// watches one token's price. without a cooldown it fires once and is done,
+/// with one it fires again at most once per cooldown seconds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Alert {
+    pub id: u64,
+    pub chain_id: u64,
+    pub token: EthAddress,
+    pub symbol: String,
+    pub decimals: u8,
+    pub condition: AlertCondition,
+    pub value: f64,
+    /// what pct_change is measured from: the price at creation, then at every firing.
+    pub reference: f64,
+    pub cooldown: Option<u64>,
+    pub last_fired: Option<u64>,
+    pub fired: u64,
+    pub active: bool,
+}
+
+/// what the notification target gets when an alert fires, as JSON.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertFired {
+    pub id: u64,
+    pub chain_id: u64,
+    pub token: EthAddress,
+    pub symbol: String,
+    pub condition: AlertCondition,
+    pub value: f64,
+    pub price: f64,
+    pub at: u64,
+}
+
+impl Alert {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !self.value.is_finite() || self.value <= 0.0 {
+            return Err(anyhow::anyhow!("alert value should be above 0"));
+        }
+        if self.cooldown == Some(0) {
+            return Err(anyhow::anyhow!("cooldown should be at least 1 second"));
+        }
+        Ok(())
+    }
+
+    pub fn triggered(&self, price: f64) -> bool {
+        match self.condition {
+            AlertCondition::Above => price > self.value,
+            AlertCondition::Below => price < self.value,
+            AlertCondition::PctChange => {
+                self.reference > 0.0
+                    && ((price - self.reference) / self.reference).abs() * 100.0 >= self.value
+            }
+        }
+    }
+
+    fn cooling_down(&self, now: u64) -> bool {
+        match (self.cooldown, self.last_fired) {
+            (Some(cooldown), Some(last)) => now < last + cooldown,
+            _ => false,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self.condition {
+            AlertCondition::Above => format!("{} above {} ETH", self.symbol, self.value),
+            AlertCondition::Below => format!("{} below {} ETH", self.symbol, self.value),
+            AlertCondition::PctChange => format!(
+                "{} moves {}% from {} ETH",
+                self.symbol, self.value, self.reference
+            ),
+        }
+    }
+}
+
+/// a process, local or on another node, e.g. `node.os@notifier:notifier:publisher.os`.
+pub fn parse_target(target: &str) -> anyhow::Result<Address> {
+    Address::from_str(target).map_err(|e| anyhow::anyhow!("bad notification target: {:?}", e))
+}
+
+/// prices the token of every active alert on chain_id and fires the ones whose condition holds.
+pub fn check_alerts(
+    state: &mut State,
+    dexes: &mut DexRegistry,
+    prices: &mut PriceFeed,
+    chain_id: u64,
+) -> anyhow::Result<()> {
+    let ids: Vec<u64> = state
+        .alerts
+        .values()
+        .filter(|a| a.active && a.chain_id == chain_id)
+        .map(|a| a.id)
+        .collect();
+    if ids.is_empty() {
+        return Ok(());
+    }
+
+    let chain = state.chain(chain_id)?.clone();
+    let dexes = dexes.get(&chain)?;
+    let now = now_secs()?;
+
+    for id in ids {
+        let Some(alert) = state.alerts.get_mut(&id) else {
+            continue;
+        };
+        if alert.cooling_down(now) {
+            continue;
+        }
+        let price = match prices.weth_price(&chain, dexes, alert.token, alert.decimals) {
+            Ok(price) => price,
+            Err(e) => {
+                println!("trader: no price for alert {}: {:?}", id, e);
+                continue;
+            }
+        };
+        if !alert.triggered(price) {
+            continue;
+        }
+
+        println!(
+            "trader: alert {} fired, {}, now {} ETH",
+            id,
+            alert.describe(),
+            price
+        );
+        alert.fired += 1;
+        alert.last_fired = Some(now);
+        if alert.cooldown.is_some() {
+            alert.reference = price;
+        } else {
+            alert.active = false;
+        }
+
+        let Some(target) = &state.alert_target else {
+            continue;
+        };
+        let fired = AlertFired {
+            id,
+            chain_id,
+            token: alert.token,
+            symbol: alert.symbol.clone(),
+            condition: alert.condition,
+            value: alert.value,
+            price,
+            at: now,
+        };
+        // a notification that can't be sent doesn't stop the alert from having fired.
+        if let Err(e) = notify(target, &fired) {
+            println!(
+                "trader: couldn't notify {} of alert {}: {:?}",
+                target, id, e
+            );
+        }
+    }
+
+    state.save()
+}
+
+fn notify(target: &str, fired: &AlertFired) -> anyhow::Result<()> {
+    Request::new()
+        .target(parse_target(target)?)
+        .body(serde_json::to_vec(fired)?)
+        .send()
+}
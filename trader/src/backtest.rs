@@ -0,0 +1,705 @@
+use kinode_process_lib::{
+    eth::{Address as EthAddress, U256},
+    println,
+    vfs::{create_drive, open_file},
+    Address as ProcessAddress,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::str::FromStr;
+
+use crate::grid::{Grid, GridStatus};
+use crate::helpers::{
+    calls::{get_erc20_info, get_reserves, to_units, v2_amount_out},
+    chains::ChainConfig,
+    dex::Dex,
+    fees::swap_gas_price,
+    router::BASE_SWAP_GAS,
+};
+use crate::orders::{LimitOrder, OrderStatus, Side};
+use crate::state::State;
+use crate::strategy::{Action, Order, Outcome, StrategyKind, Tick, MAX_ACTIONS_PER_TICK};
+use crate::timers::{schedule, TimerContext};
+
+// every sample is an eth_call, past this it's better to record in pieces.
+pub const MAX_SAMPLES: u64 = 5_000;
+// samples taken per timer tick, so requests and logs get handled while a dataset records.
+const SAMPLES_PER_TICK: u64 = 100;
+
+/// a token/WETH pair's reserves at one past block.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sample {
+    pub block: u64,
+    pub reserve_token: U256,
+    pub reserve_weth: U256,
+}
+
+impl Sample {
+    /// spot price, ETH per whole token.
+    pub fn price(&self, decimals: u8) -> f64 {
+        to_units(self.reserve_weth, 18) / to_units(self.reserve_token, decimals)
+    }
+}
+
+/// getReserves of one token/WETH pair, sampled every few blocks and kept in the package's
+/// backtests drive so the same history can be replayed against different setups.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Dataset {
+    pub name: String,
+    pub chain_id: u64,
+    pub token: EthAddress,
+    pub symbol: String,
+    pub decimals: u8,
+    pub dex: String,
+    pub pair: EthAddress,
+    pub fee_bps: u64,
+    /// the gas price when it was recorded, eth_call can't tell us past gas prices.
+    pub gas_price: U256,
+    pub samples: Vec<Sample>,
+}
+
+impl Dataset {
+    /// an empty dataset on the first pair we can simulate, Recording fills in the samples.
+    pub fn new(
+        chain: &ChainConfig,
+        dexes: &[Box<dyn Dex>],
+        name: &str,
+        token: EthAddress,
+    ) -> anyhow::Result<Self> {
+        check_name(name)?;
+
+        // only pools whose math we can replay, see Dex::fee_bps.
+        let (dex, pair, fee_bps) = dexes
+            .iter()
+            .find_map(|dex| {
+                let fee_bps = dex.fee_bps()?;
+                let pair = dex.get_pair(chain.weth, token).ok().flatten()?;
+                Some((dex, pair, fee_bps))
+            })
+            .ok_or_else(|| anyhow::anyhow!("no constant product WETH pair for {:?}", token))?;
+        let (decimals, symbol) = get_erc20_info(chain.chain_id, token)?;
+
+        Ok(Dataset {
+            name: name.to_string(),
+            chain_id: chain.chain_id,
+            token,
+            symbol,
+            decimals: decimals.to::<u8>(),
+            dex: dex.name().to_string(),
+            pair,
+            fee_bps,
+            gas_price: chain.provider().get_gas_price()?,
+            samples: vec![],
+        })
+    }
+
+    pub fn save(&self, our: &ProcessAddress) -> anyhow::Result<String> {
+        let path = format!("{}/{}.json", backtests_drive(our)?, self.name);
+        open_file(&path, true)?.write(&serde_json::to_vec(self)?)?;
+        Ok(path)
+    }
+
+    pub fn load(our: &ProcessAddress, name: &str) -> anyhow::Result<Self> {
+        check_name(name)?;
+        let path = format!("{}/{}.json", backtests_drive(our)?, name);
+        let json = open_file(&path, false)?.read_to_string()?;
+        Ok(serde_json::from_str(&json)?)
+    }
+}
+
+/// a dataset being sampled every step blocks, SAMPLES_PER_TICK of them per timer tick.
+/// it's saved to the backtests drive once the last block is sampled.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recording {
+    pub id: u64,
+    pub dataset: Dataset,
+    /// the next block to sample.
+    pub next_block: u64,
+    pub step: u64,
+    /// samples still to take.
+    pub remaining: u64,
+    /// blocks without reserves so far.
+    pub missed: u64,
+}
+
+impl Recording {
+    pub fn new(
+        id: u64,
+        dataset: Dataset,
+        from_block: u64,
+        to_block: u64,
+        step: u64,
+    ) -> anyhow::Result<Self> {
+        if step == 0 || to_block < from_block {
+            return Err(anyhow::anyhow!(
+                "need from_block <= to_block and a step of 1 or more"
+            ));
+        }
+        let samples = (to_block - from_block) / step + 1;
+        if samples > MAX_SAMPLES {
+            return Err(anyhow::anyhow!(
+                "that's more than {} samples, use a bigger step",
+                MAX_SAMPLES
+            ));
+        }
+        Ok(Recording {
+            id,
+            dataset,
+            next_block: from_block,
+            step,
+            remaining: samples,
+            missed: 0,
+        })
+    }
+
+    pub fn arm(&self) -> anyhow::Result<()> {
+        schedule(&TimerContext::Record(self.id), 0)
+    }
+
+    /// takes the next SAMPLES_PER_TICK samples.
+    fn advance(&mut self) {
+        let dataset = &mut self.dataset;
+        for _ in 0..self.remaining.min(SAMPLES_PER_TICK) {
+            let block = self.next_block;
+            // the pair may not exist yet that far back.
+            match get_reserves(dataset.chain_id, dataset.pair, dataset.token, Some(block)) {
+                Ok((reserve_token, reserve_weth)) if reserve_token > U256::ZERO => {
+                    dataset.samples.push(Sample {
+                        block,
+                        reserve_token,
+                        reserve_weth,
+                    })
+                }
+                _ => self.missed += 1,
+            }
+            self.next_block = self.next_block.saturating_add(self.step);
+            self.remaining -= 1;
+        }
+    }
+}
+
+/// the next chunk of a recording, saving the dataset once it's complete.
+pub fn run_recording(our: &ProcessAddress, state: &mut State, id: u64) -> anyhow::Result<()> {
+    let Some(recording) = state.recordings.get_mut(&id) else {
+        return Ok(());
+    };
+    recording.advance();
+    if recording.remaining > 0 {
+        recording.arm()?;
+        return state.save();
+    }
+
+    let Some(recording) = state.recordings.remove(&id) else {
+        return Ok(());
+    };
+    state.save()?;
+    let dataset = recording.dataset;
+    if dataset.samples.is_empty() {
+        return Err(anyhow::anyhow!(
+            "no reserves of {} between those blocks, nothing saved",
+            dataset.symbol
+        ));
+    }
+    if recording.missed > 0 {
+        println!(
+            "trader: {} blocks had no reserves, skipped them",
+            recording.missed
+        );
+    }
+    let path = dataset.save(our)?;
+    println!(
+        "trader: recorded {} samples of {} on {}, saved to {}",
+        dataset.samples.len(),
+        dataset.symbol,
+        dataset.dex,
+        path
+    );
+    Ok(())
+}
+
+/// what gets replayed. amounts are wei strings like everywhere else.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum BacktestSubject {
+    Strategy(StrategyKind),
+    Grid {
+        lower: f64,
+        upper: f64,
+        levels: u64,
+        capital_per_level: String,
+    },
+    LimitOrder {
+        side: Side,
+        amount: String,
+        limit_price: f64,
+    },
+}
+
+/// a simulated swap. price is what the fill paid, impact how far that is from spot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimTrade {
+    pub block: u64,
+    pub side: Side,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    pub price: f64,
+    pub impact_pct: f64,
+    pub gas_wei: U256,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BacktestReport {
+    pub dataset: String,
+    pub subject: String,
+    pub start_equity: f64,
+    pub end_equity: f64,
+    pub return_pct: f64,
+    pub max_drawdown_pct: f64,
+    /// mean over standard deviation of per-sample returns, not annualized.
+    pub sharpe: f64,
+    pub gas_eth: f64,
+    pub trades: Vec<SimTrade>,
+    /// (block, equity in ETH) at every sample.
+    pub equity: Vec<(u64, f64)>,
+}
+
+impl BacktestReport {
+    pub fn save(&self, our: &ProcessAddress) -> anyhow::Result<String> {
+        let path = format!("{}/{}.report.json", backtests_drive(our)?, self.dataset);
+        open_file(&path, true)?.write(&serde_json::to_vec(self)?)?;
+        Ok(path)
+    }
+
+    pub fn print(&self) {
+        println!("backtest of {} over {}", self.subject, self.dataset);
+        println!(
+            "equity {} -> {} ETH ({:+.2}%), max drawdown {:.2}%, sharpe {:.3}, gas {} ETH",
+            self.start_equity,
+            self.end_equity,
+            self.return_pct,
+            self.max_drawdown_pct,
+            self.sharpe,
+            self.gas_eth
+        );
+
+        println!("+------------+------+----------------------+----------------------+------------------+----------+");
+        println!("| Block      | Side | In                   | Out                  | Price (ETH)      | Impact   |");
+        println!("+------------+------+----------------------+----------------------+------------------+----------+");
+        for trade in &self.trades {
+            println!(
+                "| {:<10} | {:<4} | {:<20} | {:<20} | {:<16.10} | {:<7.3}% |",
+                trade.block,
+                format!("{:?}", trade.side),
+                trade.amount_in,
+                trade.amount_out,
+                trade.price,
+                trade.impact_pct
+            );
+        }
+        println!("+------------+------+----------------------+----------------------+------------------+----------+");
+
+        // 20 rows is enough to see the shape.
+        let high = self.equity.iter().map(|(_, e)| *e).fold(f64::MIN, f64::max);
+        let low = self.equity.iter().map(|(_, e)| *e).fold(f64::MAX, f64::min);
+        let every = (self.equity.len() / 20).max(1);
+        for (block, equity) in self.equity.iter().step_by(every) {
+            let width = if high > low {
+                ((equity - low) / (high - low) * 40.0).round() as usize
+            } else {
+                20
+            };
+            println!("{:<10} {:<14.8} {}", block, equity, "#".repeat(width + 1));
+        }
+    }
+}
+
+/// replays dataset through subject, starting with eth wei and no tokens.
+/// fills use the pool's own math on the recorded reserves, so fees and price impact are real,
+/// and every swap pays BASE_SWAP_GAS at gas_price. our own trades move the pool until the next sample.
+pub fn run_backtest(
+    dataset: &Dataset,
+    subject: BacktestSubject,
+    eth: U256,
+    gas_price: U256,
+) -> anyhow::Result<BacktestReport> {
+    if eth == U256::ZERO {
+        return Err(anyhow::anyhow!("a backtest needs some ETH to start with"));
+    }
+    let mut sim = Sim {
+        eth,
+        tokens: U256::ZERO,
+        gas_price,
+        fee_bps: dataset.fee_bps,
+        decimals: dataset.decimals,
+        block: 0,
+        reserve_token: U256::ZERO,
+        reserve_weth: U256::ZERO,
+        trades: vec![],
+    };
+    let mut runner = Runner::new(dataset, subject)?;
+    let description = runner.describe();
+
+    let mut equity = vec![];
+    for sample in &dataset.samples {
+        sim.block = sample.block;
+        sim.reserve_token = sample.reserve_token;
+        sim.reserve_weth = sample.reserve_weth;
+        let price = sample.price(dataset.decimals);
+
+        runner.step(&mut sim, dataset.token, price);
+        // marked at the recorded spot, not the pool we just moved.
+        equity.push((
+            sample.block,
+            to_units(sim.eth, 18) + to_units(sim.tokens, dataset.decimals) * price,
+        ));
+    }
+
+    let start_equity = to_units(eth, 18);
+    let end_equity = equity.last().map(|(_, e)| *e).unwrap_or(start_equity);
+    let gas_wei = sim
+        .trades
+        .iter()
+        .fold(U256::ZERO, |total, trade| total + trade.gas_wei);
+    Ok(BacktestReport {
+        dataset: dataset.name.clone(),
+        subject: description,
+        start_equity,
+        end_equity,
+        return_pct: (end_equity / start_equity - 1.0) * 100.0,
+        max_drawdown_pct: max_drawdown_pct(&equity),
+        sharpe: sharpe(&equity),
+        gas_eth: to_units(gas_wei, 18),
+        trades: sim.trades,
+        equity,
+    })
+}
+
+/// the account and pool a backtest trades against.
+struct Sim {
+    eth: U256,
+    tokens: U256,
+    gas_price: U256,
+    fee_bps: u64,
+    decimals: u8,
+    block: u64,
+    reserve_token: U256,
+    reserve_weth: U256,
+    trades: Vec<SimTrade>,
+}
+
+impl Sim {
+    fn quote(&self, side: Side, amount_in: U256) -> U256 {
+        match side {
+            Side::Buy => v2_amount_out(
+                amount_in,
+                self.reserve_weth,
+                self.reserve_token,
+                self.fee_bps,
+            ),
+            Side::Sell => v2_amount_out(
+                amount_in,
+                self.reserve_token,
+                self.reserve_weth,
+                self.fee_bps,
+            ),
+        }
+    }
+
+    fn fill(&mut self, side: Side, amount_in: U256, min_out: U256) -> anyhow::Result<U256> {
+        let gas_wei = swap_gas_price(self.gas_price) * U256::from(BASE_SWAP_GAS);
+        let amount_out = self.quote(side, amount_in);
+        if amount_in == U256::ZERO || amount_out < min_out {
+            return Err(anyhow::anyhow!(
+                "fill {} is below the required minimum {}",
+                amount_out,
+                min_out
+            ));
+        }
+        let spot = to_units(self.reserve_weth, 18) / to_units(self.reserve_token, self.decimals);
+
+        let price = match side {
+            Side::Buy => {
+                if self.eth < amount_in + gas_wei {
+                    return Err(anyhow::anyhow!("not enough ETH for {} plus gas", amount_in));
+                }
+                self.eth -= amount_in + gas_wei;
+                self.tokens += amount_out;
+                self.reserve_weth += amount_in;
+                self.reserve_token -= amount_out;
+                to_units(amount_in, 18) / to_units(amount_out, self.decimals)
+            }
+            Side::Sell => {
+                if self.tokens < amount_in || self.eth + amount_out < gas_wei {
+                    return Err(anyhow::anyhow!("not enough tokens or ETH for gas"));
+                }
+                self.tokens -= amount_in;
+                self.eth = self.eth + amount_out - gas_wei;
+                self.reserve_token += amount_in;
+                self.reserve_weth -= amount_out;
+                to_units(amount_out, 18) / to_units(amount_in, self.decimals)
+            }
+        };
+        self.trades.push(SimTrade {
+            block: self.block,
+            side,
+            amount_in,
+            amount_out,
+            price,
+            impact_pct: (price / spot - 1.0).abs() * 100.0,
+            gas_wei,
+        });
+        Ok(amount_out)
+    }
+}
+
+/// drives the same decision code the watcher does, with the sim in place of the chain.
+enum Runner {
+    Strategy { kind: StrategyKind, running: bool },
+    Grid(Grid),
+    LimitOrder(LimitOrder),
+}
+
+impl Runner {
+    fn new(dataset: &Dataset, subject: BacktestSubject) -> anyhow::Result<Self> {
+        match subject {
+            BacktestSubject::Strategy(kind) => {
+                kind.strategy().validate()?;
+                if let Some(other) = kind
+                    .strategy()
+                    .tokens()
+                    .into_iter()
+                    .find(|t| *t != dataset.token)
+                {
+                    return Err(anyhow::anyhow!(
+                        "strategy needs {:?}, the dataset only has {}",
+                        other,
+                        dataset.symbol
+                    ));
+                }
+                Ok(Runner::Strategy {
+                    kind,
+                    running: true,
+                })
+            }
+            BacktestSubject::Grid {
+                lower,
+                upper,
+                levels,
+                capital_per_level,
+            } => Ok(Runner::Grid(Grid {
+                id: 0,
+                chain_id: dataset.chain_id,
+                paper: true,
+                token: dataset.token,
+                symbol: dataset.symbol.clone(),
+                decimals: dataset.decimals,
+                capital_per_level: U256::from_str(&capital_per_level)?,
+                bands: Grid::bands(lower, upper, levels)?,
+                cycles: vec![],
+                status: GridStatus::Running,
+            })),
+            BacktestSubject::LimitOrder {
+                side,
+                amount,
+                limit_price,
+            } => Ok(Runner::LimitOrder(LimitOrder {
+                id: 0,
+                chain_id: dataset.chain_id,
+                paper: true,
+                token: dataset.token,
+                symbol: dataset.symbol.clone(),
+                decimals: dataset.decimals,
+                side,
+                amount: U256::from_str(&amount)?,
+                limit_price,
+                expiry: None,
+                status: OrderStatus::Open,
+            })),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Runner::Strategy { kind, .. } => kind.strategy().describe(),
+            Runner::Grid(grid) => format!(
+                "grid of {} bands, {} wei each",
+                grid.bands.len(),
+                grid.capital_per_level
+            ),
+            Runner::LimitOrder(order) => format!(
+                "limit {:?} of {} at {} ETH",
+                order.side, order.amount, order.limit_price
+            ),
+        }
+    }
+
+    fn step(&mut self, sim: &mut Sim, token: EthAddress, price: f64) {
+        match self {
+            Runner::Strategy { kind, running } => {
+                if !*running {
+                    return;
+                }
+                let strategy = kind.strategy_mut();
+                let tick = Tick {
+                    // blocks stand in for time, strategies only compare them.
+                    now: sim.block,
+                    gas_price: sim.gas_price,
+                };
+                let mut actions: VecDeque<Action> = strategy.on_tick(&tick).into();
+                actions.extend(strategy.on_price(token, price, &tick));
+
+                let mut handled = 0;
+                while let Some(action) = actions.pop_front() {
+                    handled += 1;
+                    if handled > MAX_ACTIONS_PER_TICK {
+                        break;
+                    }
+                    let (side, order) = match action {
+                        Action::Cancel { .. } => {
+                            *running = false;
+                            break;
+                        }
+                        Action::Quote {
+                            token,
+                            side,
+                            amount,
+                        } => {
+                            let outcome = Outcome::Quoted {
+                                token,
+                                side,
+                                amount_in: amount,
+                                amount_out: sim.quote(side, amount),
+                            };
+                            actions.extend(strategy.on_fill(&outcome));
+                            continue;
+                        }
+                        Action::Buy(order) => (Side::Buy, order),
+                        Action::Sell(order) => (Side::Sell, order),
+                    };
+                    let outcome = sim_trade(sim, side, &order);
+                    actions.extend(strategy.on_fill(&outcome));
+                }
+            }
+            Runner::Grid(grid) => {
+                let Some((i, side)) = grid.next_action(price) else {
+                    return;
+                };
+                let (amount, min_out) = grid.order(i, side, sim.tokens);
+                if let Ok(out) = sim.fill(side, amount, min_out) {
+                    grid.filled(i, side, out);
+                }
+            }
+            Runner::LimitOrder(order) => {
+                if order.is_open()
+                    && order.crossed(price)
+                    && sim.fill(order.side, order.amount, order.min_out()).is_ok()
+                {
+                    order.status = OrderStatus::Filled {
+                        tx_hash: Default::default(),
+                    };
+                }
+            }
+        }
+    }
+}
+
+fn sim_trade(sim: &mut Sim, side: Side, order: &Order) -> Outcome {
+    match sim.fill(side, order.amount, order.min_out) {
+        Ok(amount_out) => Outcome::Filled {
+            token: order.token,
+            side,
+            amount_in: order.amount,
+            quoted_out: amount_out,
+            tx_hash: Default::default(),
+        },
+        Err(e) => Outcome::Rejected {
+            token: order.token,
+            side,
+            reason: format!("{:?}", e),
+        },
+    }
+}
+
+fn max_drawdown_pct(equity: &[(u64, f64)]) -> f64 {
+    let mut peak = f64::MIN;
+    let mut worst: f64 = 0.0;
+    for (_, e) in equity {
+        peak = peak.max(*e);
+        if peak > 0.0 {
+            worst = worst.max((peak - e) / peak * 100.0);
+        }
+    }
+    worst
+}
+
+fn sharpe(equity: &[(u64, f64)]) -> f64 {
+    let returns: Vec<f64> = equity
+        .windows(2)
+        .filter(|w| w[0].1 > 0.0)
+        .map(|w| w[1].1 / w[0].1 - 1.0)
+        .collect();
+    if returns.len() < 2 {
+        return 0.0;
+    }
+    let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+    let variance =
+        returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (returns.len() - 1) as f64;
+    if variance == 0.0 {
+        return 0.0;
+    }
+    mean / variance.sqrt()
+}
+
+fn backtests_drive(our: &ProcessAddress) -> anyhow::Result<String> {
+    create_drive(our.package_id(), "backtests")
+}
+
+// names end up in a vfs path.
+fn check_name(name: &str) -> anyhow::Result<()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(anyhow::anyhow!(
+            "dataset names are letters, digits, - and _"
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn equity(values: &[f64]) -> Vec<(u64, f64)> {
+        values
+            .iter()
+            .copied()
+            .enumerate()
+            .map(|(i, e)| (i as u64, e))
+            .collect()
+    }
+
+    #[test]
+    fn drawdown_is_from_the_highest_peak() {
+        assert_eq!(
+            max_drawdown_pct(&equity(&[1.0, 2.0, 1.5, 3.0, 1.5, 2.5])),
+            50.0
+        );
+        assert_eq!(max_drawdown_pct(&equity(&[1.0, 2.0, 3.0])), 0.0);
+        assert_eq!(max_drawdown_pct(&[]), 0.0);
+    }
+
+    #[test]
+    fn sharpe_is_mean_over_deviation_of_returns() {
+        // returns of +10%, -10%, +10%.
+        let sharpe = sharpe(&equity(&[100.0, 110.0, 99.0, 108.9]));
+        assert!((sharpe - 1.0 / (2.0 * 3f64.sqrt())).abs() < 1e-9);
+    }
+
+    #[test]
+    fn sharpe_needs_returns_that_vary() {
+        assert_eq!(sharpe(&equity(&[1.0, 2.0])), 0.0);
+        assert_eq!(sharpe(&equity(&[1.0, 2.0, 4.0])), 0.0);
+    }
+}
@@ -0,0 +1,352 @@
+use alloy_sol_types::SolEvent;
+use kinode_process_lib::{
+    eth::{Address as EthAddress, Filter, Log, U256},
+    kv::{self, Kv},
+    Address as ProcessAddress,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::helpers::{
+    calls::{get_block_time, to_units},
+    chains::ChainConfig,
+    contracts::IUniswapV2Pair,
+};
+use crate::watcher::WatchEntry;
+
+// candles stored under one kv key, a day of 1m candles.
+const PAGE_CANDLES: u64 = 1_440;
+// most candles one read spans, so a wide range can't walk pages for ever.
+const MAX_CANDLES: u64 = 10 * PAGE_CANDLES;
+// history a new watchlist entry starts with, a few hours on most L2s.
+pub const DEFAULT_BACKFILL_BLOCKS: u64 = 10_000;
+// blocks per eth_getLogs, providers refuse wider ranges.
+const LOG_RANGE: u64 = 2_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Interval {
+    #[serde(rename = "1m")]
+    M1,
+    #[serde(rename = "5m")]
+    M5,
+    #[serde(rename = "1h")]
+    H1,
+    #[serde(rename = "1d")]
+    D1,
+}
+
+impl Interval {
+    pub const ALL: [Interval; 4] = [Interval::M1, Interval::M5, Interval::H1, Interval::D1];
+
+    pub fn secs(&self) -> u64 {
+        match self {
+            Interval::M1 => 60,
+            Interval::M5 => 5 * 60,
+            Interval::H1 => 60 * 60,
+            Interval::D1 => 24 * 60 * 60,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Interval::M1 => "1m",
+            Interval::M5 => "5m",
+            Interval::H1 => "1h",
+            Interval::D1 => "1d",
+        }
+    }
+
+    /// start of the candle at falls in.
+    pub fn open_time(&self, at: u64) -> u64 {
+        at - at % self.secs()
+    }
+}
+
+/// one swap on a watched pair, priced in ETH per whole token.
+#[derive(Debug, Clone, Copy)]
+pub struct Trade {
+    pub at: u64,
+    pub price: f64,
+    /// whole tokens that changed hands.
+    pub amount: f64,
+    pub amount_eth: f64,
+}
+
+impl Trade {
+    /// the trade a Swap log records, if it moved both sides.
+    pub fn from_swap(log: &Log, entry: &WatchEntry, weth: EthAddress, at: u64) -> Option<Self> {
+        let swap = IUniswapV2Pair::Swap::decode_raw_log(
+            log.topics().iter().copied(),
+            &log.data().data,
+            true,
+        )
+        .ok()?;
+        // V2 pairs sort their tokens by address.
+        let (eth, tokens) = if weth < entry.token {
+            (
+                swap.amount0In + swap.amount0Out,
+                swap.amount1In + swap.amount1Out,
+            )
+        } else {
+            (
+                swap.amount1In + swap.amount1Out,
+                swap.amount0In + swap.amount0Out,
+            )
+        };
+        if eth == U256::ZERO || tokens == U256::ZERO {
+            return None;
+        }
+        let amount = to_units(tokens, entry.decimals);
+        let amount_eth = to_units(eth, 18);
+        Some(Trade {
+            at,
+            price: amount_eth / amount,
+            amount,
+            amount_eth,
+        })
+    }
+}
+
+/// open/high/low/close in ETH per whole token, volume in whole tokens and in ETH.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Candle {
+    pub open_time: u64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
+    pub volume_eth: f64,
+    pub trades: u64,
+}
+
+impl Candle {
+    fn new(open_time: u64, trade: &Trade) -> Self {
+        Candle {
+            open_time,
+            open: trade.price,
+            high: trade.price,
+            low: trade.price,
+            close: trade.price,
+            volume: trade.amount,
+            volume_eth: trade.amount_eth,
+            trades: 1,
+        }
+    }
+
+    fn add(&mut self, trade: &Trade) {
+        self.high = self.high.max(trade.price);
+        self.low = self.low.min(trade.price);
+        self.close = trade.price;
+        self.volume += trade.amount;
+        self.volume_eth += trade.amount_eth;
+        self.trades += 1;
+    }
+}
+
+type SeriesKey = (u64, EthAddress, Interval);
+
+/// candles per watched pair, built from its Swap logs and kept in kv a page at a time.
+/// the candle still being built lives in memory and is written out on every flush.
+#[derive(Default)]
+pub struct CandleStore {
+    db: Option<Kv>,
+    building: HashMap<SeriesKey, Candle>,
+    dirty: HashSet<SeriesKey>,
+}
+
+impl CandleStore {
+    pub fn open(&mut self, our: &ProcessAddress) -> anyhow::Result<()> {
+        self.db = Some(kv::open(our.package_id(), "candles")?);
+        Ok(())
+    }
+
+    fn db(&self) -> anyhow::Result<&Kv> {
+        self.db
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("candle store isn't open"))
+    }
+
+    /// adds a live trade to every interval's current candle.
+    pub fn record(&mut self, chain_id: u64, pair: EthAddress, trade: &Trade) -> anyhow::Result<()> {
+        for interval in Interval::ALL {
+            let key = (chain_id, pair, interval);
+            let open_time = interval.open_time(trade.at);
+            match self.building.get_mut(&key) {
+                Some(candle) if candle.open_time >= open_time => candle.add(trade),
+                _ => {
+                    // after a restart or a backfill it may be stored already, carry on from that.
+                    let candle = match self.stored(key, open_time)? {
+                        Some(mut candle) => {
+                            candle.add(trade);
+                            candle
+                        }
+                        None => Candle::new(open_time, trade),
+                    };
+                    // the previous candle is done, store it before starting the next.
+                    if let Some(done) = self.building.insert(key, candle) {
+                        self.write(key, &[done])?;
+                    }
+                }
+            }
+            self.dirty.insert(key);
+        }
+        Ok(())
+    }
+
+    /// writes the candles being built that changed since the last flush.
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        for key in std::mem::take(&mut self.dirty) {
+            if let Some(candle) = self.building.get(&key).cloned() {
+                self.write(key, &[candle])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// candles of pair opened between from and to (unix seconds), oldest first.
+    /// errors if from is after to or the range spans more than MAX_CANDLES candles.
+    pub fn candles(
+        &self,
+        chain_id: u64,
+        pair: EthAddress,
+        interval: Interval,
+        from: u64,
+        to: u64,
+    ) -> anyhow::Result<Vec<Candle>> {
+        if from > to {
+            return Err(anyhow::anyhow!("from {} is after to {}", from, to));
+        }
+        if (to - from) / interval.secs() > MAX_CANDLES {
+            return Err(anyhow::anyhow!(
+                "{} to {} is more than {} {} candles, narrow it or use a bigger interval",
+                from,
+                to,
+                MAX_CANDLES,
+                interval.name()
+            ));
+        }
+        let key = (chain_id, pair, interval);
+        let mut candles = BTreeMap::new();
+        for page in page(interval, from)..=page(interval, to) {
+            for candle in self.read(key, page)? {
+                candles.insert(candle.open_time, candle);
+            }
+        }
+        // newer than what was last flushed.
+        if let Some(candle) = self.building.get(&key) {
+            candles.insert(candle.open_time, candle.clone());
+        }
+        Ok(candles
+            .into_values()
+            .filter(|c| c.open_time >= from && c.open_time <= to)
+            .collect())
+    }
+
+    /// builds candles for entry's pair from its Swap logs over the last blocks blocks.
+    /// the logs carry no timestamps, so they're spread evenly between the first and last block's.
+    /// returns how many trades were found.
+    pub fn backfill(
+        &self,
+        chain: &ChainConfig,
+        entry: &WatchEntry,
+        blocks: u64,
+    ) -> anyhow::Result<usize> {
+        let provider = chain.provider();
+        let to_block = provider.get_block_number()?;
+        let from_block = to_block.saturating_sub(blocks);
+        let from_time = get_block_time(chain.chain_id, from_block)?;
+        let to_time = get_block_time(chain.chain_id, to_block)?;
+        let at = |block: u64| {
+            if to_block == from_block {
+                return to_time;
+            }
+            from_time + (to_time - from_time) * (block - from_block) / (to_block - from_block)
+        };
+
+        let mut trades = vec![];
+        let mut start = from_block;
+        while start <= to_block {
+            let end = (start + LOG_RANGE - 1).min(to_block);
+            let filter = Filter::new()
+                .address(entry.pair)
+                .event_signature(IUniswapV2Pair::Swap::SIGNATURE_HASH)
+                .from_block(start)
+                .to_block(end);
+            for log in provider.get_logs(&filter)? {
+                let Some(block) = log.block_number else {
+                    continue;
+                };
+                if let Some(trade) = Trade::from_swap(&log, entry, chain.weth, at(block)) {
+                    trades.push(trade);
+                }
+            }
+            start = end + 1;
+        }
+
+        for interval in Interval::ALL {
+            let mut candles: BTreeMap<u64, Candle> = BTreeMap::new();
+            for trade in &trades {
+                let open_time = interval.open_time(trade.at);
+                match candles.get_mut(&open_time) {
+                    Some(candle) => candle.add(trade),
+                    None => {
+                        candles.insert(open_time, Candle::new(open_time, trade));
+                    }
+                }
+            }
+            let candles: Vec<Candle> = candles.into_values().collect();
+            self.write((chain.chain_id, entry.pair, interval), &candles)?;
+        }
+        Ok(trades.len())
+    }
+
+    fn read(&self, key: SeriesKey, page: u64) -> anyhow::Result<Vec<Candle>> {
+        // a page nobody wrote to yet doesn't exist.
+        match self.db()?.get(page_key(key, page)) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(_) => Ok(vec![]),
+        }
+    }
+
+    /// the stored candle of key opened at open_time.
+    fn stored(&self, key: SeriesKey, open_time: u64) -> anyhow::Result<Option<Candle>> {
+        Ok(self
+            .read(key, page(key.2, open_time))?
+            .into_iter()
+            .find(|c| c.open_time == open_time))
+    }
+
+    /// stores candles, replacing any stored with the same open time.
+    fn write(&self, key: SeriesKey, candles: &[Candle]) -> anyhow::Result<()> {
+        let mut pages: BTreeMap<u64, Vec<&Candle>> = BTreeMap::new();
+        for candle in candles {
+            pages
+                .entry(page(key.2, candle.open_time))
+                .or_default()
+                .push(candle);
+        }
+        for (page, updates) in pages {
+            let mut stored: BTreeMap<u64, Candle> = self
+                .read(key, page)?
+                .into_iter()
+                .map(|c| (c.open_time, c))
+                .collect();
+            for candle in updates {
+                stored.insert(candle.open_time, candle.clone());
+            }
+            let stored: Vec<&Candle> = stored.values().collect();
+            self.db()?
+                .set(page_key(key, page), serde_json::to_vec(&stored)?, None)?;
+        }
+        Ok(())
+    }
+}
+
+fn page(interval: Interval, at: u64) -> u64 {
+    at / (interval.secs() * PAGE_CANDLES)
+}
+
+fn page_key((chain_id, pair, interval): SeriesKey, page: u64) -> Vec<u8> {
+    format!("{}:{:?}:{}:{}", chain_id, pair, interval.name(), page).into_bytes()
+}
@@ -0,0 +1,284 @@
+use kinode_process_lib::{
+    eth::{Address as EthAddress, U256},
+    println,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::candles::{Candle, Interval};
+use crate::helpers::{
+    calls::{get_block_time, get_reserves, to_units},
+    chains::ChainConfig,
+    dex::Dex,
+};
+
+// columns a chart may take, one per candle.
+pub const MAX_COLUMNS: u64 = 120;
+const HEIGHT: usize = 16;
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+// blocks we look back to measure the chain's block time.
+const BLOCK_TIME_WINDOW: u64 = 1_000;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum ChartStyle {
+    #[default]
+    Sparkline,
+    Candles,
+}
+
+/// a duration like `90m`, `24h` or `7d`, in seconds.
+pub fn parse_range(range: &str) -> anyhow::Result<u64> {
+    let range = range.trim();
+    let (count, unit) = if let Some(count) = range.strip_suffix('m') {
+        (count, 60)
+    } else if let Some(count) = range.strip_suffix('h') {
+        (count, 60 * 60)
+    } else if let Some(count) = range.strip_suffix('d') {
+        (count, 24 * 60 * 60)
+    } else {
+        return Err(anyhow::anyhow!(
+            "range should end in m, h or d, got {:?}",
+            range
+        ));
+    };
+    let expected = || anyhow::anyhow!("expected a range like 90m, 24h or 7d, got {:?}", range);
+    if count.is_empty() {
+        return Err(expected());
+    }
+    let count: u64 = count.parse().map_err(|_| expected())?;
+    count
+        .checked_mul(unit)
+        .ok_or_else(|| anyhow::anyhow!("range {:?} is too long", range))
+}
+
+/// candles opened between from and now, one per interval, with the ones nothing traded in
+/// filled flat at the previous close. slots before the first candle are left out.
+pub fn fill_gaps(candles: &[Candle], interval: Interval, from: u64, now: u64) -> Vec<Candle> {
+    let mut filled: Vec<Candle> = vec![];
+    let mut stored = candles.iter().peekable();
+    let mut open_time = interval.open_time(from);
+    while open_time <= now {
+        while stored.peek().is_some_and(|c| c.open_time < open_time) {
+            stored.next();
+        }
+        match stored.peek() {
+            Some(candle) if candle.open_time == open_time => filled.push((*candle).clone()),
+            _ => {
+                if let Some(close) = filled.last().map(|c| c.close) {
+                    filled.push(Candle {
+                        open_time,
+                        open: close,
+                        high: close,
+                        low: close,
+                        close,
+                        volume: 0.0,
+                        volume_eth: 0.0,
+                        trades: 0,
+                    });
+                }
+            }
+        }
+        open_time += interval.secs();
+    }
+    filled
+}
+
+/// price history of token from its WETH pair's reserves, read at the end of every interval
+/// between from and now. each candle runs from the previous reading to its own, there's no volume.
+/// blocks are found from the chain's recent block time, so they're approximate.
+pub fn sample_candles(
+    chain: &ChainConfig,
+    dexes: &[Box<dyn Dex>],
+    token: EthAddress,
+    decimals: u8,
+    interval: Interval,
+    from: u64,
+) -> anyhow::Result<Vec<Candle>> {
+    // only pools whose reserves price the token, see Dex::fee_bps.
+    let pair = dexes
+        .iter()
+        .filter(|dex| dex.fee_bps().is_some())
+        .find_map(|dex| dex.get_pair(chain.weth, token).ok().flatten())
+        .ok_or_else(|| anyhow::anyhow!("no constant product WETH pair for {:?}", token))?;
+
+    let block = chain.provider().get_block_number()?;
+    let now = get_block_time(chain.chain_id, block)?;
+    let earlier = block.saturating_sub(BLOCK_TIME_WINDOW);
+    let block_secs =
+        (now - get_block_time(chain.chain_id, earlier)?) as f64 / (block - earlier).max(1) as f64;
+    if block_secs <= 0.0 {
+        return Err(anyhow::anyhow!("can't tell the block time"));
+    }
+
+    let mut candles: Vec<Candle> = vec![];
+    let mut open_time = interval.open_time(from);
+    while open_time <= now {
+        let close_time = (open_time + interval.secs()).min(now);
+        let back = ((now - close_time) as f64 / block_secs) as u64;
+        // the pair may not exist yet that far back.
+        let price = match get_reserves(
+            chain.chain_id,
+            pair,
+            token,
+            Some(block.saturating_sub(back)),
+        ) {
+            Ok((reserve_token, reserve_weth)) if reserve_token > U256::ZERO => {
+                Some(to_units(reserve_weth, 18) / to_units(reserve_token, decimals))
+            }
+            _ => None,
+        };
+        if let Some(price) = price {
+            let open = candles.last().map(|c| c.close).unwrap_or(price);
+            candles.push(Candle {
+                open_time,
+                open,
+                high: open.max(price),
+                low: open.min(price),
+                close: price,
+                volume: 0.0,
+                volume_eth: 0.0,
+                trades: 0,
+            });
+        }
+        open_time += interval.secs();
+    }
+    Ok(candles)
+}
+
+pub fn print_chart(title: &str, candles: &[Candle], style: ChartStyle) {
+    let (Some(first), Some(last)) = (candles.first(), candles.last()) else {
+        println!("{}: no price history", title);
+        return;
+    };
+    let high = candles.iter().map(|c| c.high).fold(f64::MIN, f64::max);
+    let low = candles.iter().map(|c| c.low).fold(f64::MAX, f64::min);
+    let change = if first.open > 0.0 {
+        (last.close - first.open) / first.open * 100.0
+    } else {
+        0.0
+    };
+
+    println!("{}", title);
+    match style {
+        ChartStyle::Sparkline => {
+            let line: String = candles
+                .iter()
+                .map(|c| SPARKS[level(c.close, low, high, SPARKS.len())])
+                .collect();
+            println!("{}", line);
+        }
+        ChartStyle::Candles => {
+            for row in (0..HEIGHT).rev() {
+                let axis = if row == HEIGHT - 1 {
+                    format!("{:>12.4e}", high)
+                } else if row == 0 {
+                    format!("{:>12.4e}", low)
+                } else {
+                    " ".repeat(12)
+                };
+                let line: String = candles
+                    .iter()
+                    .map(|c| candle_char(c, row, low, high))
+                    .collect();
+                println!("{} |{}", axis, line);
+            }
+        }
+    }
+    println!(
+        "high {:.4e} | low {:.4e} | last {:.4e} | change {:+.2}%",
+        high, low, last.close, change
+    );
+}
+
+/// the row of levels price falls on, 0 at low.
+fn level(price: f64, low: f64, high: f64, levels: usize) -> usize {
+    if high <= low {
+        return levels / 2;
+    }
+    let level = ((price - low) / (high - low) * (levels - 1) as f64).round();
+    (level.max(0.0) as usize).min(levels - 1)
+}
+
+/// `#` for the body of a candle that closed up, `=` for one that closed down, `|` for wicks.
+fn candle_char(candle: &Candle, row: usize, low: f64, high: f64) -> char {
+    let body_top = level(candle.open.max(candle.close), low, high, HEIGHT);
+    let body_bottom = level(candle.open.min(candle.close), low, high, HEIGHT);
+    if row >= body_bottom && row <= body_top {
+        if candle.close >= candle.open {
+            '#'
+        } else {
+            '='
+        }
+    } else if row >= level(candle.low, low, high, HEIGHT)
+        && row <= level(candle.high, low, high, HEIGHT)
+    {
+        '|'
+    } else {
+        ' '
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candle(open_time: u64, close: f64) -> Candle {
+        Candle {
+            open_time,
+            open: close,
+            high: close,
+            low: close,
+            close,
+            volume: 1.0,
+            volume_eth: close,
+            trades: 1,
+        }
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(parse_range("90m").unwrap(), 90 * 60);
+        assert_eq!(parse_range(" 24h ").unwrap(), 24 * 60 * 60);
+        assert_eq!(parse_range("7d").unwrap(), 7 * 24 * 60 * 60);
+    }
+
+    #[test]
+    fn rejects_malformed_ranges() {
+        for range in ["", "m", "24", "24s", "xh", "-5m", "1.5h", "é"] {
+            assert!(parse_range(range).is_err(), "{:?} parsed", range);
+        }
+    }
+
+    #[test]
+    fn rejects_ranges_that_overflow() {
+        assert!(parse_range(&format!("{}d", u64::MAX / 60)).is_err());
+    }
+
+    #[test]
+    fn fills_gaps_at_the_previous_close() {
+        let candles = [candle(60, 1.0), candle(240, 2.0)];
+        let filled = fill_gaps(&candles, Interval::M1, 60, 300);
+        let closes: Vec<(u64, f64, u64)> = filled
+            .iter()
+            .map(|c| (c.open_time, c.close, c.trades))
+            .collect();
+        assert_eq!(
+            closes,
+            vec![
+                (60, 1.0, 1),
+                (120, 1.0, 0),
+                (180, 1.0, 0),
+                (240, 2.0, 1),
+                (300, 2.0, 0)
+            ]
+        );
+        assert_eq!(filled[1].volume, 0.0);
+    }
+
+    #[test]
+    fn leaves_out_slots_before_the_first_candle() {
+        let candles = [candle(0, 3.0), candle(180, 1.0)];
+        let filled = fill_gaps(&candles, Interval::M1, 90, 200);
+        let open_times: Vec<u64> = filled.iter().map(|c| c.open_time).collect();
+        assert_eq!(open_times, vec![180]);
+    }
+}
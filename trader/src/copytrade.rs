@@ -0,0 +1,378 @@
+use alloy_signer::{k256::ecdsa::SigningKey, Signer, Wallet};
+use alloy_sol_types::{SolCall, SolEvent};
+use kinode_process_lib::{
+    eth::{Address as EthAddress, Filter, Log, TxHash, U256},
+    println,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+use crate::helpers::{
+    calls::get_erc20_balance,
+    chains::ChainConfig,
+    contracts::{IUniswapV2Pair, IUniswapV2Router01, IUniswapV2Router02, IERC20},
+    dex::{dex_by_name, Dex, DexKind, DexRegistry},
+    tax::check_round_trip,
+};
+use crate::orders::{now_secs, Side};
+use crate::risk::sell_value;
+use crate::sniper::DEFAULT_MAX_TAX_BPS;
+use crate::state::State;
+use crate::subscriptions::{Feed, Subscriptions};
+use crate::trade::{execute_buy, execute_sell, Account, Fill};
+
+// scale is applied in millionths.
+const SCALE_UNIT: u64 = 1_000_000;
+// a swap shows up in more than one of our logs, these are the txs we already looked at.
+const RECENT_TXS: usize = 32;
+
+/// copies the V2 router swaps address makes against WETH: buys scaled by scale, sells as the
+/// same share of what we hold as they sold of theirs. neither side goes past max_per_trade wei.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Follow {
+    pub id: u64,
+    pub chain_id: u64,
+    pub address: EthAddress,
+    pub scale: f64,
+    pub max_per_trade: U256,
+    /// tokens we copy trades in, any token if empty.
+    pub tokens_allowlist: Vec<EthAddress>,
+    /// trades against the paper book instead of the wallet.
+    pub paper: bool,
+    pub copied: u64,
+    pub skipped: u64,
+    #[serde(default)]
+    pub recent_txs: VecDeque<TxHash>,
+}
+
+impl Follow {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !self.scale.is_finite() || self.scale <= 0.0 {
+            return Err(anyhow::anyhow!("scale should be above 0"));
+        }
+        if self.max_per_trade == U256::ZERO {
+            return Err(anyhow::anyhow!("max_per_trade should be above 0"));
+        }
+        Ok(())
+    }
+
+    fn allows(&self, token: EthAddress) -> bool {
+        self.tokens_allowlist.is_empty() || self.tokens_allowlist.contains(&token)
+    }
+
+    /// their amount_in scaled to what we spend, before max_per_trade.
+    fn scaled(&self, amount: U256) -> U256 {
+        amount * U256::from((self.scale * SCALE_UNIT as f64) as u64) / U256::from(SCALE_UNIT)
+    }
+
+    fn has_seen(&self, tx_hash: TxHash) -> bool {
+        self.recent_txs.contains(&tx_hash)
+    }
+
+    /// once a tx is copied or skipped its other logs are ignored. failed lookups don't count,
+    /// the next log of the same tx tries again.
+    fn mark_seen(&mut self, tx_hash: TxHash) {
+        if self.recent_txs.len() >= RECENT_TXS {
+            self.recent_txs.pop_front();
+        }
+        self.recent_txs.push_back(tx_hash);
+    }
+}
+
+/// a swap the followed wallet sent to a V2 router, with the WETH side at one end of path.
+struct RouterSwap {
+    side: Side,
+    token: EthAddress,
+    /// the path they swapped along, WETH first for buys.
+    path: Vec<EthAddress>,
+    /// ETH sent for buys, tokens sold for sells. exact output swaps give their upper bound.
+    amount_in: U256,
+}
+
+/// logs that tell us address traded: Swaps paying it, which are its buys, and Transfers out of
+/// it, which catch its sells since those pay the router the ETH to unwrap.
+pub fn subscribe_follow(
+    subs: &mut Subscriptions,
+    chain_id: u64,
+    address: EthAddress,
+) -> anyhow::Result<()> {
+    let swaps = Feed::Follow {
+        chain_id,
+        address,
+        sells: false,
+    };
+    if !subs.is_subscribed(&swaps) {
+        let filter = Filter::new()
+            .event_signature(IUniswapV2Pair::Swap::SIGNATURE_HASH)
+            .topic2(address.into_word());
+        subs.subscribe(swaps, chain_id, filter)?;
+    }
+    let transfers = Feed::Follow {
+        chain_id,
+        address,
+        sells: true,
+    };
+    if !subs.is_subscribed(&transfers) {
+        let filter = Filter::new()
+            .event_signature(IERC20::Transfer::SIGNATURE_HASH)
+            .topic1(address.into_word());
+        subs.subscribe(transfers, chain_id, filter)?;
+    }
+    Ok(())
+}
+
+pub fn unsubscribe_follow(subs: &mut Subscriptions, chain_id: u64, address: EthAddress) {
+    subs.unsubscribe(|feed| {
+        matches!(feed, Feed::Follow { chain_id: c, address: a, .. }
+            if *c == chain_id && *a == address)
+    });
+}
+
+/// subscribes every follow that isn't yet, e.g. after a restart or a dropped subscription.
+pub fn subscribe_follows(subs: &mut Subscriptions, state: &State) {
+    for follow in state.follows.values() {
+        if let Err(e) = subscribe_follow(subs, follow.chain_id, follow.address) {
+            println!(
+                "trader: couldn't follow {:?}, its trades are missed: {:?}",
+                follow.address, e
+            );
+        }
+    }
+}
+
+/// looks up the tx behind a log about a followed wallet and copies it if it's a swap we copy.
+pub fn on_follow_log(
+    wallet: &Wallet<SigningKey>,
+    state: &mut State,
+    dexes: &mut DexRegistry,
+    chain_id: u64,
+    address: EthAddress,
+    log: &Log,
+) -> anyhow::Result<()> {
+    let Some(tx_hash) = log.transaction_hash else {
+        return Ok(());
+    };
+    let Some(id) = state
+        .follows
+        .values()
+        .find(|f| f.chain_id == chain_id && f.address == address)
+        .map(|f| f.id)
+    else {
+        return Ok(());
+    };
+    if state.follows[&id].has_seen(tx_hash) {
+        return Ok(());
+    }
+
+    let chain = state.chain(chain_id)?.clone();
+    let tx = chain
+        .provider()
+        .get_transaction_by_hash(tx_hash)?
+        .ok_or_else(|| anyhow::anyhow!("no tx {:?}", tx_hash))?;
+    // tokens sent to them, or swaps someone else made for them, aren't their trades.
+    let router = tx.to.filter(|_| tx.from == address).and_then(|to| {
+        chain
+            .dexes
+            .iter()
+            .find(|dex| matches!(dex.kind, DexKind::UniswapV2) && dex.router == to)
+    });
+    let Some(router) = router else {
+        if let Some(follow) = state.follows.get_mut(&id) {
+            follow.mark_seen(tx_hash);
+        }
+        return state.save();
+    };
+    let Some(swap) = decode_swap(&tx.input, tx.value, chain.weth) else {
+        println!(
+            "trader: follow {} skipped {:?}, not a WETH swap we copy",
+            id, tx_hash
+        );
+        if let Some(follow) = state.follows.get_mut(&id) {
+            follow.mark_seen(tx_hash);
+            follow.skipped += 1;
+        }
+        return state.save();
+    };
+
+    let dexes = dexes.get(&chain)?;
+    let follow = state.follows[&id].clone();
+    let copied = if !follow.allows(swap.token) {
+        println!(
+            "trader: follow {} skipped {:?}, {:?} isn't allowed",
+            id, tx_hash, swap.token
+        );
+        None
+    } else {
+        match copy_swap(wallet, state, &chain, dexes, &router.name, &follow, &swap) {
+            Ok(fill) => fill,
+            // left unseen, so it's tried again if the tx has more logs for us.
+            Err(e) => {
+                println!("trader: follow {} couldn't copy {:?}: {:?}", id, tx_hash, e);
+                return Ok(());
+            }
+        }
+    };
+
+    if let Some(follow) = state.follows.get_mut(&id) {
+        follow.mark_seen(tx_hash);
+        match copied {
+            Some(fill) => {
+                follow.copied += 1;
+                println!(
+                    "trader: follow {} copied {:?} {:?} on {}, sent! {}",
+                    id,
+                    swap.side,
+                    swap.token,
+                    fill.venue,
+                    chain.tx_url(&fill.tx_hash)
+                );
+            }
+            None => follow.skipped += 1,
+        }
+    }
+    state.save()
+}
+
+/// their swap resized for us, checked and sent. None if there's nothing to copy or it's refused,
+/// errors are lookups that failed and may work on a retry.
+fn copy_swap(
+    wallet: &Wallet<SigningKey>,
+    state: &mut State,
+    chain: &ChainConfig,
+    dexes: &[Box<dyn Dex>],
+    venue: &str,
+    follow: &Follow,
+    swap: &RouterSwap,
+) -> anyhow::Result<Option<Fill>> {
+    let now = now_secs()?;
+    let gas_price = chain.provider().get_gas_price()?;
+
+    match swap.side {
+        Side::Buy => {
+            let amount = follow.scaled(swap.amount_in).min(follow.max_per_trade);
+            if amount == U256::ZERO {
+                println!(
+                    "trader: follow {} skipped a buy too small to copy",
+                    follow.id
+                );
+                return Ok(None);
+            }
+            // simulated along their route, the one we know went through.
+            let dex = dex_by_name(dexes, venue)?;
+            let quoted_out = *dex
+                .get_amounts_out(amount, &swap.path)?
+                .last()
+                .ok_or_else(|| anyhow::anyhow!("empty quote from {}", dex.name()))?;
+            if let Some(reason) = check_round_trip(
+                wallet.address(),
+                dex,
+                amount,
+                swap.path.clone(),
+                quoted_out,
+                DEFAULT_MAX_TAX_BPS,
+            )? {
+                println!(
+                    "trader: follow {} skipped {:?}, {}",
+                    follow.id, swap.token, reason
+                );
+                return Ok(None);
+            }
+
+            if let Err(e) = state
+                .risk
+                .check(&state.risk_ledger, now, gas_price, amount, amount)
+            {
+                println!(
+                    "trader: follow {} skipped {:?}, {}",
+                    follow.id, swap.token, e
+                );
+                return Ok(None);
+            }
+            let mut account = Account::new(wallet, &mut state.paper, follow.paper);
+            let fill = execute_buy(&mut account, chain, dexes, swap.token, amount, U256::ZERO)?;
+            state.risk_ledger.record_trade(now, &account, amount);
+            Ok(Some(fill))
+        }
+        Side::Sell => {
+            // mined by now, so what they hold is what's left after the sell.
+            let theirs = get_erc20_balance(chain.chain_id, swap.token, follow.address)?;
+            let mut account = Account::new(wallet, &mut state.paper, follow.paper);
+            let ours = account.token_balance(chain.chain_id, swap.token)?;
+            let mut amount = ours * swap.amount_in / (theirs + swap.amount_in);
+            if amount == U256::ZERO {
+                println!(
+                    "trader: follow {} skipped a sell of {:?}, we hold none",
+                    follow.id, swap.token
+                );
+                return Ok(None);
+            }
+
+            let mut value = sell_value(chain, dexes, swap.token, amount, gas_price)?;
+            if value > follow.max_per_trade {
+                amount = amount * follow.max_per_trade / value;
+                value = follow.max_per_trade;
+            }
+            if let Err(e) = state
+                .risk
+                .check(&state.risk_ledger, now, gas_price, value, U256::ZERO)
+            {
+                println!(
+                    "trader: follow {} skipped {:?}, {}",
+                    follow.id, swap.token, e
+                );
+                return Ok(None);
+            }
+            let fill = execute_sell(&mut account, chain, dexes, swap.token, amount, U256::ZERO)?;
+            state.risk_ledger.record_trade(now, &account, U256::ZERO);
+            Ok(Some(fill))
+        }
+    }
+}
+
+/// the path and amount in of a V2 router swap, if it's between WETH and one token.
+/// swaps between two tokens aren't copied, there's no ETH amount to scale.
+fn decode_swap(input: &[u8], value: U256, weth: EthAddress) -> Option<RouterSwap> {
+    let (path, amount_in) = if let Ok(call) =
+        IUniswapV2Router01::swapExactETHForTokensCall::abi_decode(input, true)
+    {
+        (call.path, value)
+    } else if let Ok(call) = IUniswapV2Router01::swapETHForExactTokensCall::abi_decode(input, true)
+    {
+        (call.path, value)
+    } else if let Ok(call) =
+        IUniswapV2Router02::swapExactETHForTokensSupportingFeeOnTransferTokensCall::abi_decode(
+            input, true,
+        )
+    {
+        (call.path, value)
+    } else if let Ok(call) = IUniswapV2Router01::swapExactTokensForETHCall::abi_decode(input, true)
+    {
+        (call.path, call.amountIn)
+    } else if let Ok(call) = IUniswapV2Router01::swapTokensForExactETHCall::abi_decode(input, true)
+    {
+        (call.path, call.amountInMax)
+    } else if let Ok(call) =
+        IUniswapV2Router02::swapExactTokensForETHSupportingFeeOnTransferTokensCall::abi_decode(
+            input, true,
+        )
+    {
+        (call.path, call.amountIn)
+    } else {
+        return None;
+    };
+
+    let (first, last) = (*path.first()?, *path.last()?);
+    let (side, token) = if first == weth && last != weth {
+        (Side::Buy, last)
+    } else if last == weth && first != weth {
+        (Side::Sell, first)
+    } else {
+        return None;
+    };
+    Some(RouterSwap {
+        side,
+        token,
+        path,
+        amount_in,
+    })
+}
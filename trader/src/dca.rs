@@ -0,0 +1,208 @@
+use alloy_signer::{k256::ecdsa::SigningKey, Signer, Wallet};
+use kinode_process_lib::{
+    eth::{Address as EthAddress, U256},
+    println,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::feed::PriceFeed;
+use crate::helpers::{
+    calls::to_units,
+    chains::ChainConfig,
+    dex::{Dex, DexRegistry},
+};
+use crate::orders::{limit_min_out, now_secs, Side};
+use crate::state::State;
+use crate::timers::{schedule, TimerContext};
+use crate::trade::{execute_buy, settle_buys, Account, Fill, PendingBuy};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DcaStatus {
+    Active,
+    Paused,
+    /// the budget is spent.
+    Done,
+    Cancelled,
+}
+
+/// buys amount_per_buy wei worth of token every interval seconds until total_budget is spent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DcaSchedule {
+    pub id: u64,
+    pub chain_id: u64,
+    /// trades against the paper book instead of the wallet.
+    #[serde(default)]
+    pub paper: bool,
+    pub token: EthAddress,
+    pub symbol: String,
+    pub decimals: u8,
+    pub amount_per_buy: U256,
+    pub interval: u64,
+    pub total_budget: U256,
+    /// ETH per whole token, ticks above it are skipped.
+    pub max_price: Option<f64>,
+    /// wei, ticks with a higher gas price are skipped.
+    pub max_gas_price: Option<U256>,
+    pub status: DcaStatus,
+    /// unix seconds of the next buy.
+    pub next_run: u64,
+    /// wei sent, including buys that aren't mined yet.
+    pub spent: U256,
+    /// what the mined buys delivered.
+    pub bought: U256,
+    pub buys: u64,
+    pub skipped: u64,
+    #[serde(default)]
+    pub pending: Vec<PendingBuy>,
+}
+
+impl DcaSchedule {
+    /// sets the timer for the next buy, next_run has to be set already.
+    pub fn arm(&self) -> anyhow::Result<()> {
+        let delay = self.next_run.saturating_sub(now_secs()?);
+        schedule(&TimerContext::Dca(self.id), delay * 1000)
+    }
+
+    /// average ETH paid per whole token over the mined buys.
+    pub fn average_price(&self) -> Option<f64> {
+        let pending = self
+            .pending
+            .iter()
+            .fold(U256::ZERO, |sum, buy| sum + buy.amount_in);
+        let tokens = to_units(self.bought, self.decimals);
+        (tokens > 0.0).then(|| to_units(self.spent - pending, 18) / tokens)
+    }
+}
+
+/// runs one tick of a schedule: books the buys that got mined, buys again if there's budget left.
+/// the next tick is armed first, so a tick that fails doesn't end the schedule.
+pub fn run_dca(
+    wallet: &Wallet<SigningKey>,
+    state: &mut State,
+    dexes: &mut DexRegistry,
+    prices: &mut PriceFeed,
+    id: u64,
+) -> anyhow::Result<()> {
+    let Some(dca) = state.dcas.get_mut(&id) else {
+        return Ok(());
+    };
+    let now = now_secs()?;
+    // paused, cancelled, or a timer left over from before a resume.
+    if dca.status != DcaStatus::Active || now + 1 < dca.next_run {
+        return Ok(());
+    }
+    dca.next_run = now + dca.interval;
+    dca.arm()?;
+    let chain_id = dca.chain_id;
+
+    let chain = state.chain(chain_id)?.clone();
+    let dexes = dexes.get(&chain)?;
+    let Some(dca) = state.dcas.get_mut(&id) else {
+        return Ok(());
+    };
+
+    for (buy, received) in settle_buys(
+        chain.chain_id,
+        &mut dca.pending,
+        dca.token,
+        wallet.address(),
+    ) {
+        match received {
+            Some(received) => {
+                dca.bought += received;
+                dca.buys += 1;
+                println!("trader: dca {} got {} {}", id, received, dca.symbol);
+            }
+            None => {
+                // nothing was bought, the budget is still there.
+                dca.spent -= buy.amount_in;
+                dca.skipped += 1;
+                println!("trader: dca {} buy {:?} didn't go through", id, buy.tx_hash);
+            }
+        }
+    }
+
+    if dca.spent < dca.total_budget {
+        let amount = dca.amount_per_buy.min(dca.total_budget - dca.spent);
+        let mut account = Account::new(wallet, &mut state.paper, dca.paper);
+        match buy_tick(&mut account, &chain, dexes, prices, dca, amount) {
+            // paper fills are exact and final.
+            Ok(Some(fill)) if matches!(account, Account::Paper(_)) => {
+                dca.spent += amount;
+                dca.bought += fill.quoted_out;
+                dca.buys += 1;
+                println!(
+                    "trader: dca {} bought {} {} on paper",
+                    id, fill.quoted_out, dca.symbol
+                );
+            }
+            Ok(Some(fill)) => {
+                dca.spent += amount;
+                dca.pending.push(PendingBuy {
+                    tx_hash: fill.tx_hash,
+                    amount_in: amount,
+                });
+                println!(
+                    "trader: dca {} buying {} with {} wei, sent! {}",
+                    id,
+                    dca.symbol,
+                    amount,
+                    chain.tx_url(fill.tx_hash)
+                );
+            }
+            Ok(None) => dca.skipped += 1,
+            Err(e) => {
+                dca.skipped += 1;
+                println!("trader: dca {} buy failed: {:?}", id, e);
+            }
+        }
+    }
+
+    // the armed timer finds it done and does nothing.
+    if dca.spent >= dca.total_budget && dca.pending.is_empty() {
+        dca.status = DcaStatus::Done;
+        println!("trader: dca {} spent its budget", id);
+    }
+    state.save()
+}
+
+/// Ok(None) when the tick is skipped over price or gas.
+fn buy_tick(
+    account: &mut Account,
+    chain: &ChainConfig,
+    dexes: &[Box<dyn Dex>],
+    prices: &mut PriceFeed,
+    dca: &DcaSchedule,
+    amount: U256,
+) -> anyhow::Result<Option<Fill>> {
+    if let Some(max_gas_price) = dca.max_gas_price {
+        let gas_price = chain.provider().get_gas_price()?;
+        if gas_price > max_gas_price {
+            println!(
+                "trader: dca {} skipped, gas {} is above {}",
+                dca.id, gas_price, max_gas_price
+            );
+            return Ok(None);
+        }
+    }
+
+    let min_out = match dca.max_price {
+        Some(max_price) => {
+            let price = prices.weth_price(chain, dexes, dca.token, dca.decimals)?;
+            if price > max_price {
+                println!(
+                    "trader: dca {} skipped, {} at {} ETH is above {}",
+                    dca.id, dca.symbol, price, max_price
+                );
+                return Ok(None);
+            }
+            // and don't let slippage take the fill past it either.
+            limit_min_out(Side::Buy, amount, max_price, dca.decimals)
+        }
+        None => U256::ZERO,
+    };
+
+    Ok(Some(execute_buy(
+        account, chain, dexes, dca.token, amount, min_out,
+    )?))
+}
@@ -0,0 +1,190 @@
+use alloy_sol_types::SolEvent;
+use kinode_process_lib::{
+    eth::{Address as EthAddress, Filter, Log, U256},
+    println,
+};
+use std::collections::{HashMap, HashSet};
+
+use crate::helpers::{
+    calls::{get_pair_reserves, get_weth_price, to_units},
+    chains::{eth_provider, ChainConfig},
+    contracts::IUniswapV2Pair,
+    dex::Dex,
+};
+use crate::subscriptions::{Feed, Subscriptions};
+
+/// a V2 pair's reserves as of block.
+#[derive(Debug, Clone)]
+pub struct Reserves {
+    pub token0: EthAddress,
+    pub reserve0: U256,
+    pub reserve1: U256,
+    pub block: u64,
+}
+
+impl Reserves {
+    /// (reserve of token_in, reserve of the other token).
+    pub fn of(&self, token_in: EthAddress) -> (U256, U256) {
+        if self.token0 == token_in {
+            (self.reserve0, self.reserve1)
+        } else {
+            (self.reserve1, self.reserve0)
+        }
+    }
+}
+
+/// reserves of watched pairs, kept current from their Sync logs so a price costs no RPC.
+/// a pair whose subscription is down is polled on every read until it's back. runtime only.
+#[derive(Debug, Default)]
+pub struct PriceFeed {
+    pairs: HashMap<(u64, EthAddress), Reserves>,
+    /// pairs whose subscription is up, so their cached reserves are current.
+    live: HashSet<(u64, EthAddress)>,
+    /// the watched pair that prices each token.
+    tokens: HashMap<(u64, EthAddress), EthAddress>,
+}
+
+impl PriceFeed {
+    /// prices token from pair's logs from now on. if subscribing fails the pair is
+    /// polled until ensure_subscribed gets it going.
+    pub fn watch(
+        &mut self,
+        subs: &mut Subscriptions,
+        chain_id: u64,
+        token: EthAddress,
+        pair: EthAddress,
+    ) -> anyhow::Result<()> {
+        self.tokens.insert((chain_id, token), pair);
+        self.subscribe(subs, chain_id, pair)
+    }
+
+    pub fn unwatch(
+        &mut self,
+        subs: &mut Subscriptions,
+        chain_id: u64,
+        token: EthAddress,
+        pair: EthAddress,
+    ) {
+        self.tokens.remove(&(chain_id, token));
+        subs.unsubscribe(|feed| *feed == Feed::Pair { chain_id, pair });
+        self.pairs.remove(&(chain_id, pair));
+        self.live.remove(&(chain_id, pair));
+    }
+
+    fn subscribe(
+        &mut self,
+        subs: &mut Subscriptions,
+        chain_id: u64,
+        pair: EthAddress,
+    ) -> anyhow::Result<()> {
+        let filter = Filter::new().address(pair).event_signature(vec![
+            IUniswapV2Pair::Sync::SIGNATURE_HASH,
+            IUniswapV2Pair::Swap::SIGNATURE_HASH,
+        ]);
+        subs.subscribe(Feed::Pair { chain_id, pair }, chain_id, filter)?;
+        // subscribed first, so whatever changes after the poll comes in as a log.
+        self.poll(chain_id, pair)?;
+        self.live.insert((chain_id, pair));
+        Ok(())
+    }
+
+    fn poll(&mut self, chain_id: u64, pair: EthAddress) -> anyhow::Result<Reserves> {
+        let block = eth_provider(chain_id).get_block_number()?;
+        let (token0, reserve0, reserve1) = get_pair_reserves(chain_id, pair, Some(block))?;
+        let reserves = Reserves {
+            token0,
+            reserve0,
+            reserve1,
+            block,
+        };
+        self.pairs.insert((chain_id, pair), reserves.clone());
+        Ok(reserves)
+    }
+
+    /// a log from pair's subscription. Sync carries the reserves after every swap, mint and burn.
+    pub fn on_log(&mut self, chain_id: u64, pair: EthAddress, log: &Log) -> anyhow::Result<()> {
+        if log.topics().first() != Some(&IUniswapV2Pair::Sync::SIGNATURE_HASH) {
+            return Ok(());
+        }
+        let sync = IUniswapV2Pair::Sync::decode_raw_log(
+            log.topics().iter().copied(),
+            &log.data().data,
+            true,
+        )?;
+        let Some(reserves) = self.pairs.get_mut(&(chain_id, pair)) else {
+            return Ok(());
+        };
+        let block = log.block_number.unwrap_or(reserves.block);
+        // older than what we polled. logs within a block come in order, so the last Sync wins.
+        if block < reserves.block {
+            return Ok(());
+        }
+        reserves.reserve0 = U256::from(sync.reserve0);
+        reserves.reserve1 = U256::from(sync.reserve1);
+        reserves.block = block;
+        Ok(())
+    }
+
+    /// pair's subscription dropped, we can't trust the cache until it's back.
+    pub fn dropped(&mut self, chain_id: u64, pair: EthAddress) {
+        self.live.remove(&(chain_id, pair));
+    }
+
+    /// brings back pairs whose subscription dropped: opens it again if that didn't
+    /// already happen, and polls once to catch up on what was missed.
+    pub fn ensure_subscribed(&mut self, subs: &mut Subscriptions) {
+        let stale: Vec<(u64, EthAddress)> = self
+            .tokens
+            .iter()
+            .map(|((chain_id, _), pair)| (*chain_id, *pair))
+            .filter(|key| !self.live.contains(key))
+            .collect();
+        for (chain_id, pair) in stale {
+            let result = if subs.is_subscribed(&Feed::Pair { chain_id, pair }) {
+                self.poll(chain_id, pair).map(|_| {
+                    self.live.insert((chain_id, pair));
+                })
+            } else {
+                self.subscribe(subs, chain_id, pair)
+            };
+            match result {
+                Ok(()) => println!("trader: price feed for {:?} is live again", pair),
+                Err(e) => println!("trader: price feed for {:?} still down: {:?}", pair, e),
+            }
+        }
+    }
+
+    pub fn is_live(&self, chain_id: u64, pair: EthAddress) -> bool {
+        self.live.contains(&(chain_id, pair))
+    }
+
+    /// pair's reserves, from the cache while its subscription is up, polled otherwise.
+    pub fn reserves(&mut self, chain_id: u64, pair: EthAddress) -> anyhow::Result<Reserves> {
+        if self.is_live(chain_id, pair) {
+            if let Some(reserves) = self.pairs.get(&(chain_id, pair)) {
+                return Ok(reserves.clone());
+            }
+        }
+        self.poll(chain_id, pair)
+    }
+
+    /// spot price of token in ETH. watched tokens are priced from their pair's reserves,
+    /// the rest the usual way, see get_weth_price.
+    pub fn weth_price(
+        &mut self,
+        chain: &ChainConfig,
+        dexes: &[Box<dyn Dex>],
+        token: EthAddress,
+        decimals: u8,
+    ) -> anyhow::Result<f64> {
+        let Some(pair) = self.tokens.get(&(chain.chain_id, token)).copied() else {
+            return get_weth_price(chain, dexes, token, decimals);
+        };
+        let (weth, tokens) = self.reserves(chain.chain_id, pair)?.of(chain.weth);
+        let tokens = to_units(tokens, decimals);
+        if tokens == 0.0 {
+            return Err(anyhow::anyhow!("pair {:?} holds no {:?}", pair, token));
+        }
+        Ok(to_units(weth, 18) / tokens)
+    }
+}
@@ -0,0 +1,372 @@
+use alloy_signer::{k256::ecdsa::SigningKey, Signer, Wallet};
+use kinode_process_lib::{
+    eth::{Address as EthAddress, TxHash, U256},
+    println,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::feed::PriceFeed;
+use crate::helpers::{
+    calls::{get_tx_status, received_in, to_units, TxStatus},
+    dex::DexRegistry,
+};
+use crate::orders::{limit_min_out, Side};
+use crate::state::State;
+use crate::trade::{execute_buy, execute_sell, Account};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum GridStatus {
+    Running,
+    Stopped,
+}
+
+/// the band between two neighbouring levels. it buys at its lower price
+/// and sells what it bought at its upper price.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GridBand {
+    pub lower: f64,
+    pub upper: f64,
+    /// tokens held from the last buy, zero while waiting to buy.
+    pub held: U256,
+    /// wei paid for held.
+    pub cost: U256,
+    /// the band's live trade, until it's mined.
+    #[serde(default)]
+    pub pending: Option<BandTx>,
+}
+
+/// a band's trade that's sent but not mined yet.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BandTx {
+    pub tx_hash: TxHash,
+    pub side: Side,
+    /// what we were quoted. booked as a sell's proceeds, the ETH it pays out leaves no
+    /// Transfer log to read.
+    pub quoted_out: U256,
+}
+
+/// one buy-then-sell round trip of a band.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GridCycle {
+    pub band: usize,
+    pub cost: U256,
+    pub proceeds: U256,
+    /// ETH, proceeds minus cost, before gas.
+    pub profit: f64,
+}
+
+/// a grid on token/WETH between lower and upper (ETH per whole token).
+/// bands above the price at creation buy right away, so there's something to sell as it rises.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Grid {
+    pub id: u64,
+    pub chain_id: u64,
+    /// trades against the paper book instead of the wallet.
+    #[serde(default)]
+    pub paper: bool,
+    pub token: EthAddress,
+    pub symbol: String,
+    pub decimals: u8,
+    /// wei of ETH each band buys with.
+    pub capital_per_level: U256,
+    pub bands: Vec<GridBand>,
+    pub cycles: Vec<GridCycle>,
+    pub status: GridStatus,
+}
+
+impl Grid {
+    /// evenly spaced levels from lower to upper, levels - 1 bands between them.
+    pub fn bands(lower: f64, upper: f64, levels: u64) -> anyhow::Result<Vec<GridBand>> {
+        if !(lower > 0.0 && upper > lower && upper.is_finite()) {
+            return Err(anyhow::anyhow!("need 0 < lower < upper"));
+        }
+        if levels < 2 {
+            return Err(anyhow::anyhow!("a grid needs at least 2 levels"));
+        }
+        let step = (upper - lower) / (levels - 1) as f64;
+        Ok((0..levels - 1)
+            .map(|i| GridBand {
+                lower: lower + step * i as f64,
+                upper: lower + step * (i + 1) as f64,
+                held: U256::ZERO,
+                cost: U256::ZERO,
+                pending: None,
+            })
+            .collect())
+    }
+
+    pub fn realized_profit(&self) -> f64 {
+        self.cycles.iter().map(|c| c.profit).sum()
+    }
+
+    pub fn has_pending(&self) -> bool {
+        self.bands.iter().any(|b| b.pending.is_some())
+    }
+
+    /// the band that should trade at price, and which way. sells come first,
+    /// and we take the band closest to the price. bands waiting on a trade sit it out.
+    pub fn next_action(&self, price: f64) -> Option<(usize, Side)> {
+        let sell = self
+            .bands
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.pending.is_none() && b.held > U256::ZERO && price >= b.upper)
+            .max_by(|(_, a), (_, b)| a.upper.total_cmp(&b.upper))
+            .map(|(i, _)| (i, Side::Sell));
+        let buy = || {
+            self.bands
+                .iter()
+                .enumerate()
+                .filter(|(_, b)| b.pending.is_none() && b.held == U256::ZERO && price <= b.lower)
+                .min_by(|(_, a), (_, b)| a.lower.total_cmp(&b.lower))
+                .map(|(i, _)| (i, Side::Buy))
+        };
+        sell.or_else(buy)
+    }
+
+    /// amount in and min out for band i trading on side. the min out keeps the fill inside
+    /// the band, never paying more than its buy level or taking less than its sell level.
+    /// sells are capped at balance, in case some of what the band bought was moved out.
+    pub fn order(&self, i: usize, side: Side, balance: U256) -> (U256, U256) {
+        let band = &self.bands[i];
+        let (amount, limit) = match side {
+            Side::Buy => (self.capital_per_level, band.lower),
+            Side::Sell => (balance.min(band.held), band.upper),
+        };
+        (amount, limit_min_out(side, amount, limit, self.decimals))
+    }
+
+    /// books what band i's trade on side got, the finished round trip for a sell.
+    pub fn filled(&mut self, i: usize, side: Side, amount_out: U256) -> Option<GridCycle> {
+        let band = &mut self.bands[i];
+        match side {
+            Side::Buy => {
+                band.held = amount_out;
+                band.cost = self.capital_per_level;
+                None
+            }
+            Side::Sell => {
+                let cycle = GridCycle {
+                    band: i,
+                    cost: band.cost,
+                    proceeds: amount_out,
+                    profit: to_units(amount_out, 18) - to_units(band.cost, 18),
+                };
+                band.held = U256::ZERO;
+                band.cost = U256::ZERO;
+                self.cycles.push(cycle.clone());
+                Some(cycle)
+            }
+        }
+    }
+
+    /// books the band trades whose receipt is in: a buy holds what it delivered to owner,
+    /// a sell finishes its round trip. one that reverted or was dropped leaves its band as it
+    /// was, a failed lookup leaves it pending for the next poll.
+    fn settle(&mut self, owner: EthAddress) {
+        for i in 0..self.bands.len() {
+            let Some(tx) = self.bands[i].pending.clone() else {
+                continue;
+            };
+            let logs = match get_tx_status(self.chain_id, tx.tx_hash) {
+                Ok(TxStatus::Pending) => continue,
+                Ok(TxStatus::Mined {
+                    success: true,
+                    logs,
+                }) => logs,
+                Ok(TxStatus::Mined { success: false, .. }) | Ok(TxStatus::Dropped) => {
+                    println!(
+                        "trader: grid {} band {} {:?} {:?} didn't go through",
+                        self.id, i, tx.side, tx.tx_hash
+                    );
+                    self.bands[i].pending = None;
+                    continue;
+                }
+                Err(e) => {
+                    println!("trader: no receipt for {:?}: {:?}", tx.tx_hash, e);
+                    continue;
+                }
+            };
+            self.bands[i].pending = None;
+            let amount_out = match tx.side {
+                Side::Buy => received_in(&logs, self.token, owner),
+                Side::Sell => tx.quoted_out,
+            };
+            match self.filled(i, tx.side, amount_out) {
+                None => println!("trader: grid {} band {} holds {}", self.id, i, amount_out),
+                Some(cycle) => println!(
+                    "trader: grid {} band {} sold, cycle profit {} ETH",
+                    self.id, i, cycle.profit
+                ),
+            }
+        }
+    }
+}
+
+/// settles the band trades that got mined, then makes one trade per running grid per poll,
+/// the next poll picks up the rest. paper trades are booked right away, live ones once mined.
+pub fn check_grids(
+    wallet: &Wallet<SigningKey>,
+    state: &mut State,
+    dexes: &mut DexRegistry,
+    prices: &mut PriceFeed,
+    chain_id: u64,
+) -> anyhow::Result<()> {
+    let ids: Vec<u64> = state
+        .grids
+        .values()
+        .filter(|g| (g.status == GridStatus::Running || g.has_pending()) && g.chain_id == chain_id)
+        .map(|g| g.id)
+        .collect();
+    if ids.is_empty() {
+        return Ok(());
+    }
+
+    let chain = state.chain(chain_id)?.clone();
+    let dexes = dexes.get(&chain)?;
+
+    for id in ids {
+        let Some(grid) = state.grids.get_mut(&id) else {
+            continue;
+        };
+        grid.settle(wallet.address());
+        if grid.status != GridStatus::Running {
+            continue;
+        }
+        let price = match prices.weth_price(&chain, dexes, grid.token, grid.decimals) {
+            Ok(price) => price,
+            Err(e) => {
+                println!("trader: no price for grid {}: {:?}", id, e);
+                continue;
+            }
+        };
+        let Some((i, side)) = grid.next_action(price) else {
+            continue;
+        };
+
+        let mut account = Account::new(wallet, &mut state.paper, grid.paper);
+        let fill = match side {
+            Side::Buy => {
+                let (amount, min_out) = grid.order(i, side, U256::ZERO);
+                execute_buy(&mut account, &chain, dexes, grid.token, amount, min_out)
+            }
+            Side::Sell => account
+                .token_balance(chain_id, grid.token)
+                .and_then(|balance| {
+                    let (amount, min_out) = grid.order(i, side, balance);
+                    execute_sell(&mut account, &chain, dexes, grid.token, amount, min_out)
+                }),
+        };
+        let fill = match fill {
+            Ok(fill) => fill,
+            Err(e) => {
+                println!("trader: grid {} band {} {:?} failed: {:?}", id, i, side, e);
+                continue;
+            }
+        };
+        // paper fills are exact and final.
+        if matches!(account, Account::Paper(_)) {
+            match grid.filled(i, side, fill.quoted_out) {
+                None => println!("trader: grid {} band {} bought at {} ETH", id, i, price),
+                Some(cycle) => println!(
+                    "trader: grid {} band {} sold at {} ETH, cycle profit {} ETH",
+                    id, i, price, cycle.profit
+                ),
+            }
+            continue;
+        }
+        println!(
+            "trader: grid {} band {} {:?} at {} ETH, sent! {}",
+            id,
+            i,
+            side,
+            price,
+            chain.tx_url(fill.tx_hash)
+        );
+        grid.bands[i].pending = Some(BandTx {
+            tx_hash: fill.tx_hash,
+            side,
+            quoted_out: fill.quoted_out,
+        });
+    }
+
+    state.save()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid() -> Grid {
+        Grid {
+            id: 1,
+            chain_id: 10,
+            paper: true,
+            token: EthAddress::repeat_byte(0x11),
+            symbol: "PEPE".to_string(),
+            decimals: 18,
+            capital_per_level: U256::from(1_000u64),
+            bands: Grid::bands(1.0, 2.0, 5).unwrap(),
+            cycles: vec![],
+            status: GridStatus::Running,
+        }
+    }
+
+    #[test]
+    fn spaces_bands_evenly() {
+        let bands = Grid::bands(1.0, 2.0, 5).unwrap();
+        let edges: Vec<(f64, f64)> = bands.iter().map(|b| (b.lower, b.upper)).collect();
+        assert_eq!(
+            edges,
+            vec![(1.0, 1.25), (1.25, 1.5), (1.5, 1.75), (1.75, 2.0)]
+        );
+        assert!(bands.iter().all(|b| b.held == U256::ZERO));
+    }
+
+    #[test]
+    fn rejects_bad_bounds() {
+        assert!(Grid::bands(2.0, 1.0, 3).is_err());
+        assert!(Grid::bands(0.0, 1.0, 3).is_err());
+        assert!(Grid::bands(1.0, f64::INFINITY, 3).is_err());
+        assert!(Grid::bands(1.0, 2.0, 1).is_err());
+    }
+
+    #[test]
+    fn buys_the_lowest_band_below_the_price() {
+        let grid = grid();
+        assert_eq!(grid.next_action(1.3), Some((2, Side::Buy)));
+        let (amount, min_out) = grid.order(2, Side::Buy, U256::ZERO);
+        assert_eq!(amount, grid.capital_per_level);
+        assert_eq!(min_out, limit_min_out(Side::Buy, amount, 1.5, 18));
+    }
+
+    #[test]
+    fn sells_what_the_band_holds_at_its_upper_level() {
+        let mut grid = grid();
+        assert!(grid.filled(2, Side::Buy, U256::from(600u64)).is_none());
+        assert_eq!(grid.next_action(1.8), Some((2, Side::Sell)));
+
+        // some of it was moved out of the wallet since.
+        let (amount, min_out) = grid.order(2, Side::Sell, U256::from(500u64));
+        assert_eq!(amount, U256::from(500u64));
+        assert_eq!(min_out, limit_min_out(Side::Sell, amount, 1.75, 18));
+
+        let cycle = grid.filled(2, Side::Sell, U256::from(1_100u64)).unwrap();
+        assert_eq!(cycle.cost, U256::from(1_000u64));
+        assert_eq!(grid.bands[2].held, U256::ZERO);
+        assert_eq!(grid.cycles.len(), 1);
+    }
+
+    #[test]
+    fn skips_bands_waiting_on_a_trade() {
+        let mut grid = grid();
+        grid.bands[2].pending = Some(BandTx {
+            tx_hash: TxHash::ZERO,
+            side: Side::Buy,
+            quoted_out: U256::from(600u64),
+        });
+        assert_eq!(grid.next_action(1.3), Some((3, Side::Buy)));
+        grid.bands[3].pending = grid.bands[2].pending.clone();
+        assert_eq!(grid.next_action(1.6), None);
+    }
+}
@@ -1,20 +1,75 @@
 use alloy_consensus::{TxKind, TxLegacy};
-use alloy_sol_types::{SolCall, SolValue};
+use alloy_sol_types::{SolCall, SolEvent, SolValue};
 use kinode_process_lib::eth::{
-    call, get_gas_price, get_transaction_count, Address, TransactionInput, TransactionRequest,
-    U256, U8,
+    Address, BlockId, BlockNumberOrTag, Log, Provider, TransactionInput, TransactionRequest,
+    TxHash, U256, U64, U8,
 };
+use std::str::FromStr;
 
-use crate::helpers::contracts::{IUniswapV2Pair, IUniswapV2Router01, IERC20};
+use crate::helpers::{
+    chains::{eth_provider, ChainConfig},
+    contracts::{IUniswapV2Pair, IERC20, IWETH},
+    dex::Dex,
+    fees::swap_gas_price,
+};
+
+/// the next nonce for from, counting txs still in the mempool.
+/// the latest block's count would hand the same nonce to every tx sent before one is mined.
+pub fn pending_nonce(provider: &Provider, from: Address) -> anyhow::Result<U256> {
+    let nonce =
+        provider.get_transaction_count(from, Some(BlockId::Number(BlockNumberOrTag::Pending)))?;
+    Ok(nonce)
+}
+
+/// what became of a tx we sent.
+#[derive(Debug, Clone)]
+pub enum TxStatus {
+    /// known to the node, not mined yet.
+    Pending,
+    Mined {
+        success: bool,
+        logs: Vec<Log>,
+    },
+    /// neither mined nor in the mempool, e.g. replaced or evicted.
+    Dropped,
+}
+
+pub fn get_tx_status(chain_id: u64, tx_hash: TxHash) -> anyhow::Result<TxStatus> {
+    let provider = eth_provider(chain_id);
+    if let Some(receipt) = provider.get_transaction_receipt(tx_hash)? {
+        return Ok(TxStatus::Mined {
+            success: receipt.status_code == Some(U64::from(1)),
+            logs: receipt.logs,
+        });
+    }
+    match provider.get_transaction_by_hash(tx_hash)? {
+        Some(_) => Ok(TxStatus::Pending),
+        None => Ok(TxStatus::Dropped),
+    }
+}
+
+/// how much of token the Transfer logs among logs moved to owner.
+pub fn received_in(logs: &[Log], token: Address, owner: Address) -> U256 {
+    logs.iter()
+        .filter(|log| log.address() == token)
+        .filter(|log| log.topics().first() == Some(&IERC20::Transfer::SIGNATURE_HASH))
+        .filter_map(|log| {
+            IERC20::Transfer::decode_raw_log(log.topics().iter().copied(), &log.data().data, true)
+                .ok()
+        })
+        .filter(|transfer| transfer.to == owner)
+        .fold(U256::ZERO, |sum, transfer| sum + transfer.value)
+}
 
-pub fn get_erc20_info(address: Address) -> anyhow::Result<(U8, String)> {
+pub fn get_erc20_info(chain_id: u64, address: Address) -> anyhow::Result<(U8, String)> {
+    let provider = eth_provider(chain_id);
     let decimals_call = IERC20::decimalsCall {}.abi_encode();
     let decimals_req = TransactionRequest {
         to: Some(address),
         input: TransactionInput::new(decimals_call.into()),
         ..Default::default()
     };
-    let decimals_res = call(decimals_req, None)?;
+    let decimals_res = provider.call(decimals_req, None)?;
 
     let symbol_call = IERC20::symbolCall {}.abi_encode();
 
@@ -23,7 +78,7 @@ pub fn get_erc20_info(address: Address) -> anyhow::Result<(U8, String)> {
         input: TransactionInput::new(symbol_call.into()),
         ..Default::default()
     };
-    let symbol_res = call(symbol_req, None)?;
+    let symbol_res = provider.call(symbol_req, None)?;
 
     let symbol = String::abi_decode(&symbol_res, false)?;
     // apparently U8 decoding not implemented..
@@ -34,6 +89,7 @@ pub fn get_erc20_info(address: Address) -> anyhow::Result<(U8, String)> {
 }
 
 pub fn get_token_price(
+    chain_id: u64,
     pair_address: Address,
     token0_symbol: &str,
     token1_symbol: &str,
@@ -47,7 +103,7 @@ pub fn get_token_price(
         input: TransactionInput::new(get_reserves_call.into()),
         ..Default::default()
     };
-    let reserves_res = call(reserves_req, None)?;
+    let reserves_res = eth_provider(chain_id).call(reserves_req, None)?;
 
     // Decode the reserves
     let (reserve0, reserve1, _timestamp) = <(U256, U256, U256)>::abi_decode(&reserves_res, false)?;
@@ -72,40 +128,449 @@ pub fn get_token_price(
     Ok((price0_in_terms_of_1, price1_in_terms_of_0))
 }
 
+/// a pair's reserves as (reserve of token_in, reserve of the other token),
+/// now or as they were at a past block.
+pub fn get_reserves(
+    chain_id: u64,
+    pair: Address,
+    token_in: Address,
+    block: Option<u64>,
+) -> anyhow::Result<(U256, U256)> {
+    let (token0, reserve0, reserve1) = get_pair_reserves(chain_id, pair, block)?;
+    if token0 == token_in {
+        Ok((reserve0, reserve1))
+    } else {
+        Ok((reserve1, reserve0))
+    }
+}
+
+/// a pair's token0 and its (reserve0, reserve1), now or at a past block.
+pub fn get_pair_reserves(
+    chain_id: u64,
+    pair: Address,
+    block: Option<u64>,
+) -> anyhow::Result<(Address, U256, U256)> {
+    let provider = eth_provider(chain_id);
+    let token0_req = TransactionRequest {
+        to: Some(pair),
+        input: TransactionInput::new(IUniswapV2Pair::token0Call {}.abi_encode().into()),
+        ..Default::default()
+    };
+    let token0 = Address::abi_decode(&provider.call(token0_req, None)?, false)?;
+
+    let reserves_req = TransactionRequest {
+        to: Some(pair),
+        input: TransactionInput::new(IUniswapV2Pair::getReservesCall {}.abi_encode().into()),
+        ..Default::default()
+    };
+    let reserves_res = provider.call(reserves_req, block.map(BlockId::from))?;
+    let (reserve0, reserve1, _timestamp) = <(U256, U256, U256)>::abi_decode(&reserves_res, false)?;
+    Ok((token0, reserve0, reserve1))
+}
+
+/// Uniswap V2's getAmountOut, exactly as the pair enforces it. fee_bps is 30 on Uniswap V2.
+pub fn v2_amount_out(amount_in: U256, reserve_in: U256, reserve_out: U256, fee_bps: u64) -> U256 {
+    let amount_in_with_fee = amount_in * U256::from(10_000 - fee_bps);
+    let denominator = reserve_in * U256::from(10_000) + amount_in_with_fee;
+    if denominator == U256::ZERO {
+        return U256::ZERO;
+    }
+    amount_in_with_fee * reserve_out / denominator
+}
+
+/// spot price of token in ETH, from the reserves of its WETH pair on the first dex that has one.
+pub fn get_weth_price(
+    chain: &ChainConfig,
+    dexes: &[Box<dyn Dex>],
+    token: Address,
+    decimals: u8,
+) -> anyhow::Result<f64> {
+    let pair_address = dexes
+        .iter()
+        .find_map(|dex| dex.get_pair(chain.weth, token).ok().flatten())
+        .ok_or_else(|| anyhow::anyhow!("no WETH pair for {:?}", token))?;
+
+    let token0_call = IUniswapV2Pair::token0Call {}.abi_encode();
+    let req = TransactionRequest {
+        to: Some(pair_address),
+        input: TransactionInput::new(token0_call.into()),
+        ..Default::default()
+    };
+    let res = chain.provider().call(req, None)?;
+    let token0 = Address::abi_decode(&res, false)?;
+
+    // get_token_price gives (token0 per token1, token1 per token0).
+    if token0 == chain.weth {
+        let (eth_per_token, _) =
+            get_token_price(chain.chain_id, pair_address, "WETH", "", 18, decimals)?;
+        Ok(eth_per_token)
+    } else {
+        let (_, eth_per_token) =
+            get_token_price(chain.chain_id, pair_address, "", "WETH", decimals, 18)?;
+        Ok(eth_per_token)
+    }
+}
+
+/// raw token amount to whole units, lossy, for display and price comparisons.
+pub fn to_units(amount: U256, decimals: u8) -> f64 {
+    amount.to_string().parse::<f64>().unwrap_or(f64::MAX) / 10f64.powi(decimals.into())
+}
+
+/// whole units as written, e.g. "0.1", to a raw token amount. exact, unlike to_units.
+pub fn parse_units(amount: &str, decimals: u8) -> anyhow::Result<U256> {
+    let (whole, frac) = amount.split_once('.').unwrap_or((amount, ""));
+    if (whole.is_empty() && frac.is_empty())
+        || !whole
+            .chars()
+            .chain(frac.chars())
+            .all(|c| c.is_ascii_digit())
+    {
+        return Err(anyhow::anyhow!("{:?} isn't a number", amount));
+    }
+    if frac.len() > decimals as usize {
+        return Err(anyhow::anyhow!(
+            "{} has more than {} decimals",
+            amount,
+            decimals
+        ));
+    }
+    let digits = format!("{}{:0<width$}", whole, frac, width = decimals as usize);
+    Ok(U256::from_str(&digits)?)
+}
+
+/// a block's timestamp, unix seconds.
+pub fn get_block_time(chain_id: u64, block: u64) -> anyhow::Result<u64> {
+    let block = eth_provider(chain_id)
+        .get_block_by_number(BlockNumberOrTag::Number(block), false)?
+        .ok_or_else(|| anyhow::anyhow!("no block {}", block))?;
+    Ok(block.header.timestamp.to::<u64>())
+}
+
+/// swaps expire 20 minutes from now.
+pub fn swap_deadline() -> anyhow::Result<u64> {
+    Ok(std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs()
+        + 60 * 20)
+}
+
+pub fn get_allowance(
+    chain_id: u64,
+    token: Address,
+    owner: Address,
+    spender: Address,
+) -> anyhow::Result<U256> {
+    let allowance_call = IERC20::allowanceCall { owner, spender }.abi_encode();
+    let req = TransactionRequest {
+        to: Some(token),
+        input: TransactionInput::new(allowance_call.into()),
+        ..Default::default()
+    };
+    let res = eth_provider(chain_id).call(req, None)?;
+    let allowance = U256::abi_decode(&res, false)?;
+    Ok(allowance)
+}
+
+pub fn get_erc20_balance(chain_id: u64, token: Address, owner: Address) -> anyhow::Result<U256> {
+    let balance_call = IERC20::balanceOfCall { owner }.abi_encode();
+    let req = TransactionRequest {
+        to: Some(token),
+        input: TransactionInput::new(balance_call.into()),
+        ..Default::default()
+    };
+    let res = eth_provider(chain_id).call(req, None)?;
+    let balance = U256::abi_decode(&res, false)?;
+    Ok(balance)
+}
+
+/// WETH deposit(), amount wei of ETH in, the same amount of WETH out.
+pub fn send_wrap_request(
+    from: Address,
+    chain_id: u64,
+    weth: Address,
+    amount: U256,
+) -> anyhow::Result<TxLegacy> {
+    let deposit_call = IWETH::depositCall {}.abi_encode();
+
+    let provider = eth_provider(chain_id);
+    let gas_price = provider.get_gas_price()?;
+    let nonce = pending_nonce(&provider, from)?;
+
+    let tx = TxLegacy {
+        nonce: nonce.to::<u64>(),
+        gas_price: gas_price.to::<u128>(),
+        gas_limit: 60000,
+        to: TxKind::Call(weth),
+        value: amount,
+        input: deposit_call.into(),
+        chain_id: Some(chain_id),
+    };
+
+    Ok(tx)
+}
+
+/// WETH withdraw(wad), burns amount WETH and sends back the ETH.
+pub fn send_unwrap_request(
+    from: Address,
+    chain_id: u64,
+    weth: Address,
+    amount: U256,
+) -> anyhow::Result<TxLegacy> {
+    let withdraw_call = IWETH::withdrawCall { wad: amount }.abi_encode();
+
+    let provider = eth_provider(chain_id);
+    let gas_price = provider.get_gas_price()?;
+    let nonce = pending_nonce(&provider, from)?;
+
+    let tx = TxLegacy {
+        nonce: nonce.to::<u64>(),
+        gas_price: gas_price.to::<u128>(),
+        gas_limit: 60000,
+        to: TxKind::Call(weth),
+        value: U256::ZERO,
+        input: withdraw_call.into(),
+        chain_id: Some(chain_id),
+    };
+
+    Ok(tx)
+}
+
+pub fn send_approve_request(
+    from: Address,
+    chain_id: u64,
+    token: Address,
+    spender: Address,
+    amount: U256,
+) -> anyhow::Result<TxLegacy> {
+    let approve_call = IERC20::approveCall { spender, amount }.abi_encode();
+
+    let provider = eth_provider(chain_id);
+    let gas_price = provider.get_gas_price()?;
+    let nonce = pending_nonce(&provider, from)?;
+
+    let tx = TxLegacy {
+        nonce: nonce.to::<u64>(),
+        gas_price: gas_price.to::<u128>(),
+        gas_limit: 60000,
+        to: TxKind::Call(token),
+        value: U256::ZERO,
+        input: approve_call.into(),
+        chain_id: Some(chain_id),
+    };
+
+    Ok(tx)
+}
+
 pub fn send_swap_call_request(
-    from: Address,           // Address of the sender
-    chain_id: u64,           // Chain ID
-    router_address: Address, // Address of the Uniswap router
-    amount_in: u64,          // Amount of ETH to swap
-    min_amount_out: U256,    // Minimum amount of the other token you're willing to accept
-    path: Vec<Address>,      // Path of the swap (ETH -> Other Token)
+    from: Address,         // Address of the sender
+    chain_id: u64,         // Chain ID
+    dex: &dyn Dex,         // Dex whose router we swap on
+    amount_in: U256,       // Amount of ETH to swap
+    min_amount_out: U256,  // Minimum amount of the other token you're willing to accept
+    path: Vec<Address>,    // Path of the swap (ETH -> Other Token)
+    fee_on_transfer: bool, // Use the variant that tolerates taxed tokens
 ) -> anyhow::Result<TxLegacy> {
     // Encode the call to swapExactETHForTokens
-    let deadline = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)?
-        .as_secs()
-        + 60 * 20;
+    let deadline = swap_deadline()?;
 
-    let swap_call = IUniswapV2Router01::swapExactETHForTokensCall {
-        amountOutMin: min_amount_out,
-        path: path,
-        to: from,
-        deadline: U256::from(deadline),
-    }
-    .abi_encode();
+    let swap_call = dex.swap_exact_eth_for_tokens(
+        min_amount_out,
+        path,
+        from,
+        U256::from(deadline),
+        fee_on_transfer,
+    );
 
-    let gas_price = get_gas_price()?;
-    let nonce = get_transaction_count(from, None)?;
+    let provider = eth_provider(chain_id);
+    let gas_price = provider.get_gas_price()?;
+    let nonce = pending_nonce(&provider, from)?;
 
     let tx = TxLegacy {
         nonce: nonce.to::<u64>(),
-        gas_price: gas_price.to::<u128>() * 8,
+        gas_price: swap_gas_price(gas_price).to::<u128>(),
+        gas_limit: if fee_on_transfer { 300000 } else { 220000 },
+        to: TxKind::Call(dex.router()),
+        value: amount_in,
+        input: swap_call.into(),
+        chain_id: Some(chain_id),
+    };
+
+    Ok(tx)
+}
+
+/// exact-output buy: we send amount_in_max as value, the router refunds whatever it doesn't use.
+pub fn send_eth_for_exact_tokens_request(
+    from: Address,
+    chain_id: u64,
+    dex: &dyn Dex,
+    amount_out: U256,
+    amount_in_max: U256,
+    path: Vec<Address>,
+) -> anyhow::Result<TxLegacy> {
+    let deadline = swap_deadline()?;
+
+    let swap_call = dex.swap_eth_for_exact_tokens(amount_out, path, from, U256::from(deadline))?;
+
+    let provider = eth_provider(chain_id);
+    let gas_price = provider.get_gas_price()?;
+    let nonce = pending_nonce(&provider, from)?;
+
+    let tx = TxLegacy {
+        nonce: nonce.to::<u64>(),
+        gas_price: swap_gas_price(gas_price).to::<u128>(),
         gas_limit: 220000,
-        to: TxKind::Call(router_address),
-        value: U256::from(amount_in),
+        to: TxKind::Call(dex.router()),
+        value: amount_in_max,
         input: swap_call.into(),
         chain_id: Some(chain_id),
     };
 
     Ok(tx)
 }
+
+/// exact-output sell: pulls at most amount_in_max tokens, needs an allowance for the router.
+pub fn send_tokens_for_exact_eth_request(
+    from: Address,
+    chain_id: u64,
+    dex: &dyn Dex,
+    amount_out: U256,
+    amount_in_max: U256,
+    path: Vec<Address>,
+) -> anyhow::Result<TxLegacy> {
+    let deadline = swap_deadline()?;
+
+    let swap_call =
+        dex.swap_tokens_for_exact_eth(amount_out, amount_in_max, path, from, U256::from(deadline))?;
+
+    let provider = eth_provider(chain_id);
+    let gas_price = provider.get_gas_price()?;
+    let nonce = pending_nonce(&provider, from)?;
+
+    let tx = TxLegacy {
+        nonce: nonce.to::<u64>(),
+        gas_price: swap_gas_price(gas_price).to::<u128>(),
+        gas_limit: 220000,
+        to: TxKind::Call(dex.router()),
+        value: U256::ZERO,
+        input: swap_call.into(),
+        chain_id: Some(chain_id),
+    };
+
+    Ok(tx)
+}
+
+pub fn send_tokens_for_tokens_request(
+    from: Address,
+    chain_id: u64,
+    dex: &dyn Dex,
+    amount_in: U256,
+    min_amount_out: U256,
+    path: Vec<Address>,
+    fee_on_transfer: bool,
+) -> anyhow::Result<TxLegacy> {
+    let deadline = swap_deadline()?;
+
+    let swap_call = dex.swap_exact_tokens_for_tokens(
+        amount_in,
+        min_amount_out,
+        path,
+        from,
+        U256::from(deadline),
+        fee_on_transfer,
+    );
+
+    let provider = eth_provider(chain_id);
+    let gas_price = provider.get_gas_price()?;
+    let nonce = pending_nonce(&provider, from)?;
+
+    let tx = TxLegacy {
+        nonce: nonce.to::<u64>(),
+        gas_price: swap_gas_price(gas_price).to::<u128>(),
+        gas_limit: if fee_on_transfer { 350000 } else { 280000 },
+        to: TxKind::Call(dex.router()),
+        value: U256::ZERO,
+        input: swap_call.into(),
+        chain_id: Some(chain_id),
+    };
+
+    Ok(tx)
+}
+
+/// exact-input sell into ETH, needs an allowance for the router.
+pub fn send_tokens_for_eth_request(
+    from: Address,
+    chain_id: u64,
+    dex: &dyn Dex,
+    amount_in: U256,
+    min_amount_out: U256,
+    path: Vec<Address>,
+    fee_on_transfer: bool,
+) -> anyhow::Result<TxLegacy> {
+    let deadline = swap_deadline()?;
+
+    let swap_call = dex.swap_exact_tokens_for_eth(
+        amount_in,
+        min_amount_out,
+        path,
+        from,
+        U256::from(deadline),
+        fee_on_transfer,
+    );
+
+    let provider = eth_provider(chain_id);
+    let gas_price = provider.get_gas_price()?;
+    let nonce = pending_nonce(&provider, from)?;
+
+    let tx = TxLegacy {
+        nonce: nonce.to::<u64>(),
+        gas_price: swap_gas_price(gas_price).to::<u128>(),
+        gas_limit: if fee_on_transfer { 300000 } else { 220000 },
+        to: TxKind::Call(dex.router()),
+        value: U256::ZERO,
+        input: swap_call.into(),
+        chain_id: Some(chain_id),
+    };
+
+    Ok(tx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ETH: u128 = 1_000_000_000_000_000_000;
+
+    #[test]
+    fn charges_the_fee_on_the_way_in() {
+        let out = v2_amount_out(
+            U256::from(ETH),
+            U256::from(100 * ETH),
+            U256::from(200 * ETH),
+            30,
+        );
+        assert_eq!(out, U256::from(1_974_316_068_794_122_597u64));
+    }
+
+    #[test]
+    fn keeps_the_product_without_a_fee() {
+        let out = v2_amount_out(
+            U256::from(ETH),
+            U256::from(100 * ETH),
+            U256::from(200 * ETH),
+            0,
+        );
+        assert_eq!(out, U256::from(1_980_198_019_801_980_198u64));
+    }
+
+    #[test]
+    fn empty_pools_give_nothing() {
+        assert_eq!(
+            v2_amount_out(U256::ZERO, U256::ZERO, U256::from(ETH), 30),
+            U256::ZERO
+        );
+        assert_eq!(
+            v2_amount_out(U256::from(ETH), U256::from(ETH), U256::ZERO, 30),
+            U256::ZERO
+        );
+    }
+}
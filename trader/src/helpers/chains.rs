@@ -0,0 +1,204 @@
+use kinode_process_lib::{
+    eth::{Address, Provider},
+    vfs::open_file,
+    Address as ProcessAddress,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::str::FromStr;
+
+use crate::helpers::dex::DexConfig;
+
+// seconds we give the eth provider before a request times out.
+pub const ETH_TIMEOUT: u64 = 30;
+
+/// every eth request goes through the provider for the chain it's meant for.
+pub fn eth_provider(chain_id: u64) -> Provider {
+    Provider::new(chain_id, ETH_TIMEOUT)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TxType {
+    Legacy,
+    Eip1559,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct V3Config {
+    pub factory: Address,
+    pub quoter: Address,
+    pub router: Address,
+}
+
+/// everything the trader needs to know about a chain.
+/// the defaults ship in pkg/chains.json, edits via SetChainConfig live in state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainConfig {
+    pub chain_id: u64,
+    pub name: String,
+    pub weth: Address,
+    pub dexes: Vec<DexConfig>,
+    pub v3: Option<V3Config>,
+    /// intermediate tokens we're willing to route through.
+    pub base_tokens: Vec<Address>,
+    /// blocks to wait before we treat a tx as final.
+    pub confirmations: u64,
+    pub tx_type: TxType,
+    pub explorer_url: String,
+    /// the GasPriceOracle on OP stack chains, every tx there also pays for its L1 data.
+    #[serde(default)]
+    pub l1_fee_oracle: Option<Address>,
+    /// symbols requests may use instead of addresses, e.g. in rules.
+    #[serde(default)]
+    pub tokens: BTreeMap<String, Address>,
+}
+
+impl ChainConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.name.is_empty() {
+            return Err(anyhow::anyhow!("chain {} has no name", self.chain_id));
+        }
+        if self.weth == Address::ZERO {
+            return Err(anyhow::anyhow!(
+                "chain {} has a zero WETH address",
+                self.chain_id
+            ));
+        }
+        if self.dexes.is_empty() && self.v3.is_none() {
+            return Err(anyhow::anyhow!("chain {} has no dexes", self.chain_id));
+        }
+
+        let mut names = HashSet::new();
+        for dex in &self.dexes {
+            if !names.insert(&dex.name) {
+                return Err(anyhow::anyhow!(
+                    "chain {} has dex {} twice",
+                    self.chain_id,
+                    dex.name
+                ));
+            }
+            if dex.factory == Address::ZERO || dex.router == Address::ZERO {
+                return Err(anyhow::anyhow!(
+                    "chain {} dex {} has a zero address",
+                    self.chain_id,
+                    dex.name
+                ));
+            }
+        }
+
+        if let Some(v3) = &self.v3 {
+            if v3.factory == Address::ZERO
+                || v3.quoter == Address::ZERO
+                || v3.router == Address::ZERO
+            {
+                return Err(anyhow::anyhow!(
+                    "chain {} v3 has a zero address",
+                    self.chain_id
+                ));
+            }
+        }
+
+        if self.base_tokens.contains(&Address::ZERO) {
+            return Err(anyhow::anyhow!(
+                "chain {} has a zero base token",
+                self.chain_id
+            ));
+        }
+        if self.l1_fee_oracle == Some(Address::ZERO) {
+            return Err(anyhow::anyhow!(
+                "chain {} has a zero l1_fee_oracle",
+                self.chain_id
+            ));
+        }
+        if let Some((symbol, _)) = self.tokens.iter().find(|(_, a)| **a == Address::ZERO) {
+            return Err(anyhow::anyhow!(
+                "chain {} token {} has a zero address",
+                self.chain_id,
+                symbol
+            ));
+        }
+        if self.tx_type != TxType::Legacy {
+            // the signer only builds legacy txs for now.
+            return Err(anyhow::anyhow!(
+                "chain {} tx_type {:?} isn't supported yet",
+                self.chain_id,
+                self.tx_type
+            ));
+        }
+        if !self.explorer_url.starts_with("https://") && !self.explorer_url.starts_with("http://") {
+            return Err(anyhow::anyhow!(
+                "chain {} explorer_url should be an http(s) url",
+                self.chain_id
+            ));
+        }
+        Ok(())
+    }
+
+    /// takes what a newer shipped config adds that this one leaves unset.
+    pub fn fill_from(&mut self, shipped: &ChainConfig) {
+        if self.l1_fee_oracle.is_none() {
+            self.l1_fee_oracle = shipped.l1_fee_oracle;
+        }
+        for (symbol, address) in &shipped.tokens {
+            self.tokens.entry(symbol.clone()).or_insert(*address);
+        }
+    }
+
+    pub fn provider(&self) -> Provider {
+        eth_provider(self.chain_id)
+    }
+
+    pub fn tx_url(&self, tx_hash: impl std::fmt::Debug) -> String {
+        format!(
+            "{}/tx/{:?}",
+            self.explorer_url.trim_end_matches('/'),
+            tx_hash
+        )
+    }
+
+    /// a token by address or by symbol, ETH and WETH both mean WETH.
+    pub fn token(&self, name: &str) -> anyhow::Result<Address> {
+        if name.eq_ignore_ascii_case("ETH") || name.eq_ignore_ascii_case("WETH") {
+            return Ok(self.weth);
+        }
+        if name.starts_with("0x") {
+            return Ok(Address::from_str(name)?);
+        }
+        self.tokens
+            .iter()
+            .find(|(symbol, _)| symbol.eq_ignore_ascii_case(name))
+            .map(|(_, address)| *address)
+            .ok_or_else(|| anyhow::anyhow!("chain {} has no token {}", self.chain_id, name))
+    }
+
+    pub fn v3(&self) -> anyhow::Result<&V3Config> {
+        self.v3
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("no uniswap v3 configured for chain {}", self.chain_id))
+    }
+}
+
+/// reads and validates the chains.json shipped with the package.
+/// the order is kept, the first chain listed is the default.
+pub fn load_package_chains(our: &ProcessAddress) -> anyhow::Result<Vec<ChainConfig>> {
+    let path = format!("/{}/pkg/chains.json", our.package_id());
+    let file = open_file(&path, false)?;
+    let json = file.read_to_string()?;
+    parse_chains(&json)
+}
+
+pub fn parse_chains(json: &str) -> anyhow::Result<Vec<ChainConfig>> {
+    let configs = serde_json::from_str::<Vec<ChainConfig>>(json)?;
+
+    let mut seen = HashSet::new();
+    for config in &configs {
+        config.validate()?;
+        if !seen.insert(config.chain_id) {
+            return Err(anyhow::anyhow!(
+                "chains.json lists chain {} twice",
+                config.chain_id
+            ));
+        }
+    }
+    Ok(configs)
+}
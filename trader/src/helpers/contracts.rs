@@ -1,35 +1,9 @@
 use alloy_sol_types::sol;
-use kinode_process_lib::eth::Address;
-use lazy_static::lazy_static;
-use std::collections::HashMap;
+// contract addresses live in pkg/chains.json, see helpers/chains.rs.
 // here we define the types from the ABIs we need.
 // we could compile the contracts, and import their entire JSONs,
 // but here we go the lightweight route and only define the functions that we need!
 
-// contract addresses for Sepolia and Optimism (for now)
-lazy_static! {
-    pub static ref WETH: HashMap<u64, Address> = {
-        let mut m = HashMap::new();
-        m.insert(10, "0x4200000000000000000000000000000000000006".parse::<Address>().unwrap()); // Optimism
-        m.insert(11155111, "0x7b79995e5f793A07Bc00c21412e50Ecae098E7f9".parse::<Address>().unwrap()); // Sepolia
-        m
-    };
-
-    pub static ref FACTORY: HashMap<u64, Address> = {
-        let mut m = HashMap::new();
-        m.insert(10, "0x7E0987E5b3a30e3f2828572Bb659A548460a3003".parse::<Address>().unwrap());
-        m.insert(11155111,"0x7E0987E5b3a30e3f2828572Bb659A548460a3003".parse::<Address>().unwrap());
-        m
-    };
-
-    pub static ref ROUTER: HashMap<u64, Address> = {
-        let mut m = HashMap::new();
-        m.insert(10, "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2".parse::<Address>().unwrap());
-        m.insert(11155111, "0xC532a74256D3Db42D0Bf7a0400fEFDbad7694008".parse::<Address>().unwrap());
-        m
-    };
-}
-
 sol! {
     /// Interface of the ERC20 standard as defined in [the EIP].
     ///
@@ -52,9 +26,19 @@ sol! {
     }
 }
 
+sol! {
+    #[derive(Debug)]
+    interface IWETH {
+        function deposit() external payable;
+        function withdraw(uint wad) external;
+    }
+}
+
 sol! {
     #[derive(Debug)]
     interface IUniswapV2Factory {
+        event PairCreated(address indexed token0, address indexed token1, address pair, uint pairIndex);
+
         function getPair(address tokenA, address tokenB) external view returns (address pair);
     }
 }
@@ -62,6 +46,9 @@ sol! {
 sol! {
     #[derive(Debug)]
     interface IUniswapV2Pair {
+        event Swap(address indexed sender, uint amount0In, uint amount1In, uint amount0Out, uint amount1Out, address indexed to);
+        event Sync(uint112 reserve0, uint112 reserve1);
+
         function token0() external view returns (address);
         function token1() external view returns (address);
         function getReserves() external view returns (uint112 reserve0, uint112 reserve1, uint32 blockTimestampLast);
@@ -100,6 +87,9 @@ sol! {
             payable
             returns (uint[] memory amounts);
 
+        function factory() external pure returns (address);
+        function WETH() external pure returns (address);
+
         function quote(uint amountA, uint reserveA, uint reserveB) external pure returns (uint amountB);
         function getAmountOut(uint amountIn, uint reserveIn, uint reserveOut) external pure returns (uint amountOut);
         function getAmountIn(uint amountOut, uint reserveIn, uint reserveOut) external pure returns (uint amountIn);
@@ -107,3 +97,152 @@ sol! {
         function getAmountsIn(uint amountOut, address[] calldata path) external view returns (uint[] memory amounts);
     }
 }
+
+sol! {
+    #[derive(Debug)]
+    interface IUniswapV2Router02 {
+        function swapExactTokensForTokensSupportingFeeOnTransferTokens(
+            uint amountIn,
+            uint amountOutMin,
+            address[] calldata path,
+            address to,
+            uint deadline
+        ) external;
+        function swapExactETHForTokensSupportingFeeOnTransferTokens(
+            uint amountOutMin,
+            address[] calldata path,
+            address to,
+            uint deadline
+        ) external payable;
+        function swapExactTokensForETHSupportingFeeOnTransferTokens(
+            uint amountIn,
+            uint amountOutMin,
+            address[] calldata path,
+            address to,
+            uint deadline
+        ) external;
+    }
+}
+
+sol! {
+    #[derive(Debug)]
+    interface IUniswapV3Factory {
+        function getPool(address tokenA, address tokenB, uint24 fee) external view returns (address pool);
+    }
+}
+
+sol! {
+    #[derive(Debug)]
+    interface IQuoterV2 {
+        struct QuoteExactInputSingleParams {
+            address tokenIn;
+            address tokenOut;
+            uint256 amountIn;
+            uint24 fee;
+            uint160 sqrtPriceLimitX96;
+        }
+
+        function quoteExactInputSingle(QuoteExactInputSingleParams memory params)
+            external
+            returns (uint256 amountOut, uint160 sqrtPriceX96After, uint32 initializedTicksCrossed, uint256 gasEstimate);
+        function quoteExactInput(bytes memory path, uint256 amountIn)
+            external
+            returns (uint256 amountOut, uint160[] memory sqrtPriceX96AfterList, uint32[] memory initializedTicksCrossedList, uint256 gasEstimate);
+    }
+}
+
+sol! {
+    #[derive(Debug)]
+    interface ISwapRouter02 {
+        struct ExactInputSingleParams {
+            address tokenIn;
+            address tokenOut;
+            uint24 fee;
+            address recipient;
+            uint256 amountIn;
+            uint256 amountOutMinimum;
+            uint160 sqrtPriceLimitX96;
+        }
+
+        struct ExactInputParams {
+            bytes path;
+            address recipient;
+            uint256 amountIn;
+            uint256 amountOutMinimum;
+        }
+
+        function exactInputSingle(ExactInputSingleParams calldata params) external payable returns (uint256 amountOut);
+        function exactInput(ExactInputParams calldata params) external payable returns (uint256 amountOut);
+    }
+}
+
+sol! {
+    #[derive(Debug)]
+    interface IVelodromePoolFactory {
+        function getPool(address tokenA, address tokenB, bool stable) external view returns (address);
+    }
+}
+
+sol! {
+    #[derive(Debug)]
+    interface IVelodromeRouter {
+        struct Route {
+            address from;
+            address to;
+            bool stable;
+            address factory;
+        }
+
+        function defaultFactory() external view returns (address);
+        function weth() external view returns (address);
+
+        function getAmountsOut(uint256 amountIn, Route[] memory routes) external view returns (uint256[] memory amounts);
+
+        function swapExactTokensForTokens(
+            uint256 amountIn,
+            uint256 amountOutMin,
+            Route[] calldata routes,
+            address to,
+            uint256 deadline
+        ) external returns (uint256[] memory amounts);
+        function swapExactETHForTokens(uint256 amountOutMin, Route[] calldata routes, address to, uint256 deadline)
+            external
+            payable
+            returns (uint256[] memory amounts);
+        function swapExactTokensForETH(
+            uint256 amountIn,
+            uint256 amountOutMin,
+            Route[] calldata routes,
+            address to,
+            uint256 deadline
+        ) external returns (uint256[] memory amounts);
+
+        function swapExactTokensForTokensSupportingFeeOnTransferTokens(
+            uint256 amountIn,
+            uint256 amountOutMin,
+            Route[] calldata routes,
+            address to,
+            uint256 deadline
+        ) external;
+        function swapExactETHForTokensSupportingFeeOnTransferTokens(
+            uint256 amountOutMin,
+            Route[] calldata routes,
+            address to,
+            uint256 deadline
+        ) external payable;
+        function swapExactTokensForETHSupportingFeeOnTransferTokens(
+            uint256 amountIn,
+            uint256 amountOutMin,
+            Route[] calldata routes,
+            address to,
+            uint256 deadline
+        ) external;
+    }
+}
+
+sol! {
+    // OP stack predeploy, prices the L1 data a tx posts.
+    interface IGasPriceOracle {
+        function getL1Fee(bytes memory _data) external view returns (uint256);
+    }
+}
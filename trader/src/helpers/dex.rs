@@ -0,0 +1,607 @@
+use alloy_sol_types::{SolCall, SolValue};
+use kinode_process_lib::{
+    eth::{Address, Bytes, TransactionInput, TransactionRequest, U256},
+    println,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::helpers::{
+    chains::{eth_provider, ChainConfig},
+    contracts::{
+        IUniswapV2Factory, IUniswapV2Router01, IUniswapV2Router02, IVelodromePoolFactory,
+        IVelodromeRouter,
+    },
+};
+
+/// a V2-style AMM we can quote and swap on.
+/// the encode functions only build calldata, the caller wraps it into a tx to router().
+pub trait Dex {
+    fn name(&self) -> &str;
+    fn chain_id(&self) -> u64;
+    fn factory(&self) -> Address;
+    fn router(&self) -> Address;
+
+    /// checks the router is wired to the factory and WETH we think it is.
+    fn verify(&self, weth: Address) -> anyhow::Result<()>;
+
+    fn get_pair(&self, token_a: Address, token_b: Address) -> anyhow::Result<Option<Address>>;
+    fn get_amounts_out(&self, amount_in: U256, path: &[Address]) -> anyhow::Result<Vec<U256>>;
+    /// the swap fee, for pools that use plain constant product math we can replicate off-chain.
+    fn fee_bps(&self) -> Option<u64> {
+        None
+    }
+    fn get_amounts_in(&self, _amount_out: U256, _path: &[Address]) -> anyhow::Result<Vec<U256>> {
+        Err(anyhow::anyhow!(
+            "{} doesn't support exact output",
+            self.name()
+        ))
+    }
+
+    fn swap_exact_eth_for_tokens(
+        &self,
+        min_amount_out: U256,
+        path: Vec<Address>,
+        to: Address,
+        deadline: U256,
+        fee_on_transfer: bool,
+    ) -> Vec<u8>;
+    fn swap_exact_tokens_for_tokens(
+        &self,
+        amount_in: U256,
+        min_amount_out: U256,
+        path: Vec<Address>,
+        to: Address,
+        deadline: U256,
+        fee_on_transfer: bool,
+    ) -> Vec<u8>;
+    fn swap_exact_tokens_for_eth(
+        &self,
+        amount_in: U256,
+        min_amount_out: U256,
+        path: Vec<Address>,
+        to: Address,
+        deadline: U256,
+        fee_on_transfer: bool,
+    ) -> Vec<u8>;
+
+    fn swap_eth_for_exact_tokens(
+        &self,
+        _amount_out: U256,
+        _path: Vec<Address>,
+        _to: Address,
+        _deadline: U256,
+    ) -> anyhow::Result<Vec<u8>> {
+        Err(anyhow::anyhow!(
+            "{} doesn't support exact output",
+            self.name()
+        ))
+    }
+    fn swap_tokens_for_exact_eth(
+        &self,
+        _amount_out: U256,
+        _amount_in_max: U256,
+        _path: Vec<Address>,
+        _to: Address,
+        _deadline: U256,
+    ) -> anyhow::Result<Vec<u8>> {
+        Err(anyhow::anyhow!(
+            "{} doesn't support exact output",
+            self.name()
+        ))
+    }
+}
+
+fn view_call(chain_id: u64, to: Address, input: Vec<u8>) -> anyhow::Result<Bytes> {
+    let req = TransactionRequest {
+        to: Some(to),
+        input: TransactionInput::new(input.into()),
+        ..Default::default()
+    };
+    Ok(eth_provider(chain_id).call(req, None)?)
+}
+
+/// Uniswap V2 and its straight forks (Sushi etc.), same ABI, different addresses.
+pub struct UniswapV2 {
+    pub name: String,
+    pub chain_id: u64,
+    pub factory: Address,
+    pub router: Address,
+}
+
+impl Dex for UniswapV2 {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
+
+    fn factory(&self) -> Address {
+        self.factory
+    }
+
+    fn router(&self) -> Address {
+        self.router
+    }
+
+    fn verify(&self, weth: Address) -> anyhow::Result<()> {
+        let res = view_call(
+            self.chain_id,
+            self.router,
+            IUniswapV2Router01::factoryCall {}.abi_encode(),
+        )?;
+        let factory = Address::abi_decode(&res, false)?;
+        if factory != self.factory {
+            return Err(anyhow::anyhow!(
+                "{} router reports factory {:?}, expected {:?}",
+                self.name,
+                factory,
+                self.factory
+            ));
+        }
+
+        let res = view_call(
+            self.chain_id,
+            self.router,
+            IUniswapV2Router01::WETHCall {}.abi_encode(),
+        )?;
+        let router_weth = Address::abi_decode(&res, false)?;
+        if router_weth != weth {
+            return Err(anyhow::anyhow!(
+                "{} router reports WETH {:?}, expected {:?}",
+                self.name,
+                router_weth,
+                weth
+            ));
+        }
+        Ok(())
+    }
+
+    fn get_pair(&self, token_a: Address, token_b: Address) -> anyhow::Result<Option<Address>> {
+        let func_call = IUniswapV2Factory::getPairCall {
+            tokenA: token_a,
+            tokenB: token_b,
+        }
+        .abi_encode();
+        let res = view_call(self.chain_id, self.factory, func_call)?;
+        let pair = Address::abi_decode(&res, false)?;
+
+        if pair == Address::ZERO {
+            Ok(None)
+        } else {
+            Ok(Some(pair))
+        }
+    }
+
+    fn get_amounts_out(&self, amount_in: U256, path: &[Address]) -> anyhow::Result<Vec<U256>> {
+        let amounts_call = IUniswapV2Router01::getAmountsOutCall {
+            amountIn: amount_in,
+            path: path.to_vec(),
+        }
+        .abi_encode();
+        let res = view_call(self.chain_id, self.router, amounts_call)?;
+        let amounts = Vec::<U256>::abi_decode(&res, false)?;
+        Ok(amounts)
+    }
+
+    fn fee_bps(&self) -> Option<u64> {
+        Some(30)
+    }
+
+    fn get_amounts_in(&self, amount_out: U256, path: &[Address]) -> anyhow::Result<Vec<U256>> {
+        let amounts_call = IUniswapV2Router01::getAmountsInCall {
+            amountOut: amount_out,
+            path: path.to_vec(),
+        }
+        .abi_encode();
+        let res = view_call(self.chain_id, self.router, amounts_call)?;
+        let amounts = Vec::<U256>::abi_decode(&res, false)?;
+        Ok(amounts)
+    }
+
+    fn swap_exact_eth_for_tokens(
+        &self,
+        min_amount_out: U256,
+        path: Vec<Address>,
+        to: Address,
+        deadline: U256,
+        fee_on_transfer: bool,
+    ) -> Vec<u8> {
+        if fee_on_transfer {
+            IUniswapV2Router02::swapExactETHForTokensSupportingFeeOnTransferTokensCall {
+                amountOutMin: min_amount_out,
+                path,
+                to,
+                deadline,
+            }
+            .abi_encode()
+        } else {
+            IUniswapV2Router01::swapExactETHForTokensCall {
+                amountOutMin: min_amount_out,
+                path,
+                to,
+                deadline,
+            }
+            .abi_encode()
+        }
+    }
+
+    fn swap_exact_tokens_for_tokens(
+        &self,
+        amount_in: U256,
+        min_amount_out: U256,
+        path: Vec<Address>,
+        to: Address,
+        deadline: U256,
+        fee_on_transfer: bool,
+    ) -> Vec<u8> {
+        if fee_on_transfer {
+            IUniswapV2Router02::swapExactTokensForTokensSupportingFeeOnTransferTokensCall {
+                amountIn: amount_in,
+                amountOutMin: min_amount_out,
+                path,
+                to,
+                deadline,
+            }
+            .abi_encode()
+        } else {
+            IUniswapV2Router01::swapExactTokensForTokensCall {
+                amountIn: amount_in,
+                amountOutMin: min_amount_out,
+                path,
+                to,
+                deadline,
+            }
+            .abi_encode()
+        }
+    }
+
+    fn swap_exact_tokens_for_eth(
+        &self,
+        amount_in: U256,
+        min_amount_out: U256,
+        path: Vec<Address>,
+        to: Address,
+        deadline: U256,
+        fee_on_transfer: bool,
+    ) -> Vec<u8> {
+        if fee_on_transfer {
+            IUniswapV2Router02::swapExactTokensForETHSupportingFeeOnTransferTokensCall {
+                amountIn: amount_in,
+                amountOutMin: min_amount_out,
+                path,
+                to,
+                deadline,
+            }
+            .abi_encode()
+        } else {
+            IUniswapV2Router01::swapExactTokensForETHCall {
+                amountIn: amount_in,
+                amountOutMin: min_amount_out,
+                path,
+                to,
+                deadline,
+            }
+            .abi_encode()
+        }
+    }
+
+    fn swap_eth_for_exact_tokens(
+        &self,
+        amount_out: U256,
+        path: Vec<Address>,
+        to: Address,
+        deadline: U256,
+    ) -> anyhow::Result<Vec<u8>> {
+        Ok(IUniswapV2Router01::swapETHForExactTokensCall {
+            amountOut: amount_out,
+            path,
+            to,
+            deadline,
+        }
+        .abi_encode())
+    }
+
+    fn swap_tokens_for_exact_eth(
+        &self,
+        amount_out: U256,
+        amount_in_max: U256,
+        path: Vec<Address>,
+        to: Address,
+        deadline: U256,
+    ) -> anyhow::Result<Vec<u8>> {
+        Ok(IUniswapV2Router01::swapTokensForExactETHCall {
+            amountOut: amount_out,
+            amountInMax: amount_in_max,
+            path,
+            to,
+            deadline,
+        }
+        .abi_encode())
+    }
+}
+
+/// Velodrome/Aerodrome style router. every pool is either stable (curve-ish) or
+/// volatile (x*y=k), we register one instance per pool type so each path sticks to one.
+pub struct Velodrome {
+    pub name: String,
+    pub chain_id: u64,
+    pub factory: Address,
+    pub router: Address,
+    pub stable: bool,
+}
+
+impl Velodrome {
+    fn routes(&self, path: &[Address]) -> Vec<IVelodromeRouter::Route> {
+        path.windows(2)
+            .map(|hop| IVelodromeRouter::Route {
+                from: hop[0],
+                to: hop[1],
+                stable: self.stable,
+                factory: self.factory,
+            })
+            .collect()
+    }
+}
+
+impl Dex for Velodrome {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
+
+    fn factory(&self) -> Address {
+        self.factory
+    }
+
+    fn router(&self) -> Address {
+        self.router
+    }
+
+    fn verify(&self, weth: Address) -> anyhow::Result<()> {
+        let res = view_call(
+            self.chain_id,
+            self.router,
+            IVelodromeRouter::defaultFactoryCall {}.abi_encode(),
+        )?;
+        let factory = Address::abi_decode(&res, false)?;
+        if factory != self.factory {
+            return Err(anyhow::anyhow!(
+                "{} router reports factory {:?}, expected {:?}",
+                self.name,
+                factory,
+                self.factory
+            ));
+        }
+
+        let res = view_call(
+            self.chain_id,
+            self.router,
+            IVelodromeRouter::wethCall {}.abi_encode(),
+        )?;
+        let router_weth = Address::abi_decode(&res, false)?;
+        if router_weth != weth {
+            return Err(anyhow::anyhow!(
+                "{} router reports WETH {:?}, expected {:?}",
+                self.name,
+                router_weth,
+                weth
+            ));
+        }
+        Ok(())
+    }
+
+    fn get_pair(&self, token_a: Address, token_b: Address) -> anyhow::Result<Option<Address>> {
+        let func_call = IVelodromePoolFactory::getPoolCall {
+            tokenA: token_a,
+            tokenB: token_b,
+            stable: self.stable,
+        }
+        .abi_encode();
+        let res = view_call(self.chain_id, self.factory, func_call)?;
+        let pool = Address::abi_decode(&res, false)?;
+
+        if pool == Address::ZERO {
+            Ok(None)
+        } else {
+            Ok(Some(pool))
+        }
+    }
+
+    fn get_amounts_out(&self, amount_in: U256, path: &[Address]) -> anyhow::Result<Vec<U256>> {
+        let amounts_call = IVelodromeRouter::getAmountsOutCall {
+            amountIn: amount_in,
+            routes: self.routes(path),
+        }
+        .abi_encode();
+        let res = view_call(self.chain_id, self.router, amounts_call)?;
+        let amounts = Vec::<U256>::abi_decode(&res, false)?;
+        Ok(amounts)
+    }
+
+    fn swap_exact_eth_for_tokens(
+        &self,
+        min_amount_out: U256,
+        path: Vec<Address>,
+        to: Address,
+        deadline: U256,
+        fee_on_transfer: bool,
+    ) -> Vec<u8> {
+        let routes = self.routes(&path);
+        if fee_on_transfer {
+            IVelodromeRouter::swapExactETHForTokensSupportingFeeOnTransferTokensCall {
+                amountOutMin: min_amount_out,
+                routes,
+                to,
+                deadline,
+            }
+            .abi_encode()
+        } else {
+            IVelodromeRouter::swapExactETHForTokensCall {
+                amountOutMin: min_amount_out,
+                routes,
+                to,
+                deadline,
+            }
+            .abi_encode()
+        }
+    }
+
+    fn swap_exact_tokens_for_tokens(
+        &self,
+        amount_in: U256,
+        min_amount_out: U256,
+        path: Vec<Address>,
+        to: Address,
+        deadline: U256,
+        fee_on_transfer: bool,
+    ) -> Vec<u8> {
+        let routes = self.routes(&path);
+        if fee_on_transfer {
+            IVelodromeRouter::swapExactTokensForTokensSupportingFeeOnTransferTokensCall {
+                amountIn: amount_in,
+                amountOutMin: min_amount_out,
+                routes,
+                to,
+                deadline,
+            }
+            .abi_encode()
+        } else {
+            IVelodromeRouter::swapExactTokensForTokensCall {
+                amountIn: amount_in,
+                amountOutMin: min_amount_out,
+                routes,
+                to,
+                deadline,
+            }
+            .abi_encode()
+        }
+    }
+
+    fn swap_exact_tokens_for_eth(
+        &self,
+        amount_in: U256,
+        min_amount_out: U256,
+        path: Vec<Address>,
+        to: Address,
+        deadline: U256,
+        fee_on_transfer: bool,
+    ) -> Vec<u8> {
+        let routes = self.routes(&path);
+        if fee_on_transfer {
+            IVelodromeRouter::swapExactTokensForETHSupportingFeeOnTransferTokensCall {
+                amountIn: amount_in,
+                amountOutMin: min_amount_out,
+                routes,
+                to,
+                deadline,
+            }
+            .abi_encode()
+        } else {
+            IVelodromeRouter::swapExactTokensForETHCall {
+                amountIn: amount_in,
+                amountOutMin: min_amount_out,
+                routes,
+                to,
+                deadline,
+            }
+            .abi_encode()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum DexKind {
+    UniswapV2,
+    Velodrome { stable: bool },
+}
+
+/// how a dex is registered for a chain, before it's been checked on-chain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DexConfig {
+    pub name: String,
+    pub kind: DexKind,
+    pub factory: Address,
+    pub router: Address,
+}
+
+impl DexConfig {
+    pub fn build(&self, chain_id: u64) -> Box<dyn Dex> {
+        match self.kind {
+            DexKind::UniswapV2 => Box::new(UniswapV2 {
+                name: self.name.clone(),
+                chain_id,
+                factory: self.factory,
+                router: self.router,
+            }),
+            DexKind::Velodrome { stable } => Box::new(Velodrome {
+                name: self.name.clone(),
+                chain_id,
+                factory: self.factory,
+                router: self.router,
+                stable,
+            }),
+        }
+    }
+}
+
+/// verified dexes, loaded per chain the first time it's used.
+#[derive(Default)]
+pub struct DexRegistry {
+    chains: HashMap<u64, Vec<Box<dyn Dex>>>,
+}
+
+impl DexRegistry {
+    /// builds every dex configured for the chain and keeps the ones that verify.
+    pub fn load(&mut self, chain: &ChainConfig) -> anyhow::Result<()> {
+        let chain_id = chain.chain_id;
+
+        let mut dexes = vec![];
+        for config in &chain.dexes {
+            let dex = config.build(chain_id);
+            match dex.verify(chain.weth) {
+                Ok(()) => dexes.push(dex),
+                Err(e) => println!("skipping {} on chain {}: {:?}", config.name, chain_id, e),
+            }
+        }
+        if dexes.is_empty() && !chain.dexes.is_empty() {
+            return Err(anyhow::anyhow!(
+                "no dex verified for chain_id: {:?}",
+                chain_id
+            ));
+        }
+
+        println!(
+            "loaded dexes for chain {}: {:?}",
+            chain_id,
+            dexes.iter().map(|d| d.name()).collect::<Vec<_>>()
+        );
+        self.chains.insert(chain_id, dexes);
+        Ok(())
+    }
+
+    pub fn get(&mut self, chain: &ChainConfig) -> anyhow::Result<&[Box<dyn Dex>]> {
+        if !self.chains.contains_key(&chain.chain_id) {
+            self.load(chain)?;
+        }
+        Ok(self.chains[&chain.chain_id].as_slice())
+    }
+
+    /// forget a chain's dexes, e.g. after its config changed.
+    pub fn unload(&mut self, chain_id: u64) {
+        self.chains.remove(&chain_id);
+    }
+}
+
+pub fn dex_by_name<'a>(dexes: &'a [Box<dyn Dex>], name: &str) -> anyhow::Result<&'a dyn Dex> {
+    dexes
+        .iter()
+        .find(|d| d.name() == name)
+        .map(|d| d.as_ref())
+        .ok_or_else(|| anyhow::anyhow!("dex {} not loaded", name))
+}
@@ -0,0 +1,96 @@
+use alloy_consensus::{TxKind, TxLegacy};
+use alloy_signer::Transaction;
+use alloy_sol_types::SolCall;
+use kinode_process_lib::{
+    eth::{Address, TransactionInput, TransactionRequest, U256},
+    println,
+};
+
+use crate::helpers::{chains::ChainConfig, contracts::IGasPriceOracle};
+
+// swaps are signed at this multiple of the node's gas price so they still land if it climbs.
+const SWAP_GAS_PRICE_MULTIPLIER: u64 = 8;
+
+/// what swaps are signed with, and so pay per unit of gas, given the node's gas price.
+pub fn swap_gas_price(gas_price: U256) -> U256 {
+    gas_price * U256::from(SWAP_GAS_PRICE_MULTIPLIER)
+}
+
+/// what a tx costs to land, in wei.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TxCost {
+    /// gas_limit * gas_price, the most execution can cost.
+    pub l2_fee: U256,
+    /// the L1 data fee on OP stack chains, zero everywhere else.
+    pub l1_fee: U256,
+}
+
+impl TxCost {
+    pub fn total(&self) -> U256 {
+        self.l2_fee + self.l1_fee
+    }
+}
+
+/// asks the chain's GasPriceOracle what posting this tx to L1 costs.
+/// we pass the unsigned encoding, the oracle pads for the signature itself.
+pub fn get_l1_fee(chain: &ChainConfig, tx: &TxLegacy) -> anyhow::Result<U256> {
+    let Some(oracle) = chain.l1_fee_oracle else {
+        return Ok(U256::ZERO);
+    };
+
+    let fee_call = IGasPriceOracle::getL1FeeCall {
+        _data: tx.encoded_for_signing().into(),
+    }
+    .abi_encode();
+    let req = TransactionRequest {
+        to: Some(oracle),
+        input: TransactionInput::new(fee_call.into()),
+        ..Default::default()
+    };
+    let res = chain.provider().call(req, None)?;
+    let fee = IGasPriceOracle::getL1FeeCall::abi_decode_returns(&res, false)?;
+
+    Ok(fee._0)
+}
+
+/// the L1 fee of calling `to` with input, for comparing venues before a tx exists.
+/// nonce and gas are placeholders, only the size and content of the calldata matter.
+pub fn l1_fee_for_input(
+    chain: &ChainConfig,
+    to: Address,
+    value: U256,
+    input: Vec<u8>,
+) -> anyhow::Result<U256> {
+    if chain.l1_fee_oracle.is_none() {
+        return Ok(U256::ZERO);
+    }
+    let tx = TxLegacy {
+        nonce: 0,
+        gas_price: 0,
+        gas_limit: 0,
+        to: TxKind::Call(to),
+        value,
+        input: input.into(),
+        chain_id: Some(chain.chain_id),
+    };
+    get_l1_fee(chain, &tx)
+}
+
+/// l1_fee_for_input for comparing routes: an oracle that can't be reached prices the L1 data
+/// at zero rather than failing the comparison.
+pub fn estimate_l1_fee(chain: &ChainConfig, to: Address, value: U256, input: Vec<u8>) -> U256 {
+    l1_fee_for_input(chain, to, value, input).unwrap_or_else(|e| {
+        println!(
+            "trader: couldn't get the L1 fee on chain {}, leaving it out: {:?}",
+            chain.chain_id, e
+        );
+        U256::ZERO
+    })
+}
+
+pub fn estimate_tx_cost(chain: &ChainConfig, tx: &TxLegacy) -> anyhow::Result<TxCost> {
+    Ok(TxCost {
+        l2_fee: U256::from(tx.gas_limit) * U256::from(tx.gas_price),
+        l1_fee: get_l1_fee(chain, tx)?,
+    })
+}
@@ -1,3 +1,9 @@
 pub mod calls;
+pub mod chains;
 pub mod contracts;
+pub mod dex;
 pub mod encryption;
+pub mod fees;
+pub mod router;
+pub mod tax;
+pub mod v3;
@@ -0,0 +1,354 @@
+use kinode_process_lib::eth::{Address, U256};
+
+use crate::helpers::{
+    chains::ChainConfig,
+    dex::Dex,
+    fees::{estimate_l1_fee, swap_gas_price},
+    v3::{find_best_v3_quote, V3Quote},
+};
+
+// rough gas figures for a V2 swap, each extra hop touches one more pair.
+pub const BASE_SWAP_GAS: u64 = 120_000;
+pub const GAS_PER_HOP: u64 = 60_000;
+
+// used when a request doesn't say how much slippage it tolerates.
+pub const DEFAULT_SLIPPAGE_BPS: u64 = 100;
+
+/// slippage a request gave, strictly between 0 and 100%.
+pub fn check_slippage(slippage_bps: u64) -> anyhow::Result<()> {
+    if slippage_bps == 0 || slippage_bps >= 10_000 {
+        return Err(anyhow::anyhow!(
+            "slippage_bps should be between 0 and 10000"
+        ));
+    }
+    Ok(())
+}
+
+/// slippage is given in basis points, 100 = 1%.
+pub fn with_slippage_max(amount: U256, slippage_bps: u64) -> U256 {
+    amount * U256::from(10_000 + slippage_bps) / U256::from(10_000)
+}
+
+pub fn with_slippage_min(amount: U256, slippage_bps: u64) -> U256 {
+    amount * U256::from(10_000u64.saturating_sub(slippage_bps)) / U256::from(10_000)
+}
+
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub dex: String,
+    pub path: Vec<Address>,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    pub gas_estimate: u64,
+    /// L1 data fee of the swap tx, zero off OP stack chains.
+    pub l1_fee: U256,
+}
+
+impl Route {
+    pub fn hops(&self) -> usize {
+        self.path.len() - 1
+    }
+
+    /// what landing the swap costs in wei at the node's gas_price, execution plus L1 data.
+    pub fn cost_wei(&self, gas_price: U256) -> U256 {
+        swap_gas_price(gas_price) * U256::from(self.gas_estimate) + self.l1_fee
+    }
+}
+
+/// all 1- and 2-hop paths from token_in to token_out that have existing pairs on this dex.
+pub fn candidate_paths(
+    chain: &ChainConfig,
+    dex: &dyn Dex,
+    token_in: Address,
+    token_out: Address,
+) -> anyhow::Result<Vec<Vec<Address>>> {
+    let mut paths = vec![];
+
+    if dex.get_pair(token_in, token_out)?.is_some() {
+        paths.push(vec![token_in, token_out]);
+    }
+
+    for &base in &chain.base_tokens {
+        if base == token_in || base == token_out {
+            continue;
+        }
+        if dex.get_pair(token_in, base)?.is_none() {
+            continue;
+        }
+        if dex.get_pair(base, token_out)?.is_none() {
+            continue;
+        }
+        paths.push(vec![token_in, base, token_out]);
+    }
+
+    Ok(paths)
+}
+
+/// L1 data fee of swapping along path on dex. every router variant encodes to about
+/// the same size, so the plain token swap stands in for all of them.
+pub fn route_l1_fee(
+    chain: &ChainConfig,
+    dex: &dyn Dex,
+    path: &[Address],
+    amount_in: U256,
+    amount_out: U256,
+) -> U256 {
+    if chain.l1_fee_oracle.is_none() {
+        return U256::ZERO;
+    }
+    // placeholders, any non-zero recipient and a deadline of a few bytes encode like ours.
+    let input = dex.swap_exact_tokens_for_tokens(
+        amount_in,
+        amount_out,
+        path.to_vec(),
+        Address::repeat_byte(0xff),
+        U256::from(u32::MAX),
+        false,
+    );
+    estimate_l1_fee(chain, dex.router(), U256::ZERO, input)
+}
+
+/// values gas_wei in units of token_out.
+/// uses the quoted rate of the swap itself when token_in is WETH, otherwise
+/// quotes the gas cost from WETH to token_out on the first dex that can.
+pub fn gas_in_token_out(
+    dexes: &[Box<dyn Dex>],
+    weth: Address,
+    token_in: Address,
+    token_out: Address,
+    amount_in: U256,
+    amount_out: U256,
+    gas_wei: U256,
+) -> U256 {
+    if token_in == weth {
+        if amount_in == U256::ZERO {
+            U256::ZERO
+        } else {
+            gas_wei * amount_out / amount_in
+        }
+    } else if token_out == weth {
+        gas_wei
+    } else {
+        // best effort, if we can't price the gas we just compare raw outputs.
+        dexes
+            .iter()
+            .find_map(|dex| {
+                dex.get_amounts_out(gas_wei, &[weth, token_out])
+                    .ok()
+                    .and_then(|a| a.last().cloned())
+            })
+            .unwrap_or(U256::ZERO)
+    }
+}
+
+/// quotes every candidate path on every dex and returns the one with the best output,
+/// after subtracting the cost of the gas the extra hops burn and their L1 data fee.
+pub fn find_best_route(
+    chain: &ChainConfig,
+    dexes: &[Box<dyn Dex>],
+    token_in: Address,
+    token_out: Address,
+    amount_in: U256,
+    gas_price: U256,
+) -> anyhow::Result<Route> {
+    let mut routes = vec![];
+    for dex in dexes {
+        for path in candidate_paths(chain, dex.as_ref(), token_in, token_out)? {
+            // a path can exist but still fail to quote, e.g. an empty pair.
+            let Ok(amounts) = dex.get_amounts_out(amount_in, &path) else {
+                continue;
+            };
+            let Some(amount_out) = amounts.last().cloned() else {
+                continue;
+            };
+            let gas_estimate = BASE_SWAP_GAS + GAS_PER_HOP * (path.len() as u64 - 2);
+            let l1_fee = route_l1_fee(chain, dex.as_ref(), &path, amount_in, amount_out);
+            routes.push(Route {
+                dex: dex.name().to_string(),
+                path,
+                amount_in,
+                amount_out,
+                gas_estimate,
+                l1_fee,
+            });
+        }
+    }
+
+    let mut best: Option<(Route, U256)> = None;
+    for route in routes {
+        let gas_wei = route.cost_wei(gas_price);
+        let gas_in_out = gas_in_token_out(
+            dexes,
+            chain.weth,
+            token_in,
+            token_out,
+            amount_in,
+            route.amount_out,
+            gas_wei,
+        );
+        let net = route.amount_out.saturating_sub(gas_in_out);
+
+        match &best {
+            Some((_, best_net)) if *best_net >= net => {}
+            _ => best = Some((route, net)),
+        }
+    }
+
+    best.map(|(route, _)| route)
+        .ok_or_else(|| anyhow::anyhow!("no route found from {:?} to {:?}", token_in, token_out))
+}
+
+/// for exact-output swaps: quotes every candidate path with getAmountsIn
+/// and returns the one that needs the least input for amount_out.
+/// dexes without exact-output support are skipped.
+pub fn find_cheapest_route(
+    chain: &ChainConfig,
+    dexes: &[Box<dyn Dex>],
+    token_in: Address,
+    token_out: Address,
+    amount_out: U256,
+) -> anyhow::Result<Route> {
+    let mut best: Option<Route> = None;
+    for dex in dexes {
+        for path in candidate_paths(chain, dex.as_ref(), token_in, token_out)? {
+            let Ok(amounts) = dex.get_amounts_in(amount_out, &path) else {
+                continue;
+            };
+            let Some(amount_in) = amounts.first().cloned() else {
+                continue;
+            };
+            let gas_estimate = BASE_SWAP_GAS + GAS_PER_HOP * (path.len() as u64 - 2);
+
+            match &best {
+                Some(b) if b.amount_in <= amount_in => {}
+                _ => {
+                    let l1_fee = route_l1_fee(chain, dex.as_ref(), &path, amount_in, amount_out);
+                    best = Some(Route {
+                        dex: dex.name().to_string(),
+                        path,
+                        amount_in,
+                        amount_out,
+                        gas_estimate,
+                        l1_fee,
+                    })
+                }
+            }
+        }
+    }
+
+    best.ok_or_else(|| {
+        anyhow::anyhow!(
+            "no route found from {:?} to {:?} for exact output",
+            token_in,
+            token_out
+        )
+    })
+}
+
+/// where a swap gets executed.
+#[derive(Debug, Clone)]
+pub enum Venue {
+    V2(Route),
+    V3(V3Quote),
+}
+
+impl Venue {
+    pub fn name(&self) -> &str {
+        match self {
+            Venue::V2(route) => &route.dex,
+            Venue::V3(_) => "uniswap-v3",
+        }
+    }
+
+    pub fn path(&self) -> &Vec<Address> {
+        match self {
+            Venue::V2(route) => &route.path,
+            Venue::V3(quote) => &quote.path,
+        }
+    }
+
+    pub fn amount_out(&self) -> U256 {
+        match self {
+            Venue::V2(route) => route.amount_out,
+            Venue::V3(quote) => quote.amount_out,
+        }
+    }
+
+    pub fn gas_estimate(&self) -> u64 {
+        match self {
+            Venue::V2(route) => route.gas_estimate,
+            Venue::V3(quote) => quote.gas_estimate,
+        }
+    }
+
+    pub fn l1_fee(&self) -> U256 {
+        match self {
+            Venue::V2(route) => route.l1_fee,
+            Venue::V3(quote) => quote.l1_fee,
+        }
+    }
+
+    /// what landing the swap costs in wei at the node's gas_price, execution plus L1 data.
+    pub fn cost_wei(&self, gas_price: U256) -> U256 {
+        swap_gas_price(gas_price) * U256::from(self.gas_estimate()) + self.l1_fee()
+    }
+}
+
+/// quotes the swap on the V2-style dexes and on V3, either side may come back empty.
+pub fn quote_venues(
+    chain: &ChainConfig,
+    dexes: &[Box<dyn Dex>],
+    token_in: Address,
+    token_out: Address,
+    amount_in: U256,
+    gas_price: U256,
+) -> anyhow::Result<Vec<Venue>> {
+    let mut venues = vec![];
+
+    if let Ok(route) = find_best_route(chain, dexes, token_in, token_out, amount_in, gas_price) {
+        venues.push(Venue::V2(route));
+    }
+    if let Ok(Some(quote)) = find_best_v3_quote(chain, token_in, token_out, amount_in) {
+        venues.push(Venue::V3(quote));
+    }
+
+    Ok(venues)
+}
+
+/// picks the venue with the best output net of gas.
+pub fn best_venue(
+    chain: &ChainConfig,
+    dexes: &[Box<dyn Dex>],
+    token_in: Address,
+    token_out: Address,
+    amount_in: U256,
+    gas_price: U256,
+) -> anyhow::Result<Venue> {
+    let venues = quote_venues(chain, dexes, token_in, token_out, amount_in, gas_price)?;
+    pick_best_venue(chain, dexes, venues, amount_in, gas_price)
+        .ok_or_else(|| anyhow::anyhow!("no venue can quote {:?} to {:?}", token_in, token_out))
+}
+
+/// the venue with the best output net of gas out of ones quote_venues already returned.
+pub fn pick_best_venue(
+    chain: &ChainConfig,
+    dexes: &[Box<dyn Dex>],
+    venues: Vec<Venue>,
+    amount_in: U256,
+    gas_price: U256,
+) -> Option<Venue> {
+    venues.into_iter().max_by_key(|venue| {
+        let path = venue.path();
+        let gas_wei = venue.cost_wei(gas_price);
+        let gas_in_out = gas_in_token_out(
+            dexes,
+            chain.weth,
+            path[0],
+            path[path.len() - 1],
+            amount_in,
+            venue.amount_out(),
+            gas_wei,
+        );
+        venue.amount_out().saturating_sub(gas_in_out)
+    })
+}
@@ -0,0 +1,320 @@
+use alloy_primitives::{keccak256, B256};
+use alloy_sol_types::SolCall;
+use kinode_process_lib::eth::{
+    Address, BlockId, BlockNumberOrTag, Bytes, EthAction, TransactionInput, TransactionRequest,
+    U256,
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::helpers::{
+    calls::{get_allowance, swap_deadline},
+    chains::eth_provider,
+    contracts::IERC20,
+    dex::Dex,
+};
+
+// solidity tokens keep their balance and allowance mappings in the first few slots.
+const MAX_SLOT: u64 = 20;
+
+/// what a simulated swap tells us about a token.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TaxCheck {
+    /// the plain router functions go through at the quoted output.
+    NoTax,
+    /// the token skims this many basis points on transfer.
+    Taxed(u64),
+    /// nothing goes through, not even with a zero minimum. likely a honeypot.
+    Untradeable,
+}
+
+impl TaxCheck {
+    pub fn fee_on_transfer(&self) -> bool {
+        matches!(self, TaxCheck::Taxed(_))
+    }
+
+    pub fn bps(&self) -> u64 {
+        match self {
+            TaxCheck::Taxed(bps) => *bps,
+            _ => 0,
+        }
+    }
+}
+
+/// eth_call a tx as if we sent it, without signing anything.
+pub fn simulate(
+    chain_id: u64,
+    from: Address,
+    to: Address,
+    value: U256,
+    input: Vec<u8>,
+) -> anyhow::Result<Bytes> {
+    let req = TransactionRequest {
+        from: Some(from),
+        to: Some(to),
+        value: Some(value),
+        input: TransactionInput::new(input.into()),
+        ..Default::default()
+    };
+    Ok(eth_provider(chain_id).call(req, None)?)
+}
+
+/// what eth_call pretends an account holds, see the state override set of eth_call.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AccountOverride {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    balance: Option<U256>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    state_diff: BTreeMap<B256, B256>,
+}
+
+/// simulate, with accounts made to hold what overrides says first. nothing has to be in the
+/// wallet, so this works for paper trading and before the wallet is funded.
+fn simulate_with(
+    chain_id: u64,
+    from: Address,
+    to: Address,
+    value: U256,
+    input: Vec<u8>,
+    overrides: &BTreeMap<Address, AccountOverride>,
+) -> anyhow::Result<Bytes> {
+    let req = TransactionRequest {
+        from: Some(from),
+        to: Some(to),
+        value: Some(value),
+        input: TransactionInput::new(input.into()),
+        ..Default::default()
+    };
+    // Provider::call has no room for overrides, this is the same request with them added.
+    let action = EthAction::Request {
+        chain_id,
+        method: "eth_call".to_string(),
+        params: serde_json::to_value((req, BlockId::Number(BlockNumberOrTag::Latest), overrides))?,
+    };
+    Ok(eth_provider(chain_id).send_request_and_parse_response::<Bytes>(action)?)
+}
+
+/// the storage key of `mapping(address => ...)` at slot for key.
+fn mapping_key(key: Address, slot: B256) -> B256 {
+    keccak256([key.into_word().as_slice(), slot.as_slice()].concat())
+}
+
+/// the storage key behind a uint256 getter of token, found by writing a marker under each
+/// candidate slot's key until the getter reads it back. None for layouts we don't know.
+fn find_storage_key(
+    chain_id: u64,
+    token: Address,
+    getter: Vec<u8>,
+    key: impl Fn(B256) -> B256,
+) -> anyhow::Result<Option<B256>> {
+    let marker = B256::from(U256::from(0x5eed_u64));
+    for slot in 0..MAX_SLOT {
+        let storage_key = key(B256::from(U256::from(slot)));
+        let overrides = BTreeMap::from([(
+            token,
+            AccountOverride {
+                state_diff: BTreeMap::from([(storage_key, marker)]),
+                ..Default::default()
+            },
+        )]);
+        let read = simulate_with(
+            chain_id,
+            Address::ZERO,
+            token,
+            U256::ZERO,
+            getter.clone(),
+            &overrides,
+        )?;
+        if read.as_ref() == marker.as_slice() {
+            return Ok(Some(storage_key));
+        }
+    }
+    Ok(None)
+}
+
+/// the fee-on-transfer router variants check the actual balance change against
+/// amountOutMin, so we can binary search the minimum to find what really arrives.
+fn search_tax(quoted_out: U256, passes: impl Fn(U256) -> bool) -> TaxCheck {
+    if !passes(U256::ZERO) {
+        return TaxCheck::Untradeable;
+    }
+    if passes(quoted_out) {
+        return TaxCheck::NoTax;
+    }
+
+    // lowest tax (in bps) at which the swap still goes through.
+    let (mut lo, mut hi) = (0u64, 10_000u64);
+    while hi - lo > 1 {
+        let mid = (lo + hi) / 2;
+        let min_out = quoted_out * U256::from(10_000 - mid) / U256::from(10_000);
+        if passes(min_out) {
+            hi = mid;
+        } else {
+            lo = mid;
+        }
+    }
+    TaxCheck::Taxed(hi)
+}
+
+/// checks an ETH -> token buy for a transfer tax. from is given amount_in to spend.
+pub fn check_buy_tax(
+    from: Address,
+    dex: &dyn Dex,
+    amount_in: U256,
+    path: Vec<Address>,
+    quoted_out: U256,
+) -> anyhow::Result<TaxCheck> {
+    let deadline = U256::from(swap_deadline()?);
+    let overrides = BTreeMap::from([(
+        from,
+        AccountOverride {
+            balance: Some(amount_in),
+            ..Default::default()
+        },
+    )]);
+    let passes = |input: Vec<u8>| {
+        simulate_with(
+            dex.chain_id(),
+            from,
+            dex.router(),
+            amount_in,
+            input,
+            &overrides,
+        )
+        .is_ok()
+    };
+
+    // if the plain swap simulates fine there's nothing to look for.
+    if passes(dex.swap_exact_eth_for_tokens(quoted_out, path.clone(), from, deadline, false)) {
+        return Ok(TaxCheck::NoTax);
+    }
+
+    Ok(search_tax(quoted_out, |min_out| {
+        passes(dex.swap_exact_eth_for_tokens(min_out, path.clone(), from, deadline, true))
+    }))
+}
+
+/// checks a sell of amount_in along path for a transfer tax, simulating the call that gets sent:
+/// swapExactTokensForETH when to_eth, swapExactTokensForTokens otherwise. from is given the
+/// tokens and the router's allowance, so nothing has to be bought or approved first. tokens whose
+/// storage we can't find are simulated with what from really holds and allowed, None if that
+/// allowance isn't there yet.
+pub fn check_sell_tax(
+    from: Address,
+    dex: &dyn Dex,
+    amount_in: U256,
+    path: Vec<Address>,
+    quoted_out: U256,
+    to_eth: bool,
+) -> anyhow::Result<Option<TaxCheck>> {
+    let chain_id = dex.chain_id();
+    let token = path[0];
+    let balance_key = find_storage_key(
+        chain_id,
+        token,
+        IERC20::balanceOfCall { owner: from }.abi_encode(),
+        |slot| mapping_key(from, slot),
+    )?;
+    let allowance_key = find_storage_key(
+        chain_id,
+        token,
+        IERC20::allowanceCall {
+            owner: from,
+            spender: dex.router(),
+        }
+        .abi_encode(),
+        |slot| mapping_key(dex.router(), mapping_key(from, slot)),
+    )?;
+    let overrides = match (balance_key, allowance_key) {
+        (Some(balance_key), Some(allowance_key)) => {
+            let amount = B256::from(amount_in);
+            BTreeMap::from([(
+                token,
+                AccountOverride {
+                    state_diff: BTreeMap::from([(balance_key, amount), (allowance_key, amount)]),
+                    ..Default::default()
+                },
+            )])
+        }
+        _ if get_allowance(chain_id, token, from, dex.router())? >= amount_in => BTreeMap::new(),
+        _ => return Ok(None),
+    };
+
+    let deadline = U256::from(swap_deadline()?);
+    let swap = |min_out: U256, fee_on_transfer: bool| {
+        let path = path.clone();
+        if to_eth {
+            dex.swap_exact_tokens_for_eth(amount_in, min_out, path, from, deadline, fee_on_transfer)
+        } else {
+            dex.swap_exact_tokens_for_tokens(
+                amount_in,
+                min_out,
+                path,
+                from,
+                deadline,
+                fee_on_transfer,
+            )
+        }
+    };
+    let passes = |input: Vec<u8>| {
+        simulate_with(chain_id, from, dex.router(), U256::ZERO, input, &overrides).is_ok()
+    };
+
+    // if the plain swap simulates fine there's nothing to look for.
+    if passes(swap(quoted_out, false)) {
+        return Ok(Some(TaxCheck::NoTax));
+    }
+
+    Ok(Some(search_tax(quoted_out, |min_out| {
+        passes(swap(min_out, true))
+    })))
+}
+
+/// why a buy of amount_in wei along path shouldn't go ahead: it or selling the tokens back
+/// doesn't go through, or either side is taxed past max_tax_bps. None if it looks tradeable.
+pub fn check_round_trip(
+    from: Address,
+    dex: &dyn Dex,
+    amount_in: U256,
+    path: Vec<Address>,
+    quoted_out: U256,
+    max_tax_bps: u64,
+) -> anyhow::Result<Option<String>> {
+    let over = |side: &str, bps: u64| {
+        format!(
+            "{} tax {}% is above {}%",
+            side,
+            bps as f64 / 100.0,
+            max_tax_bps as f64 / 100.0
+        )
+    };
+
+    let buy = check_buy_tax(from, dex, amount_in, path.clone(), quoted_out)?;
+    match buy {
+        TaxCheck::Untradeable => {
+            return Ok(Some(
+                "the buy doesn't go through, likely a honeypot".to_string(),
+            ))
+        }
+        TaxCheck::Taxed(bps) if bps > max_tax_bps => return Ok(Some(over("buy", bps))),
+        TaxCheck::Taxed(_) | TaxCheck::NoTax => {}
+    }
+
+    // what the buy delivers, sold straight back.
+    let received = quoted_out * U256::from(10_000 - buy.bps()) / U256::from(10_000);
+    let back: Vec<Address> = path.into_iter().rev().collect();
+    let sell_quote = *dex
+        .get_amounts_out(received, &back)?
+        .last()
+        .ok_or_else(|| anyhow::anyhow!("empty quote from {}", dex.name()))?;
+    let sell = check_sell_tax(from, dex, received, back, sell_quote, true)?;
+    Ok(match sell {
+        None => Some("can't simulate selling it, its storage layout is unknown".to_string()),
+        Some(TaxCheck::Untradeable) => {
+            Some("selling it back doesn't go through, likely a honeypot".to_string())
+        }
+        Some(TaxCheck::Taxed(bps)) if bps > max_tax_bps => Some(over("sell", bps)),
+        Some(TaxCheck::Taxed(_) | TaxCheck::NoTax) => None,
+    })
+}
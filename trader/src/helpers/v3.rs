@@ -0,0 +1,282 @@
+use alloy_consensus::{TxKind, TxLegacy};
+use alloy_primitives::U160;
+use alloy_sol_types::{SolCall, SolValue};
+use kinode_process_lib::eth::{Address, TransactionInput, TransactionRequest, U256};
+
+use crate::helpers::{
+    calls::pending_nonce,
+    chains::{eth_provider, ChainConfig},
+    contracts::{IQuoterV2, ISwapRouter02, IUniswapV3Factory},
+    fees::{estimate_l1_fee, swap_gas_price},
+};
+
+// 0.01%, 0.05%, 0.3% and 1% pools.
+pub const FEE_TIERS: [u32; 4] = [100, 500, 3000, 10000];
+
+#[derive(Debug, Clone)]
+pub struct V3Quote {
+    pub path: Vec<Address>,
+    pub fees: Vec<u32>,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    pub gas_estimate: u64,
+    /// L1 data fee of the swap tx, zero off OP stack chains.
+    pub l1_fee: U256,
+}
+
+impl V3Quote {
+    pub fn hops(&self) -> usize {
+        self.fees.len()
+    }
+}
+
+pub fn get_pool(
+    chain_id: u64,
+    factory: Address,
+    token_a: Address,
+    token_b: Address,
+    fee: u32,
+) -> anyhow::Result<Option<Address>> {
+    let pool_call = IUniswapV3Factory::getPoolCall {
+        tokenA: token_a,
+        tokenB: token_b,
+        fee,
+    }
+    .abi_encode();
+    let req = TransactionRequest {
+        to: Some(factory),
+        input: TransactionInput::new(pool_call.into()),
+        ..Default::default()
+    };
+    let res = eth_provider(chain_id).call(req, None)?;
+    let pool = Address::abi_decode(&res, false)?;
+
+    if pool == Address::ZERO {
+        Ok(None)
+    } else {
+        Ok(Some(pool))
+    }
+}
+
+/// V3 paths are packed as token (20 bytes), fee (3 bytes), token, fee, token...
+pub fn encode_path(tokens: &[Address], fees: &[u32]) -> Vec<u8> {
+    let mut path = vec![];
+    for (i, token) in tokens.iter().enumerate() {
+        path.extend_from_slice(token.as_slice());
+        if let Some(fee) = fees.get(i) {
+            path.extend_from_slice(&fee.to_be_bytes()[1..]);
+        }
+    }
+    path
+}
+
+/// QuoterV2 isn't a view function, it simulates the swap and reverts with the result,
+/// but an eth_call gets us the decoded return all the same.
+pub fn quote_exact_input_single(
+    chain_id: u64,
+    quoter: Address,
+    token_in: Address,
+    token_out: Address,
+    fee: u32,
+    amount_in: U256,
+) -> anyhow::Result<(U256, u64)> {
+    let quote_call = IQuoterV2::quoteExactInputSingleCall {
+        params: IQuoterV2::QuoteExactInputSingleParams {
+            tokenIn: token_in,
+            tokenOut: token_out,
+            amountIn: amount_in,
+            fee,
+            sqrtPriceLimitX96: U160::ZERO,
+        },
+    }
+    .abi_encode();
+    let req = TransactionRequest {
+        to: Some(quoter),
+        input: TransactionInput::new(quote_call.into()),
+        ..Default::default()
+    };
+    let res = eth_provider(chain_id).call(req, None)?;
+    let quote = IQuoterV2::quoteExactInputSingleCall::abi_decode_returns(&res, false)?;
+
+    Ok((quote.amountOut, quote.gasEstimate.to::<u64>()))
+}
+
+pub fn quote_exact_input(
+    chain_id: u64,
+    quoter: Address,
+    tokens: &[Address],
+    fees: &[u32],
+    amount_in: U256,
+) -> anyhow::Result<(U256, u64)> {
+    let quote_call = IQuoterV2::quoteExactInputCall {
+        path: encode_path(tokens, fees).into(),
+        amountIn: amount_in,
+    }
+    .abi_encode();
+    let req = TransactionRequest {
+        to: Some(quoter),
+        input: TransactionInput::new(quote_call.into()),
+        ..Default::default()
+    };
+    let res = eth_provider(chain_id).call(req, None)?;
+    let quote = IQuoterV2::quoteExactInputCall::abi_decode_returns(&res, false)?;
+
+    Ok((quote.amountOut, quote.gasEstimate.to::<u64>()))
+}
+
+/// best single-pool quote across the fee tiers that have a pool.
+fn best_single(
+    chain_id: u64,
+    factory: Address,
+    quoter: Address,
+    token_in: Address,
+    token_out: Address,
+    amount_in: U256,
+) -> anyhow::Result<Option<(u32, U256, u64)>> {
+    let mut best: Option<(u32, U256, u64)> = None;
+    for fee in FEE_TIERS {
+        if get_pool(chain_id, factory, token_in, token_out, fee)?.is_none() {
+            continue;
+        }
+        let Ok((amount_out, gas)) =
+            quote_exact_input_single(chain_id, quoter, token_in, token_out, fee, amount_in)
+        else {
+            continue;
+        };
+        match best {
+            Some((_, best_out, _)) if best_out >= amount_out => {}
+            _ => best = Some((fee, amount_out, gas)),
+        }
+    }
+    Ok(best)
+}
+
+/// quotes direct pools on every fee tier, and 2-hop paths through the base tokens.
+/// for 2 hops each leg greedily takes its best tier, then the full path is re-quoted.
+/// chains without v3 configured just get no quote.
+pub fn find_best_v3_quote(
+    chain: &ChainConfig,
+    token_in: Address,
+    token_out: Address,
+    amount_in: U256,
+) -> anyhow::Result<Option<V3Quote>> {
+    let Some(v3) = &chain.v3 else {
+        return Ok(None);
+    };
+    let (factory, quoter) = (v3.factory, v3.quoter);
+    let mut quotes = vec![];
+
+    if let Some((fee, amount_out, gas_estimate)) = best_single(
+        chain.chain_id,
+        factory,
+        quoter,
+        token_in,
+        token_out,
+        amount_in,
+    )? {
+        quotes.push(V3Quote {
+            path: vec![token_in, token_out],
+            fees: vec![fee],
+            amount_in,
+            amount_out,
+            gas_estimate,
+            l1_fee: U256::ZERO,
+        });
+    }
+
+    for &base in &chain.base_tokens {
+        if base == token_in || base == token_out {
+            continue;
+        }
+        let Some((fee0, mid_out, _)) =
+            best_single(chain.chain_id, factory, quoter, token_in, base, amount_in)?
+        else {
+            continue;
+        };
+        let Some((fee1, _, _)) =
+            best_single(chain.chain_id, factory, quoter, base, token_out, mid_out)?
+        else {
+            continue;
+        };
+        let path = vec![token_in, base, token_out];
+        let fees = vec![fee0, fee1];
+        let Ok((amount_out, gas_estimate)) =
+            quote_exact_input(chain.chain_id, quoter, &path, &fees, amount_in)
+        else {
+            continue;
+        };
+        quotes.push(V3Quote {
+            path,
+            fees,
+            amount_in,
+            amount_out,
+            gas_estimate,
+            l1_fee: U256::ZERO,
+        });
+    }
+
+    let Some(mut best) = quotes.into_iter().max_by_key(|q| q.amount_out) else {
+        return Ok(None);
+    };
+    // any non-zero recipient encodes the same as ours would.
+    let input = v3_swap_calldata(Address::repeat_byte(0xff), &best, best.amount_out);
+    best.l1_fee = estimate_l1_fee(chain, v3.router, amount_in, input);
+    Ok(Some(best))
+}
+
+pub fn v3_swap_calldata(recipient: Address, quote: &V3Quote, min_amount_out: U256) -> Vec<u8> {
+    if quote.hops() == 1 {
+        ISwapRouter02::exactInputSingleCall {
+            params: ISwapRouter02::ExactInputSingleParams {
+                tokenIn: quote.path[0],
+                tokenOut: quote.path[1],
+                fee: quote.fees[0],
+                recipient,
+                amountIn: quote.amount_in,
+                amountOutMinimum: min_amount_out,
+                sqrtPriceLimitX96: U160::ZERO,
+            },
+        }
+        .abi_encode()
+    } else {
+        ISwapRouter02::exactInputCall {
+            params: ISwapRouter02::ExactInputParams {
+                path: encode_path(&quote.path, &quote.fees).into(),
+                recipient,
+                amountIn: quote.amount_in,
+                amountOutMinimum: min_amount_out,
+            },
+        }
+        .abi_encode()
+    }
+}
+
+/// builds a SwapRouter02 swap. value is the ETH to attach, the router wraps it
+/// itself when path[0] is WETH.
+pub fn send_v3_swap_request(
+    from: Address,
+    chain_id: u64,
+    router_address: Address,
+    quote: &V3Quote,
+    min_amount_out: U256,
+    value: U256,
+) -> anyhow::Result<TxLegacy> {
+    let swap_call = v3_swap_calldata(from, quote, min_amount_out);
+
+    let provider = eth_provider(chain_id);
+    let gas_price = provider.get_gas_price()?;
+    let nonce = pending_nonce(&provider, from)?;
+
+    let tx = TxLegacy {
+        nonce: nonce.to::<u64>(),
+        gas_price: swap_gas_price(gas_price).to::<u128>(),
+        // quoter gas is for the pool math only, leave room for transfers and wrapping.
+        gas_limit: quote.gas_estimate + 100000,
+        to: TxKind::Call(router_address),
+        value,
+        input: swap_call.into(),
+        chain_id: Some(chain_id),
+    };
+
+    Ok(tx)
+}
@@ -1,26 +1,77 @@
 use alloy_consensus::TxKind;
-use alloy_sol_types::{SolCall, SolValue};
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 
 use kinode_process_lib::{
     await_message, call_init,
-    eth::{
-        call, get_balance, get_block_number, get_chain_id, get_gas_price, get_transaction_count,
-        send_raw_transaction, Address as EthAddress, Bytes, TransactionInput, TransactionRequest,
-        U256,
-    },
-    get_state, println, set_state, Address, Message,
+    eth::{Address as EthAddress, Bytes, U256},
+    println, Address, Message,
 };
 
-use alloy_signer::{k256::ecdsa::SigningKey, LocalWallet, Signer, SignerSync, Transaction, Wallet};
+use alloy_signer::{k256::ecdsa::SigningKey, LocalWallet, Signer, Wallet};
 
+mod alerts;
+mod backtest;
+mod candles;
+mod chart;
+mod copytrade;
+mod dca;
+mod feed;
+mod grid;
 mod helpers;
+mod orders;
+mod paper;
+mod positions;
+mod risk;
+mod sniper;
+mod state;
+mod strategy;
+mod subscriptions;
+mod timers;
+mod trade;
+mod twap;
+mod watcher;
+use crate::alerts::{check_alerts, parse_target, Alert, AlertCondition};
+use crate::backtest::{run_backtest, run_recording, BacktestSubject, Dataset, Recording};
+use crate::candles::{Interval, DEFAULT_BACKFILL_BLOCKS};
+use crate::chart::{fill_gaps, parse_range, print_chart, sample_candles, ChartStyle, MAX_COLUMNS};
+use crate::copytrade::{
+    on_follow_log, subscribe_follow, subscribe_follows, unsubscribe_follow, Follow,
+};
+use crate::dca::{run_dca, DcaSchedule, DcaStatus};
+use crate::grid::{check_grids, Grid, GridStatus};
 use crate::helpers::{
-    calls::{get_erc20_info, get_token_price, send_swap_call_request},
-    contracts::{IUniswapV2Factory, FACTORY, ROUTER, WETH},
+    calls::{
+        get_erc20_balance, get_erc20_info, get_token_price, pending_nonce,
+        send_eth_for_exact_tokens_request, send_tokens_for_exact_eth_request,
+        send_tokens_for_tokens_request, send_unwrap_request, send_wrap_request, to_units,
+        v2_amount_out,
+    },
+    chains::{eth_provider, load_package_chains, ChainConfig},
+    dex::{dex_by_name, DexRegistry},
     encryption::{decrypt_data, encrypt_data},
+    fees::{estimate_tx_cost, TxCost},
+    router::{
+        best_venue, check_slippage, find_cheapest_route, pick_best_venue, quote_venues,
+        with_slippage_max, with_slippage_min, Venue, DEFAULT_SLIPPAGE_BPS,
+    },
+    tax::{check_sell_tax, TaxCheck},
+    v3::send_v3_swap_request,
 };
+use crate::orders::{check_orders, now_secs, LimitOrder, OrderStatus, Side};
+use crate::positions::{check_positions, Exits, Position, PositionStatus};
+use crate::risk::RiskLimits;
+use crate::sniper::{
+    check_snipes, on_pair_created, subscribe_pairs, unsubscribe_pairs, SnipeStatus, SniperConfig,
+    DEFAULT_MAX_TAX_BPS, DEFAULT_MAX_WAIT_BLOCKS,
+};
+use crate::state::State;
+use crate::strategy::{add_strategy, rules::Rule, run_strategies, StrategyKind, StrategySpec};
+use crate::subscriptions::{is_eth, Feed, Received, Subscriptions};
+use crate::timers::{is_timer, schedule, TimerContext, WATCH_TICK_MS};
+use crate::trade::{ensure_allowance, execute_buy, sign_and_send, Account};
+use crate::twap::{run_twap, Twap, TwapStatus, DEFAULT_TWAP_BOUND_PCT};
+use crate::watcher::{WatchEntry, Watcher};
 
 wit_bindgen::generate!({
     path: "wit",
@@ -30,155 +81,2409 @@ wit_bindgen::generate!({
     },
 });
 
+/// every request can name the chain it's for, otherwise the default chain is used.
+/// requests that set up trades can set paper to trade the paper book instead of the wallet.
 #[derive(Debug, Serialize, Deserialize)]
 enum TradeRequest {
-    Buy,
-    Info,
-    Send { amount: u64, to: String },
+    /// interactive buy. the bought amount is kept as a position, sold automatically
+    /// if one of the exits triggers.
+    Buy {
+        #[serde(default)]
+        chain_id: Option<u64>,
+        #[serde(default)]
+        exits: Exits,
+        #[serde(default)]
+        paper: bool,
+    },
+    /// without a chain_id, shows balances on every configured chain.
+    Info {
+        #[serde(default)]
+        chain_id: Option<u64>,
+    },
+    Send {
+        amount: u64,
+        to: String,
+        #[serde(default)]
+        chain_id: Option<u64>,
+    },
+    /// send the whole ETH balance, less what the tx itself costs.
+    SendMax {
+        to: String,
+        #[serde(default)]
+        chain_id: Option<u64>,
+    },
+    /// buy exactly amount_out of token (in its smallest unit), paying at most the quote plus
+    /// slippage in ETH.
+    BuyExact {
+        token: String,
+        amount_out: String,
+        slippage_bps: u64,
+        #[serde(default)]
+        chain_id: Option<u64>,
+    },
+    /// sell just enough of token to receive exactly amount_out wei.
+    SellExact {
+        token: String,
+        amount_out: String,
+        slippage_bps: u64,
+        #[serde(default)]
+        chain_id: Option<u64>,
+    },
+    /// compare what amount wei of ETH buys on each venue. watched tokens are quoted from their
+    /// live reserves instead, the venues only while the pair's subscription is down.
+    Quote {
+        token: String,
+        amount: u64,
+        #[serde(default)]
+        chain_id: Option<u64>,
+    },
+    /// token to token, routed through WETH or another base token if that's better, on whichever
+    /// of the V2 dexes and V3 returns the most net of gas.
+    Swap {
+        from_token: String,
+        to_token: String,
+        amount: String,
+        #[serde(default)]
+        chain_id: Option<u64>,
+    },
+    /// wrap amount wei of ETH into WETH.
+    Wrap {
+        amount: String,
+        #[serde(default)]
+        chain_id: Option<u64>,
+    },
+    /// unwrap amount wei of WETH back into ETH.
+    Unwrap {
+        amount: String,
+        #[serde(default)]
+        chain_id: Option<u64>,
+    },
+    /// buy or sell token once its price in ETH crosses limit_price (ETH per whole token).
+    /// buys spend amount wei, sells sell amount of the token. expiry is in unix seconds.
+    PlaceLimitOrder {
+        token: String,
+        side: Side,
+        amount: String,
+        limit_price: f64,
+        #[serde(default)]
+        expiry: Option<u64>,
+        #[serde(default)]
+        chain_id: Option<u64>,
+        #[serde(default)]
+        paper: bool,
+    },
+    ListOrders,
+    CancelOrder {
+        id: u64,
+    },
+    ListPositions,
+    /// replace a position's exits, relative to its entry price.
+    SetExits {
+        id: u64,
+        exits: Exits,
+    },
+    /// stop tracking a position without selling it.
+    DismissPosition {
+        id: u64,
+    },
+    /// buy amount_per_buy wei of token every interval seconds until total_budget is spent.
+    /// ticks are skipped while the price is above max_price (ETH per whole token)
+    /// or gas is above max_gas_price (wei).
+    CreateDca {
+        token: String,
+        amount_per_buy: String,
+        interval: u64,
+        total_budget: String,
+        #[serde(default)]
+        max_price: Option<f64>,
+        #[serde(default)]
+        max_gas_price: Option<String>,
+        #[serde(default)]
+        chain_id: Option<u64>,
+        #[serde(default)]
+        paper: bool,
+    },
+    /// all schedules, or just one.
+    DcaStatus {
+        #[serde(default)]
+        id: Option<u64>,
+    },
+    PauseDca {
+        id: u64,
+    },
+    ResumeDca {
+        id: u64,
+    },
+    CancelDca {
+        id: u64,
+    },
+    /// buy with total_amount wei of ETH in slices, one every interval seconds.
+    /// stops if the price runs more than bound_pct above the price at arrival.
+    TwapOrder {
+        token: String,
+        total_amount: String,
+        slices: u64,
+        interval: u64,
+        #[serde(default)]
+        bound_pct: Option<f64>,
+        #[serde(default)]
+        chain_id: Option<u64>,
+        #[serde(default)]
+        paper: bool,
+    },
+    TwapStatus {
+        #[serde(default)]
+        id: Option<u64>,
+    },
+    CancelTwap {
+        id: u64,
+    },
+    /// a grid on token/WETH: levels evenly spaced from lower to upper (ETH per whole token),
+    /// each band between two levels buys with capital_per_level wei and sells a level up.
+    CreateGrid {
+        token: String,
+        lower: f64,
+        upper: f64,
+        levels: u64,
+        capital_per_level: String,
+        #[serde(default)]
+        chain_id: Option<u64>,
+        #[serde(default)]
+        paper: bool,
+    },
+    GridStatus {
+        #[serde(default)]
+        id: Option<u64>,
+    },
+    /// stops trading, whatever the bands hold stays in the wallet.
+    StopGrid {
+        id: u64,
+    },
+    /// create a strategy instance and start running it on every poll.
+    CreateStrategy {
+        strategy: StrategySpec,
+        #[serde(default)]
+        chain_id: Option<u64>,
+        #[serde(default)]
+        paper: bool,
+    },
+    StartStrategy {
+        id: u64,
+    },
+    StopStrategy {
+        id: u64,
+    },
+    /// change a strategy's parameters, params depend on the strategy.
+    ConfigureStrategy {
+        id: u64,
+        params: serde_json::Value,
+    },
+    ListStrategies,
+    /// parse a rule like `when price(PEPE/WETH) < 0.0000012 and gas < 0.05 gwei
+    /// then buy 0.1 eth of PEPE slippage 2%` and check it every poll, see strategy::rules.
+    CreateRule {
+        rule: String,
+        #[serde(default)]
+        chain_id: Option<u64>,
+        #[serde(default)]
+        paper: bool,
+    },
+    ListRules,
+    DisableRule {
+        id: u64,
+    },
+    /// buy amount wei of every new token that gets a WETH pair on one of the chain's V2 factories,
+    /// once the pair holds min_liquidity wei of WETH and a simulated buy and sell back go
+    /// through, neither taxed above max_tax_bps. each buy opens a position with exits.
+    StartSniper {
+        amount: String,
+        min_liquidity: String,
+        #[serde(default)]
+        max_wait_blocks: Option<u64>,
+        #[serde(default)]
+        max_tax_bps: Option<u64>,
+        #[serde(default)]
+        slippage_bps: Option<u64>,
+        #[serde(default)]
+        exits: Exits,
+        #[serde(default)]
+        chain_id: Option<u64>,
+        #[serde(default)]
+        paper: bool,
+    },
+    StopSniper,
+    SniperStatus,
+    /// copy the V2 router swaps address makes against WETH. buys are scale times what they
+    /// spent, sells the same share of our tokens as they sold of theirs, each capped at
+    /// max_per_trade wei. buys are first simulated buying and selling back, like the sniper's,
+    /// both sides go through the risk limits.
+    /// an empty tokens_allowlist copies every token.
+    Follow {
+        address: String,
+        scale: f64,
+        max_per_trade: String,
+        #[serde(default)]
+        tokens_allowlist: Vec<String>,
+        #[serde(default)]
+        chain_id: Option<u64>,
+        #[serde(default)]
+        paper: bool,
+    },
+    Unfollow {
+        id: u64,
+    },
+    ListFollows,
+    /// limits checked before anything a strategy asks for is signed.
+    SetRiskLimits {
+        limits: RiskLimits,
+    },
+    /// with paper mode on whatever gets set up trades the paper book, what's already set up
+    /// keeps its mode. swaps, wraps and exact buys/sells fill on the paper book too, sends are refused.
+    SetPaperMode {
+        enabled: bool,
+    },
+    /// set the paper book's virtual ETH on a chain, in wei.
+    FundPaper {
+        amount: String,
+        #[serde(default)]
+        chain_id: Option<u64>,
+    },
+    /// paper balances valued at current prices, and the result against what was funded.
+    PaperStatus {
+        #[serde(default)]
+        chain_id: Option<u64>,
+    },
+    /// sample token's WETH pair reserves every step blocks and save them as dataset name.
+    /// samples are taken a chunk per timer tick, the dataset is saved once the last one is in.
+    RecordDataset {
+        name: String,
+        token: String,
+        from_block: u64,
+        to_block: u64,
+        step: u64,
+        #[serde(default)]
+        chain_id: Option<u64>,
+    },
+    /// replay a recorded dataset through a strategy, grid or limit order, starting with eth wei.
+    /// gas_price (wei) defaults to what it was when the dataset was recorded.
+    Backtest {
+        dataset: String,
+        subject: BacktestSubject,
+        eth: String,
+        #[serde(default)]
+        gas_price: Option<String>,
+    },
+    /// follow token's WETH pair on the first V2 dex that has one, keeping its price live
+    /// from the pair's logs. limit orders on watched tokens are priced from it.
+    /// candles for the pair are built from its Swap logs, starting with backfill_blocks of history.
+    Watch {
+        token: String,
+        #[serde(default)]
+        backfill_blocks: Option<u64>,
+        #[serde(default)]
+        chain_id: Option<u64>,
+    },
+    Unwatch {
+        id: u64,
+    },
+    Watchlist,
+    /// stored candles of a watched pair opened between from and to, in unix seconds.
+    /// interval is 1m, 5m, 1h or 1d. at most 14400 candles per request, bigger ranges need a
+    /// bigger interval.
+    GetCandles {
+        pair: String,
+        interval: Interval,
+        from: u64,
+        to: u64,
+        #[serde(default)]
+        chain_id: Option<u64>,
+    },
+    /// token's price over the last range (e.g. `24h`, `7d`) in interval candles, drawn in the
+    /// terminal. watched tokens use their stored candles, others are sampled from reserves.
+    Chart {
+        token: String,
+        interval: Interval,
+        range: String,
+        #[serde(default)]
+        style: ChartStyle,
+        #[serde(default)]
+        chain_id: Option<u64>,
+    },
+    /// alert when token's price in ETH goes above or below value, or moves value percent
+    /// (pct_change). with a cooldown in seconds the alert repeats, otherwise it fires once.
+    CreateAlert {
+        token: String,
+        condition: AlertCondition,
+        value: f64,
+        #[serde(default)]
+        cooldown: Option<u64>,
+        #[serde(default)]
+        chain_id: Option<u64>,
+    },
+    ListAlerts,
+    DeleteAlert {
+        id: u64,
+    },
+    /// process that fired alerts are sent to, e.g. `node.os@notifier:notifier:publisher.os`.
+    /// leave it out to only print them.
+    SetAlertTarget {
+        #[serde(default)]
+        target: Option<String>,
+    },
+    /// how many blocks pass between price checks.
+    SetWatcher {
+        poll_blocks: u64,
+    },
+    /// add or replace a chain's config, validated before it's stored.
+    SetChainConfig {
+        config: ChainConfig,
+    },
+    /// which chain requests without a chain_id go to.
+    SetDefaultChain {
+        chain_id: u64,
+    },
+}
+
+/// Buy and Info used to take no fields, clients may still send them as bare strings.
+fn parse_request(body: &[u8]) -> anyhow::Result<TradeRequest> {
+    match serde_json::from_slice::<String>(body).as_deref() {
+        Ok("Buy") => Ok(TradeRequest::Buy {
+            chain_id: None,
+            exits: Exits::default(),
+            paper: false,
+        }),
+        Ok("Info") => Ok(TradeRequest::Info { chain_id: None }),
+        _ => Ok(serde_json::from_slice(body)?),
+    }
+}
+
+/// what an exact-output swap is allowed to spend, and what may come back.
+#[derive(Debug)]
+struct ExactOutResult {
+    amount_out: U256,
+    quoted_in: U256,
+    max_in: U256,
+    /// what's left of max_in if the swap fills at the quote. the real amount depends on
+    /// where the price is when the tx is mined.
+    est_unspent: U256,
+}
+
+impl ExactOutResult {
+    fn print(&self, in_symbol: &str, out_symbol: &str) {
+        println!("+------------------+--------------------------------+");
+        println!(
+            "| Exact Output     | {:<30} |",
+            format!("{} {}", self.amount_out, out_symbol)
+        );
+        println!(
+            "| Quoted Input     | {:<30} |",
+            format!("{} {}", self.quoted_in, in_symbol)
+        );
+        println!(
+            "| Max Input        | {:<30} |",
+            format!("{} {}", self.max_in, in_symbol)
+        );
+        println!(
+            "| Est. Unspent     | {:<30} |",
+            format!("{} {}", self.est_unspent, in_symbol)
+        );
+        println!("+------------------+--------------------------------+");
+    }
+}
+
+/// reads the next line a human sends us. timers that fire in the meantime
+/// get pushed back a little so they don't eat the input.
+fn await_input(
+    subs: &mut Subscriptions,
+    state: &State,
+    watcher: &mut Watcher,
+) -> anyhow::Result<String> {
+    loop {
+        let message = await_message()?;
+        if let Message::Response {
+            ref source,
+            ref context,
+            ..
+        } = message
+        {
+            if is_timer(source) {
+                if let Some(context) = context {
+                    schedule(&serde_json::from_slice(context)?, WATCH_TICK_MS)?;
+                }
+                continue;
+            }
+        }
+        // prices keep flowing while a human is typing. new pairs created and trades of followed
+        // wallets meanwhile are missed.
+        if let Message::Request {
+            ref source,
+            ref body,
+            ..
+        } = message
+        {
+            if is_eth(source) {
+                match subs.receive(body)? {
+                    Some(Received::Log(Feed::Pair { chain_id, pair }, log)) => {
+                        watcher.on_pair_log(state, chain_id, pair, &log)?
+                    }
+                    Some(Received::Dropped(Feed::Pair { chain_id, pair })) => {
+                        watcher.prices.dropped(chain_id, pair)
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+        }
+        return Ok(String::from_utf8(message.body().to_vec())?);
+    }
+}
+
+fn handle_timer(
+    our: &Address,
+    context: TimerContext,
+    wallet: &Wallet<SigningKey>,
+    state: &mut State,
+    dexes: &mut DexRegistry,
+    watcher: &mut Watcher,
+    subs: &mut Subscriptions,
+) -> anyhow::Result<()> {
+    match context {
+        TimerContext::Watch => {
+            // re-arm first, one bad poll shouldn't stop the watcher.
+            schedule(&TimerContext::Watch, WATCH_TICK_MS)?;
+            state.risk_ledger.start_tick();
+
+            // subscriptions that dropped and couldn't be opened again right away.
+            watcher.prices.ensure_subscribed(subs);
+            if let Err(e) = watcher.candles.flush() {
+                println!("trader: couldn't store candles: {:?}", e);
+            }
+            if let Some(config) = &state.sniper {
+                if let Err(e) = state
+                    .chain(config.chain_id)
+                    .and_then(|chain| subscribe_pairs(subs, chain))
+                {
+                    println!("trader: sniper can't see new pairs: {:?}", e);
+                }
+            }
+            subscribe_follows(subs, state);
+
+            for chain_id in state.watched_chains() {
+                match watcher.due(chain_id, state.watcher.poll_blocks) {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    Err(e) => {
+                        println!("trader: couldn't poll chain {}: {:?}", chain_id, e);
+                        continue;
+                    }
+                }
+                if let Err(e) = check_orders(wallet, state, dexes, &mut watcher.prices, chain_id) {
+                    println!("trader: order check on chain {} failed: {:?}", chain_id, e);
+                }
+                if let Err(e) = check_grids(wallet, state, dexes, &mut watcher.prices, chain_id) {
+                    println!("trader: grid check on chain {} failed: {:?}", chain_id, e);
+                }
+                if let Err(e) = run_strategies(wallet, state, dexes, &mut watcher.prices, chain_id)
+                {
+                    println!("trader: strategies on chain {} failed: {:?}", chain_id, e);
+                }
+                if let Err(e) = check_alerts(state, dexes, &mut watcher.prices, chain_id) {
+                    println!("trader: alert check on chain {} failed: {:?}", chain_id, e);
+                }
+                if let Err(e) = check_positions(wallet, state, dexes, &mut watcher.prices, chain_id)
+                {
+                    println!(
+                        "trader: position check on chain {} failed: {:?}",
+                        chain_id, e
+                    );
+                }
+            }
+            // new pairs can't wait for poll_blocks, they're checked every tick.
+            if let Err(e) = check_snipes(wallet, state, dexes) {
+                println!("trader: snipe check failed: {:?}", e);
+            }
+        }
+        TimerContext::Dca(id) => run_dca(wallet, state, dexes, &mut watcher.prices, id)?,
+        TimerContext::Twap(id) => run_twap(wallet, state, dexes, &mut watcher.prices, id)?,
+        TimerContext::Record(id) => run_recording(our, state, id)?,
+    }
+    Ok(())
 }
 
-fn handle_message(our: &Address, wallet: &mut Wallet<SigningKey>) -> anyhow::Result<()> {
+/// something from one of our eth subscriptions.
+fn handle_eth(
+    received: Received,
+    wallet: &Wallet<SigningKey>,
+    state: &mut State,
+    dexes: &mut DexRegistry,
+    watcher: &mut Watcher,
+) -> anyhow::Result<()> {
+    match received {
+        Received::Log(Feed::PairCreated { chain_id, dex }, log) => {
+            // the pair may already have liquidity in its creation block, try it right away.
+            if on_pair_created(state, chain_id, &dex, &log)?.is_some() {
+                check_snipes(wallet, state, dexes)?;
+            }
+        }
+        Received::Log(Feed::Pair { chain_id, pair }, log) => {
+            watcher.on_pair_log(state, chain_id, pair, &log)?;
+        }
+        Received::Log(
+            Feed::Follow {
+                chain_id, address, ..
+            },
+            log,
+        ) => on_follow_log(wallet, state, dexes, chain_id, address, &log)?,
+        Received::Dropped(Feed::Pair { chain_id, pair }) => watcher.prices.dropped(chain_id, pair),
+        // the watcher opens it again if that didn't work already.
+        Received::Dropped(Feed::PairCreated { .. } | Feed::Follow { .. }) => {}
+    }
+    Ok(())
+}
+
+fn handle_message(
+    our: &Address,
+    wallet: &mut Wallet<SigningKey>,
+    state: &mut State,
+    dexes: &mut DexRegistry,
+    watcher: &mut Watcher,
+    subs: &mut Subscriptions,
+) -> anyhow::Result<()> {
     let message = await_message()?;
 
     match message {
-        Message::Response { .. } => {
-            return Err(anyhow::anyhow!("unexpected Response: {:?}", message));
+        Message::Response {
+            ref source,
+            ref context,
+            ..
+        } => {
+            if !is_timer(source) {
+                return Err(anyhow::anyhow!("unexpected Response: {:?}", message));
+            }
+            let context = context
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("timer fired without a context"))?;
+            handle_timer(
+                our,
+                serde_json::from_slice(context)?,
+                wallet,
+                state,
+                dexes,
+                watcher,
+                subs,
+            )?;
+        }
+        Message::Request {
+            ref source,
+            ref body,
+            ..
+        } if is_eth(source) => {
+            if let Some(received) = subs.receive(body)? {
+                handle_eth(received, wallet, state, dexes, watcher)?;
+            }
         }
         Message::Request {
             ref source,
             ref body,
             ..
-        } => match serde_json::from_slice::<TradeRequest>(body)? {
-            TradeRequest::Info => {
+        } => match parse_request(body)? {
+            TradeRequest::Info { chain_id } => {
                 let address = wallet.address();
-                let eth_balance = get_balance(wallet.address(), None)?;
-                let gas_price = get_gas_price()?;
-                let block_number = get_block_number()?;
-
-                println!("+------------------+--------------------------------+");
-                println!("| Field            | Value                          |");
-                println!("+------------------+--------------------------------+");
-                println!("| Address          | {:<30} |", address);
-                println!("| ETH Balance      | {:<30} |", eth_balance.to::<u64>());
-                println!("| Gas Price        | {:<30} |", gas_price.to::<u64>());
-                println!("| Block Number     | {:<30} |", block_number);
-                println!("+------------------+--------------------------------+");
-            }
-            TradeRequest::Buy => {
+                let mut chain_ids: Vec<u64> = match chain_id {
+                    Some(chain_id) => vec![chain_id],
+                    None => state.chains.keys().cloned().collect(),
+                };
+                chain_ids.sort();
+
+                println!("Address: {}", address);
+                println!("+------------------+----------------------+----------------------+------------------+------------+");
+                println!("| Chain            | ETH Balance          | WETH Balance         | Gas Price        | Block      |");
+                println!("+------------------+----------------------+----------------------+------------------+------------+");
+                for chain_id in chain_ids {
+                    let chain = state.chain(chain_id).ok();
+                    let name = chain
+                        .map(|chain| chain.name.clone())
+                        .unwrap_or_else(|| chain_id.to_string());
+                    let provider = eth_provider(chain_id);
+                    // one chain being down shouldn't hide the others.
+                    let row = (|| -> anyhow::Result<_> {
+                        let weth_balance = match chain {
+                            Some(chain) => {
+                                get_erc20_balance(chain_id, chain.weth, address)?.to_string()
+                            }
+                            None => "-".to_string(),
+                        };
+                        Ok((
+                            provider.get_balance(address, None)?,
+                            weth_balance,
+                            provider.get_gas_price()?,
+                            provider.get_block_number()?,
+                        ))
+                    })();
+                    match row {
+                        Ok((eth_balance, weth_balance, gas_price, block_number)) => println!(
+                            "| {:<16} | {:<20} | {:<20} | {:<16} | {:<10} |",
+                            name,
+                            eth_balance,
+                            weth_balance,
+                            gas_price.to::<u64>(),
+                            block_number
+                        ),
+                        Err(e) => println!("| {:<16} | {:<75} |", name, format!("error: {:?}", e)),
+                    }
+                }
+                println!("+------------------+----------------------+----------------------+------------------+------------+");
+            }
+            TradeRequest::Buy {
+                chain_id,
+                exits,
+                paper,
+            } => {
+                exits.validate()?;
                 println!("entered buy mode, enter contract address:");
-                let contract_address = await_message()?;
-                let contract_address = String::from_utf8(contract_address.body().to_vec())?;
-                let contract_address = EthAddress::from_str(&contract_address)?;
+                let contract_address =
+                    EthAddress::from_str(await_input(subs, state, watcher)?.trim())?;
 
-                let chain_id = get_chain_id()?.to::<u64>();
-                let (decimals, symbol) = get_erc20_info(contract_address)?;
+                let chain_id = state.chain_id(chain_id)?;
+                let (decimals, symbol) = get_erc20_info(chain_id, contract_address)?;
                 println!("getting WETH pair for {:?}", symbol);
 
-                let WETH_ADDRESS = WETH.get(&chain_id).ok_or_else(|| {
-                    anyhow::anyhow!("WETH not found for chain_id: {:?}", chain_id)
-                })?;
+                let chain = state.chain(chain_id)?.clone();
+                let WETH_ADDRESS = chain.weth;
+                let dexes = dexes.get(&chain)?;
 
-                let FACTORY_ADDRESS = FACTORY.get(&chain_id).ok_or_else(|| {
-                    anyhow::anyhow!("WETH not found for chain_id: {:?}", chain_id)
-                })?;
+                // show the direct WETH price if there is a pair, otherwise we'll route around it.
+                let direct_pair = dexes.iter().find_map(|dex| {
+                    dex.get_pair(WETH_ADDRESS, contract_address)
+                        .ok()
+                        .flatten()
+                        .map(|pair| (dex.name(), pair))
+                });
+                match direct_pair {
+                    Some((dex_name, pair_address)) => {
+                        println!("got {} pair address: {:?}", dex_name, pair_address);
 
-                let ROUTER_ADDRESS = ROUTER.get(&chain_id).ok_or_else(|| {
-                    anyhow::anyhow!("WETH not found for chain_id: {:?}", chain_id)
-                })?;
+                        let (p0, p1) = get_token_price(
+                            chain_id,
+                            pair_address,
+                            "WETH",
+                            &symbol,
+                            18,
+                            decimals.to::<u8>(),
+                        )?;
+                        println!("{:.4} {} per {}", p0, symbol, "WETH");
+                        println!("{:.4} {} per {}", p1, "WETH", symbol);
+                    }
+                    None => println!("no direct WETH pair for {}, will look for a route", symbol),
+                }
+
+                println!("input how much you want to buy:");
+                let amount_in = await_input(subs, state, watcher)?.trim().parse::<u64>()?;
 
-                let func_call = IUniswapV2Factory::getPairCall {
-                    tokenA: *WETH_ADDRESS,
-                    tokenB: contract_address,
+                let paper = paper || state.paper.enabled;
+                let mut account = Account::new(wallet, &mut state.paper, paper);
+                let fill = execute_buy(
+                    &mut account,
+                    &chain,
+                    dexes,
+                    contract_address,
+                    U256::from(amount_in),
+                    U256::ZERO,
+                )?;
+                println!(
+                    "bought {} via {}, sent! {}",
+                    symbol,
+                    fill.venue,
+                    chain.tx_url(fill.tx_hash)
+                );
+
+                if fill.quoted_out > U256::ZERO {
+                    let id = state.next_id();
+                    println!("trader: position {} opens once the buy is mined", id);
+                    state.positions.insert(
+                        id,
+                        Position {
+                            id,
+                            chain_id,
+                            paper,
+                            token: contract_address,
+                            symbol,
+                            decimals: decimals.to::<u8>(),
+                            amount: fill.quoted_out,
+                            entry_price: 0.0,
+                            high_price: 0.0,
+                            exits,
+                            status: PositionStatus::Pending {
+                                tx_hash: fill.tx_hash,
+                            },
+                        },
+                    );
+                    state.save()?;
                 }
-                .abi_encode();
-                // this should be a better builder...
-                let req = TransactionRequest {
-                    to: Some(FACTORY_ADDRESS.clone()),
-                    input: TransactionInput::new(func_call.into()),
-                    ..Default::default()
-                };
-                let res = call(req, None)?;
+            }
+            TradeRequest::Quote {
+                token,
+                amount,
+                chain_id,
+            } => {
+                let token = EthAddress::from_str(&token)?;
+                let chain_id = state.chain_id(chain_id)?;
+                let chain = state.chain(chain_id)?;
+                let dexes = dexes.get(chain)?;
 
-                let pair_address = EthAddress::abi_decode(&res, false)?;
-                println!("got pair address: {:?}", pair_address);
+                // watched tokens are quoted straight from the live reserves, the venues only
+                // get asked while the pair's subscription is down.
+                if let Some(entry) = state
+                    .watchlist
+                    .values()
+                    .find(|w| w.chain_id == chain_id && w.token == token)
+                    .filter(|w| watcher.prices.is_live(chain_id, w.pair))
+                {
+                    let fee_bps = dex_by_name(dexes, &entry.dex)?
+                        .fee_bps()
+                        .ok_or_else(|| anyhow::anyhow!("{} has no fixed fee", entry.dex))?;
+                    let reserves = watcher.prices.reserves(chain_id, entry.pair)?;
+                    let (reserve_in, reserve_out) = reserves.of(chain.weth);
+                    println!(
+                        "{} pair at block {} (live): {} {}",
+                        entry.dex,
+                        reserves.block,
+                        v2_amount_out(U256::from(amount), reserve_in, reserve_out, fee_bps),
+                        entry.symbol
+                    );
+                    return Ok(());
+                }
 
-                let (p0, p1) =
-                    get_token_price(pair_address, "WETH", &symbol, 18, decimals.to::<u8>())?;
-                println!("{:.4} {} per {}", p0, symbol, "WETH");
-                println!("{:.4} {} per {}", p1, "WETH", symbol);
+                let (_, symbol) = get_erc20_info(chain_id, token)?;
+                let gas_price = chain.provider().get_gas_price()?;
+                let venues = quote_venues(
+                    chain,
+                    dexes,
+                    chain.weth,
+                    token,
+                    U256::from(amount),
+                    gas_price,
+                )?;
+                if venues.is_empty() {
+                    return Err(anyhow::anyhow!("no venue can quote {}", symbol));
+                }
 
-                println!("input how much you want to buy:");
-                let amount_in = await_message()?;
-                let amount_in = String::from_utf8(amount_in.body().to_vec())?.parse::<u64>()?;
+                println!("+--------------------+--------------------------------+------------+----------------------+");
+                println!(
+                    "| Venue              | Output ({:<21}) | Gas        | L1 Fee (wei)         |",
+                    symbol
+                );
+                println!("+--------------------+--------------------------------+------------+----------------------+");
+                for venue in &venues {
+                    println!(
+                        "| {:<18} | {:<30} | {:<10} | {:<20} |",
+                        venue.name(),
+                        venue.amount_out(),
+                        venue.gas_estimate(),
+                        venue.l1_fee()
+                    );
+                }
+                println!("+--------------------+--------------------------------+------------+----------------------+");
 
-                // manual 5% slippage rn. or 100% is there mev on sepolia?
-                let amount_in_scaled = U256::from(amount_in);
+                if let Some(best) =
+                    pick_best_venue(chain, dexes, venues, U256::from(amount), gas_price)
+                {
+                    println!("would route to {} via {:?}", best.name(), best.path());
+                }
+            }
+            TradeRequest::BuyExact {
+                token,
+                amount_out,
+                slippage_bps,
+                chain_id,
+            } => {
+                check_slippage(slippage_bps)?;
+                let token = EthAddress::from_str(&token)?;
+                let chain_id = state.chain_id(chain_id)?;
+                let chain = state.chain(chain_id)?.clone();
+                let dexes = dexes.get(&chain)?;
+                let (_decimals, symbol) = get_erc20_info(chain_id, token)?;
 
-                // let min_amount_out = p0 * amount_in_scaled;
-                // let min_amount_out = U256::from(min_amount_out as u64);
+                let amount_out = U256::from_str(&amount_out)?;
+                let route = find_cheapest_route(&chain, dexes, chain.weth, token, amount_out)?;
+                if state.paper.enabled {
+                    let gas_price = chain.provider().get_gas_price()?;
+                    state.paper.buy_exact(&chain, &route, gas_price)?;
+                    state.save()?;
+                    return Ok(());
+                }
+                let dex = dex_by_name(dexes, &route.dex)?;
+                let max_in = with_slippage_max(route.amount_in, slippage_bps);
 
-                let path = vec![*WETH_ADDRESS, contract_address];
+                // the router refunds any ETH it doesn't spend, at the quote this is what comes back.
+                let result = ExactOutResult {
+                    amount_out,
+                    quoted_in: route.amount_in,
+                    max_in,
+                    est_unspent: max_in - route.amount_in,
+                };
+                result.print("wei", &symbol);
 
-                let mut tx = send_swap_call_request(
+                let tx = send_eth_for_exact_tokens_request(
                     wallet.address(),
                     chain_id,
-                    *ROUTER_ADDRESS,
-                    amount_in,
-                    U256::from(0),
-                    path,
+                    dex,
+                    amount_out,
+                    max_in,
+                    route.path,
                 )?;
 
-                let sig = wallet.sign_transaction_sync(&mut tx)?;
-                let signed_tx = tx.into_signed(sig);
+                let tx_hash = sign_and_send(wallet, tx)?;
+                println!("sent! {}", chain.tx_url(tx_hash));
+            }
+            TradeRequest::SellExact {
+                token,
+                amount_out,
+                slippage_bps,
+                chain_id,
+            } => {
+                check_slippage(slippage_bps)?;
+                let token = EthAddress::from_str(&token)?;
+                let chain_id = state.chain_id(chain_id)?;
+                let chain = state.chain(chain_id)?.clone();
+                let dexes = dexes.get(&chain)?;
+                let (_decimals, symbol) = get_erc20_info(chain_id, token)?;
 
-                let mut buf = vec![];
-                signed_tx.encode_signed(&mut buf);
+                let amount_out = U256::from_str(&amount_out)?;
+                let route = find_cheapest_route(&chain, dexes, token, chain.weth, amount_out)?;
+                if state.paper.enabled {
+                    let gas_price = chain.provider().get_gas_price()?;
+                    state.paper.sell_exact(&chain, &route, gas_price)?;
+                    state.save()?;
+                    return Ok(());
+                }
+                let dex = dex_by_name(dexes, &route.dex)?;
+                let max_in = with_slippage_max(route.amount_in, slippage_bps);
+
+                // tokens are pulled by the router, anything under max_in just stays in the wallet.
+                let result = ExactOutResult {
+                    amount_out,
+                    quoted_in: route.amount_in,
+                    max_in,
+                    est_unspent: max_in - route.amount_in,
+                };
+                result.print(&symbol, "wei");
 
-                let tx_hash = send_raw_transaction(buf.into())?;
-                println!("sent! with tx_hash {:?}", tx_hash);
+                ensure_allowance(wallet, chain_id, token, dex.router(), max_in)?;
+
+                let tx = send_tokens_for_exact_eth_request(
+                    wallet.address(),
+                    chain_id,
+                    dex,
+                    amount_out,
+                    max_in,
+                    route.path,
+                )?;
+
+                let tx_hash = sign_and_send(wallet, tx)?;
+                println!("sent! {}", chain.tx_url(tx_hash));
+            }
+            TradeRequest::Swap {
+                from_token,
+                to_token,
+                amount,
+                chain_id,
+            } => {
+                let from_token = EthAddress::from_str(&from_token)?;
+                let to_token = EthAddress::from_str(&to_token)?;
+                let amount_in = U256::from_str(&amount)?;
+                let chain_id = state.chain_id(chain_id)?;
+                if state.paper.enabled {
+                    let chain = state.chain(chain_id)?.clone();
+                    let dexes = dexes.get(&chain)?;
+                    state
+                        .paper
+                        .swap(&chain, dexes, from_token, to_token, amount_in)?;
+                    state.save()?;
+                    return Ok(());
+                }
+                let chain = state.chain(chain_id)?;
+                let dexes = dexes.get(chain)?;
+                let (_, from_symbol) = get_erc20_info(chain_id, from_token)?;
+                let (_, to_symbol) = get_erc20_info(chain_id, to_token)?;
+
+                let gas_price = chain.provider().get_gas_price()?;
+                let venue = best_venue(chain, dexes, from_token, to_token, amount_in, gas_price)?;
+                let cost = venue.cost_wei(gas_price);
+                let tx = match venue {
+                    Venue::V2(route) => {
+                        let dex = dex_by_name(dexes, &route.dex)?;
+                        // tokens we can't fake a balance for are simulated with the real
+                        // allowance, so approve first and have the swap retried once it's mined.
+                        let Some(tax) = check_sell_tax(
+                            wallet.address(),
+                            dex,
+                            amount_in,
+                            route.path.clone(),
+                            route.amount_out,
+                            false,
+                        )?
+                        else {
+                            ensure_allowance(
+                                wallet,
+                                chain_id,
+                                from_token,
+                                dex.router(),
+                                amount_in,
+                            )?;
+                            return Err(anyhow::anyhow!(
+                                "can't simulate swapping {} until the approve is mined, try again then",
+                                from_symbol
+                            ));
+                        };
+                        if tax == TaxCheck::Untradeable {
+                            return Err(anyhow::anyhow!(
+                                "swap of {} to {} fails in simulation, refusing to send",
+                                from_symbol,
+                                to_symbol
+                            ));
+                        }
+                        let min_amount_out = with_slippage_min(
+                            with_slippage_min(route.amount_out, tax.bps()),
+                            DEFAULT_SLIPPAGE_BPS,
+                        );
+                        println!(
+                            "swapping {} {} for at least {} {} on {} via {:?} (tax {}bps), costs ~{} wei (L1 fee {})",
+                            amount_in,
+                            from_symbol,
+                            min_amount_out,
+                            to_symbol,
+                            route.dex,
+                            route.path,
+                            tax.bps(),
+                            cost,
+                            route.l1_fee
+                        );
+
+                        ensure_allowance(wallet, chain_id, from_token, dex.router(), amount_in)?;
+                        send_tokens_for_tokens_request(
+                            wallet.address(),
+                            chain_id,
+                            dex,
+                            amount_in,
+                            min_amount_out,
+                            route.path,
+                            tax.fee_on_transfer(),
+                        )?
+                    }
+                    // v3 pools have no fee-on-transfer variant, taxed tokens just revert there
+                    // and the V2 route is the one to use for them.
+                    Venue::V3(quote) => {
+                        let v3_router = chain.v3()?.router;
+                        let min_amount_out =
+                            with_slippage_min(quote.amount_out, DEFAULT_SLIPPAGE_BPS);
+                        println!(
+                            "swapping {} {} for at least {} {} on uniswap-v3 via {:?}, costs ~{} wei (L1 fee {})",
+                            amount_in,
+                            from_symbol,
+                            min_amount_out,
+                            to_symbol,
+                            quote.path,
+                            cost,
+                            quote.l1_fee
+                        );
+
+                        ensure_allowance(wallet, chain_id, from_token, v3_router, amount_in)?;
+                        send_v3_swap_request(
+                            wallet.address(),
+                            chain_id,
+                            v3_router,
+                            &quote,
+                            min_amount_out,
+                            U256::ZERO,
+                        )?
+                    }
+                };
+
+                let tx_hash = sign_and_send(wallet, tx)?;
+                println!("sent! {}", chain.tx_url(tx_hash));
             }
-            TradeRequest::Send { amount, to } => {
+            TradeRequest::Send {
+                amount,
+                to,
+                chain_id,
+            } => {
+                live_only(state)?;
                 let to = EthAddress::from_str(&to)?;
-                let chain_id = get_chain_id()?;
-                let gas_price = get_gas_price()?;
-                let nonce = get_transaction_count(wallet.address(), None)?;
-
-                let mut tx = alloy_consensus::TxLegacy {
-                    nonce: nonce.to::<u64>(),
-                    gas_price: gas_price.to::<u128>(),
-                    gas_limit: 21000,
-                    to: TxKind::Call(to), // Use `TxKind::Call` with the recipient's address
-                    value: U256::from(amount),
-                    input: Bytes::default(),
-                    chain_id: Some(chain_id.to::<u64>()),
+                let chain_id = state.chain_id(chain_id)?;
+                let tx = eth_transfer_tx(wallet.address(), chain_id, to, U256::from(amount))?;
+
+                // unconfigured chains still work, we just can't price their L1 data.
+                if let Ok(chain) = state.chain(chain_id) {
+                    print_tx_cost(&estimate_tx_cost(chain, &tx)?);
+                }
+
+                let tx_hash = sign_and_send(wallet, tx)?;
+                match state.chain(chain_id) {
+                    Ok(chain) => println!("sent! {}", chain.tx_url(tx_hash)),
+                    Err(_) => println!("sent! with tx_hash {:?}", tx_hash),
+                }
+            }
+            TradeRequest::SendMax { to, chain_id } => {
+                live_only(state)?;
+                let to = EthAddress::from_str(&to)?;
+                let chain_id = state.chain_id(chain_id)?;
+                let chain = state.chain(chain_id)?;
+                let balance = chain.provider().get_balance(wallet.address(), None)?;
+
+                // price the tx at the full balance, the value we end up sending is never longer.
+                let mut tx = eth_transfer_tx(wallet.address(), chain_id, to, balance)?;
+                let cost = estimate_tx_cost(chain, &tx)?;
+                print_tx_cost(&cost);
+
+                // the L1 fee moves with L1 gas until the tx lands, hold back 10% of it.
+                let reserve = cost.total() + cost.l1_fee / U256::from(10);
+                if balance <= reserve {
+                    return Err(anyhow::anyhow!(
+                        "balance {} doesn't cover the {} wei fee",
+                        balance,
+                        reserve
+                    ));
+                }
+                tx.value = balance - reserve;
+                println!("sending {} wei of {}", tx.value, balance);
+
+                let tx_hash = sign_and_send(wallet, tx)?;
+                println!("sent! {}", chain.tx_url(tx_hash));
+            }
+            TradeRequest::Wrap { amount, chain_id } => {
+                let amount = U256::from_str(&amount)?;
+                let chain_id = state.chain_id(chain_id)?;
+                let chain = state.chain(chain_id)?.clone();
+
+                let tx = send_wrap_request(wallet.address(), chain_id, chain.weth, amount)?;
+                let cost = estimate_tx_cost(&chain, &tx)?;
+                if state.paper.enabled {
+                    state.paper.wrap(&chain, amount, cost.total())?;
+                    state.save()?;
+                    return Ok(());
+                }
+                let balance = chain.provider().get_balance(wallet.address(), None)?;
+                if balance < amount + cost.total() {
+                    return Err(anyhow::anyhow!(
+                        "can't wrap {} wei, balance is {} and the tx costs {}",
+                        amount,
+                        balance,
+                        cost.total()
+                    ));
+                }
+                print_tx_cost(&cost);
+
+                let tx_hash = sign_and_send(wallet, tx)?;
+                println!("wrapping {} wei, sent! {}", amount, chain.tx_url(tx_hash));
+            }
+            TradeRequest::Unwrap { amount, chain_id } => {
+                let amount = U256::from_str(&amount)?;
+                let chain_id = state.chain_id(chain_id)?;
+                let chain = state.chain(chain_id)?.clone();
+                if state.paper.enabled {
+                    let tx = send_unwrap_request(wallet.address(), chain_id, chain.weth, amount)?;
+                    let cost = estimate_tx_cost(&chain, &tx)?;
+                    state.paper.unwrap(&chain, amount, cost.total())?;
+                    state.save()?;
+                    return Ok(());
+                }
+
+                let weth_balance = get_erc20_balance(chain_id, chain.weth, wallet.address())?;
+                if weth_balance < amount {
+                    return Err(anyhow::anyhow!(
+                        "can't unwrap {} wei, WETH balance is {}",
+                        amount,
+                        weth_balance
+                    ));
+                }
+
+                let tx = send_unwrap_request(wallet.address(), chain_id, chain.weth, amount)?;
+                print_tx_cost(&estimate_tx_cost(&chain, &tx)?);
+
+                let tx_hash = sign_and_send(wallet, tx)?;
+                println!("unwrapping {} wei, sent! {}", amount, chain.tx_url(tx_hash));
+            }
+            TradeRequest::PlaceLimitOrder {
+                token,
+                side,
+                amount,
+                limit_price,
+                expiry,
+                chain_id,
+                paper,
+            } => {
+                let token = EthAddress::from_str(&token)?;
+                let amount = U256::from_str(&amount)?;
+                let chain_id = state.chain_id(chain_id)?;
+                let paper = paper || state.paper.enabled;
+                if !limit_price.is_finite() || limit_price <= 0.0 {
+                    return Err(anyhow::anyhow!("limit_price should be a positive number"));
+                }
+                if amount == U256::ZERO {
+                    return Err(anyhow::anyhow!("amount should be more than 0"));
+                }
+                if expiry.is_some_and(|expiry| expiry <= now_secs().unwrap_or(0)) {
+                    return Err(anyhow::anyhow!("expiry is already in the past"));
+                }
+
+                let chain = state.chain(chain_id)?;
+                let (decimals, symbol) = get_erc20_info(chain_id, token)?;
+                let decimals = decimals.to::<u8>();
+                // the watcher needs a WETH pair to price the token, better to find out now.
+                let price = watcher
+                    .prices
+                    .weth_price(chain, dexes.get(chain)?, token, decimals)?;
+
+                let id = state.next_id();
+                let order = LimitOrder {
+                    id,
+                    chain_id,
+                    paper,
+                    token,
+                    symbol,
+                    decimals,
+                    side,
+                    amount,
+                    limit_price,
+                    expiry,
+                    status: OrderStatus::Open,
                 };
+                println!(
+                    "trader: placed order {}, {:?} {} at {} ETH, now {} ETH",
+                    id, side, order.symbol, limit_price, price
+                );
+                state.orders.insert(id, order);
+                state.save()?;
+            }
+            TradeRequest::ListOrders => {
+                println!("+--------+------------------+------+----------------------+----------------------+------------+");
+                println!("| Id     | Token            | Side | Amount               | Limit (ETH)          | Status     |");
+                println!("+--------+------------------+------+----------------------+----------------------+------------+");
+                for order in state.orders.values() {
+                    let status = match order.status {
+                        OrderStatus::Open => "open",
+                        OrderStatus::Pending { .. } => "pending",
+                        OrderStatus::Filled { .. } => "filled",
+                        OrderStatus::Expired => "expired",
+                        OrderStatus::Cancelled => "cancelled",
+                    };
+                    println!(
+                        "| {:<6} | {:<16} | {:<4} | {:<20} | {:<20} | {:<10} |",
+                        order.id,
+                        order.symbol,
+                        format!("{:?}", order.side),
+                        order.amount,
+                        order.limit_price,
+                        status
+                    );
+                }
+                println!("+--------+------------------+------+----------------------+----------------------+------------+");
+            }
+            TradeRequest::CancelOrder { id } => {
+                let order = state
+                    .orders
+                    .get_mut(&id)
+                    .ok_or_else(|| anyhow::anyhow!("no order {}", id))?;
+                if !order.is_open() {
+                    return Err(anyhow::anyhow!(
+                        "order {} is already {:?}",
+                        id,
+                        order.status
+                    ));
+                }
+                order.status = OrderStatus::Cancelled;
+                state.save()?;
+                println!("trader: cancelled order {}", id);
+            }
+            TradeRequest::ListPositions => {
+                println!("+--------+------------------+----------------------+----------------------+------------------------+--------------+");
+                println!("| Id     | Token            | Amount               | Entry (ETH)          | SL / TP / Trail (%)    | Status       |");
+                println!("+--------+------------------+----------------------+----------------------+------------------------+--------------+");
+                for position in state.positions.values() {
+                    let pct = |p: Option<f64>| p.map(|p| p.to_string()).unwrap_or("-".into());
+                    let status = match &position.status {
+                        PositionStatus::Pending { .. } => "pending".to_string(),
+                        PositionStatus::Open => "open".to_string(),
+                        PositionStatus::Closing { .. } => "closing".to_string(),
+                        PositionStatus::Closed { reason, .. } => format!("{:?}", reason),
+                        PositionStatus::Dismissed => "dismissed".to_string(),
+                    };
+                    println!(
+                        "| {:<6} | {:<16} | {:<20} | {:<20} | {:<22} | {:<12} |",
+                        position.id,
+                        position.symbol,
+                        position.amount,
+                        position.entry_price,
+                        format!(
+                            "{} / {} / {}",
+                            pct(position.exits.stop_loss_pct),
+                            pct(position.exits.take_profit_pct),
+                            pct(position.exits.trailing_stop_pct)
+                        ),
+                        status
+                    );
+                }
+                println!("+--------+------------------+----------------------+----------------------+------------------------+--------------+");
+            }
+            TradeRequest::SetExits { id, exits } => {
+                exits.validate()?;
+                let position = state
+                    .positions
+                    .get_mut(&id)
+                    .ok_or_else(|| anyhow::anyhow!("no position {}", id))?;
+                if !position.is_open() && !position.is_pending() {
+                    return Err(anyhow::anyhow!("position {} is {:?}", id, position.status));
+                }
+                println!("trader: position {} exits now {:?}", id, exits);
+                position.exits = exits;
+                state.save()?;
+            }
+            TradeRequest::DismissPosition { id } => {
+                let position = state
+                    .positions
+                    .get_mut(&id)
+                    .ok_or_else(|| anyhow::anyhow!("no position {}", id))?;
+                position.status = PositionStatus::Dismissed;
+                state.save()?;
+                println!("trader: dismissed position {}", id);
+            }
+            TradeRequest::CreateDca {
+                token,
+                amount_per_buy,
+                interval,
+                total_budget,
+                max_price,
+                max_gas_price,
+                chain_id,
+                paper,
+            } => {
+                let token = EthAddress::from_str(&token)?;
+                let amount_per_buy = U256::from_str(&amount_per_buy)?;
+                let total_budget = U256::from_str(&total_budget)?;
+                let max_gas_price = max_gas_price.map(|p| U256::from_str(&p)).transpose()?;
+                let chain_id = state.chain_id(chain_id)?;
+                let paper = paper || state.paper.enabled;
+                if amount_per_buy == U256::ZERO || total_budget < amount_per_buy {
+                    return Err(anyhow::anyhow!(
+                        "amount_per_buy should be above 0 and within total_budget"
+                    ));
+                }
+                if interval == 0 {
+                    return Err(anyhow::anyhow!("interval should be at least 1 second"));
+                }
+                if max_price.is_some_and(|p| !p.is_finite() || p <= 0.0) {
+                    return Err(anyhow::anyhow!("max_price should be a positive number"));
+                }
+
+                let chain = state.chain(chain_id)?;
+                let (decimals, symbol) = get_erc20_info(chain_id, token)?;
+                let decimals = decimals.to::<u8>();
+                // fail now rather than on every tick if the token can't be priced.
+                if max_price.is_some() {
+                    watcher
+                        .prices
+                        .weth_price(chain, dexes.get(chain)?, token, decimals)?;
+                }
+
+                let id = state.next_id();
+                let dca = DcaSchedule {
+                    id,
+                    chain_id,
+                    paper,
+                    token,
+                    symbol,
+                    decimals,
+                    amount_per_buy,
+                    interval,
+                    total_budget,
+                    max_price,
+                    max_gas_price,
+                    status: DcaStatus::Active,
+                    // the first buy goes out right away.
+                    next_run: now_secs()?,
+                    spent: U256::ZERO,
+                    bought: U256::ZERO,
+                    buys: 0,
+                    skipped: 0,
+                    pending: vec![],
+                };
+                dca.arm()?;
+                println!(
+                    "trader: created dca {}, {} wei of {} every {}s up to {} wei",
+                    id, amount_per_buy, dca.symbol, interval, total_budget
+                );
+                state.dcas.insert(id, dca);
+                state.save()?;
+            }
+            TradeRequest::DcaStatus { id } => {
+                println!("+--------+------------------+-----------+----------------------+----------------------+----------------------+-------------+----------+");
+                println!("| Id     | Token            | Status    | Spent (wei)          | Budget (wei)         | Avg Price (ETH)      | Buys / Skip | Next In  |");
+                println!("+--------+------------------+-----------+----------------------+----------------------+----------------------+-------------+----------+");
+                let now = now_secs()?;
+                for dca in state
+                    .dcas
+                    .values()
+                    .filter(|d| id.map_or(true, |id| d.id == id))
+                {
+                    let next_in = if dca.status == DcaStatus::Active {
+                        format!("{}s", dca.next_run.saturating_sub(now))
+                    } else {
+                        "-".to_string()
+                    };
+                    println!(
+                        "| {:<6} | {:<16} | {:<9} | {:<20} | {:<20} | {:<20} | {:<11} | {:<8} |",
+                        dca.id,
+                        dca.symbol,
+                        format!("{:?}", dca.status),
+                        dca.spent,
+                        dca.total_budget,
+                        dca.average_price()
+                            .map(|p| p.to_string())
+                            .unwrap_or("-".to_string()),
+                        format!("{} / {}", dca.buys, dca.skipped),
+                        next_in
+                    );
+                }
+                println!("+--------+------------------+-----------+----------------------+----------------------+----------------------+-------------+----------+");
+            }
+            TradeRequest::PauseDca { id } => {
+                let dca = state
+                    .dcas
+                    .get_mut(&id)
+                    .ok_or_else(|| anyhow::anyhow!("no dca {}", id))?;
+                if dca.status != DcaStatus::Active {
+                    return Err(anyhow::anyhow!("dca {} is {:?}", id, dca.status));
+                }
+                // the pending timer still fires, it just finds the schedule paused.
+                dca.status = DcaStatus::Paused;
+                state.save()?;
+                println!("trader: paused dca {}", id);
+            }
+            TradeRequest::ResumeDca { id } => {
+                let dca = state
+                    .dcas
+                    .get_mut(&id)
+                    .ok_or_else(|| anyhow::anyhow!("no dca {}", id))?;
+                if dca.status != DcaStatus::Paused {
+                    return Err(anyhow::anyhow!("dca {} is {:?}", id, dca.status));
+                }
+                dca.status = DcaStatus::Active;
+                dca.next_run = now_secs()? + dca.interval;
+                dca.arm()?;
+                state.save()?;
+                println!("trader: resumed dca {}", id);
+            }
+            TradeRequest::CancelDca { id } => {
+                let dca = state
+                    .dcas
+                    .get_mut(&id)
+                    .ok_or_else(|| anyhow::anyhow!("no dca {}", id))?;
+                if matches!(dca.status, DcaStatus::Done | DcaStatus::Cancelled) {
+                    return Err(anyhow::anyhow!("dca {} is already {:?}", id, dca.status));
+                }
+                dca.status = DcaStatus::Cancelled;
+                state.save()?;
+                println!("trader: cancelled dca {}", id);
+            }
+            TradeRequest::TwapOrder {
+                token,
+                total_amount,
+                slices,
+                interval,
+                bound_pct,
+                chain_id,
+                paper,
+            } => {
+                let token = EthAddress::from_str(&token)?;
+                let total_amount = U256::from_str(&total_amount)?;
+                let bound_pct = bound_pct.unwrap_or(DEFAULT_TWAP_BOUND_PCT);
+                let chain_id = state.chain_id(chain_id)?;
+                let paper = paper || state.paper.enabled;
+                if slices == 0 || total_amount < U256::from(slices) {
+                    return Err(anyhow::anyhow!(
+                        "need at least 1 slice, and 1 wei per slice"
+                    ));
+                }
+                if !bound_pct.is_finite() || bound_pct <= 0.0 {
+                    return Err(anyhow::anyhow!("bound_pct should be above 0"));
+                }
+
+                let chain = state.chain(chain_id)?;
+                let (decimals, symbol) = get_erc20_info(chain_id, token)?;
+                let decimals = decimals.to::<u8>();
+                let arrival_price =
+                    watcher
+                        .prices
+                        .weth_price(chain, dexes.get(chain)?, token, decimals)?;
+
+                let id = state.next_id();
+                let twap = Twap {
+                    id,
+                    chain_id,
+                    paper,
+                    token,
+                    symbol,
+                    decimals,
+                    total_amount,
+                    slices,
+                    interval,
+                    bound_pct,
+                    arrival_price,
+                    status: TwapStatus::Running,
+                    next_run: now_secs()?,
+                    executed: 0,
+                    spent: U256::ZERO,
+                    bought: U256::ZERO,
+                    pending: vec![],
+                };
+                twap.arm()?;
+                println!(
+                    "trader: twap {} buying {} wei of {} in {} slices, arrival {} ETH, bound {} ETH",
+                    id,
+                    total_amount,
+                    twap.symbol,
+                    slices,
+                    arrival_price,
+                    twap.price_bound()
+                );
+                state.twaps.insert(id, twap);
+                state.save()?;
+            }
+            TradeRequest::TwapStatus { id } => {
+                for twap in state
+                    .twaps
+                    .values()
+                    .filter(|t| id.map_or(true, |id| t.id == id))
+                {
+                    twap.report();
+                }
+            }
+            TradeRequest::CancelTwap { id } => {
+                let twap = state
+                    .twaps
+                    .get_mut(&id)
+                    .ok_or_else(|| anyhow::anyhow!("no twap {}", id))?;
+                if twap.status != TwapStatus::Running {
+                    return Err(anyhow::anyhow!("twap {} is {:?}", id, twap.status));
+                }
+                twap.status = TwapStatus::Stopped("cancelled".to_string());
+                twap.report();
+                state.save()?;
+            }
+            TradeRequest::CreateGrid {
+                token,
+                lower,
+                upper,
+                levels,
+                capital_per_level,
+                chain_id,
+                paper,
+            } => {
+                let token = EthAddress::from_str(&token)?;
+                let capital_per_level = U256::from_str(&capital_per_level)?;
+                let chain_id = state.chain_id(chain_id)?;
+                let paper = paper || state.paper.enabled;
+                let bands = Grid::bands(lower, upper, levels)?;
+                if capital_per_level == U256::ZERO {
+                    return Err(anyhow::anyhow!("capital_per_level should be above 0"));
+                }
+
+                let chain = state.chain(chain_id)?;
+                let (decimals, symbol) = get_erc20_info(chain_id, token)?;
+                let decimals = decimals.to::<u8>();
+                let price = watcher
+                    .prices
+                    .weth_price(chain, dexes.get(chain)?, token, decimals)?;
+
+                let id = state.next_id();
+                println!(
+                    "trader: grid {} on {} from {} to {} ETH in {} bands, price now {} ETH",
+                    id,
+                    symbol,
+                    lower,
+                    upper,
+                    bands.len(),
+                    price
+                );
+                state.grids.insert(
+                    id,
+                    Grid {
+                        id,
+                        chain_id,
+                        paper,
+                        token,
+                        symbol,
+                        decimals,
+                        capital_per_level,
+                        bands,
+                        cycles: vec![],
+                        status: GridStatus::Running,
+                    },
+                );
+                state.save()?;
+            }
+            TradeRequest::GridStatus { id } => {
+                for grid in state
+                    .grids
+                    .values()
+                    .filter(|g| id.map_or(true, |id| g.id == id))
+                {
+                    println!(
+                        "grid {} on {} ({:?}): {} cycles, realized {} ETH",
+                        grid.id,
+                        grid.symbol,
+                        grid.status,
+                        grid.cycles.len(),
+                        grid.realized_profit()
+                    );
+                    println!("+------+----------------------+----------------------+----------------------+");
+                    println!("| Band | Buy At (ETH)         | Sell At (ETH)        | Held                 |");
+                    println!("+------+----------------------+----------------------+----------------------+");
+                    for (i, band) in grid.bands.iter().enumerate() {
+                        println!(
+                            "| {:<4} | {:<20} | {:<20} | {:<20} |",
+                            i, band.lower, band.upper, band.held
+                        );
+                    }
+                    println!("+------+----------------------+----------------------+----------------------+");
+                    for cycle in &grid.cycles {
+                        println!(
+                            "band {} cycle: paid {} wei, got {} wei, profit {} ETH",
+                            cycle.band, cycle.cost, cycle.proceeds, cycle.profit
+                        );
+                    }
+                }
+            }
+            TradeRequest::StopGrid { id } => {
+                let grid = state
+                    .grids
+                    .get_mut(&id)
+                    .ok_or_else(|| anyhow::anyhow!("no grid {}", id))?;
+                grid.status = GridStatus::Stopped;
+                state.save()?;
+                println!("trader: stopped grid {}", id);
+            }
+            TradeRequest::CreateStrategy {
+                strategy,
+                chain_id,
+                paper,
+            } => {
+                let chain_id = state.chain_id(chain_id)?;
+                let paper = paper || state.paper.enabled;
+                add_strategy(state, chain_id, strategy.into(), paper)?;
+            }
+            TradeRequest::StartStrategy { id } => {
+                let instance = state
+                    .strategies
+                    .get_mut(&id)
+                    .ok_or_else(|| anyhow::anyhow!("no strategy {}", id))?;
+                instance.running = true;
+                state.save()?;
+                println!("trader: started strategy {}", id);
+            }
+            TradeRequest::StopStrategy { id } => {
+                let instance = state
+                    .strategies
+                    .get_mut(&id)
+                    .ok_or_else(|| anyhow::anyhow!("no strategy {}", id))?;
+                instance.running = false;
+                state.save()?;
+                println!("trader: stopped strategy {}", id);
+            }
+            TradeRequest::ConfigureStrategy { id, params } => {
+                let instance = state
+                    .strategies
+                    .get_mut(&id)
+                    .ok_or_else(|| anyhow::anyhow!("no strategy {}", id))?;
+                let strategy = instance.kind.strategy_mut();
+                strategy.configure(params)?;
+                strategy.validate()?;
+                println!("trader: strategy {} is now {}", id, strategy.describe());
+                state.save()?;
+            }
+            TradeRequest::ListStrategies => {
+                for instance in state.strategies.values() {
+                    println!(
+                        "strategy {} on chain {} ({}): {}",
+                        instance.id,
+                        instance.chain_id,
+                        if instance.running {
+                            "running"
+                        } else {
+                            "stopped"
+                        },
+                        instance.kind.strategy().describe()
+                    );
+                }
+                println!("risk limits: {:?}", state.risk);
+            }
+            TradeRequest::CreateRule {
+                rule,
+                chain_id,
+                paper,
+            } => {
+                let chain_id = state.chain_id(chain_id)?;
+                let paper = paper || state.paper.enabled;
+                let rule = Rule::parse(&rule, state.chain(chain_id)?, |token| {
+                    Ok(get_erc20_info(chain_id, token)?.0.to::<u8>())
+                })?;
+                add_strategy(state, chain_id, StrategyKind::Rule(rule), paper)?;
+            }
+            TradeRequest::ListRules => {
+                println!(
+                    "{:<6} | {:<8} | {:<8} | {:<5} | {}",
+                    "ID", "Chain", "Status", "Fired", "Rule"
+                );
+                println!("{}", "-".repeat(80));
+                for instance in state.strategies.values() {
+                    let StrategyKind::Rule(rule) = &instance.kind else {
+                        continue;
+                    };
+                    let status = if instance.running {
+                        "active"
+                    } else if rule.fired() > 0 {
+                        "done"
+                    } else {
+                        "disabled"
+                    };
+                    println!(
+                        "{:<6} | {:<8} | {:<8} | {:<5} | {}",
+                        instance.id,
+                        instance.chain_id,
+                        status,
+                        rule.fired(),
+                        rule.source
+                    );
+                }
+            }
+            TradeRequest::DisableRule { id } => {
+                let instance = state
+                    .strategies
+                    .get_mut(&id)
+                    .filter(|s| matches!(s.kind, StrategyKind::Rule(_)))
+                    .ok_or_else(|| anyhow::anyhow!("no rule {}", id))?;
+                instance.running = false;
+                state.save()?;
+                println!("trader: disabled rule {}", id);
+            }
+            TradeRequest::StartSniper {
+                amount,
+                min_liquidity,
+                max_wait_blocks,
+                max_tax_bps,
+                slippage_bps,
+                exits,
+                chain_id,
+                paper,
+            } => {
+                exits.validate()?;
+                let amount = U256::from_str(&amount)?;
+                if amount == U256::ZERO {
+                    return Err(anyhow::anyhow!("the sniper needs an amount to buy with"));
+                }
+                let chain_id = state.chain_id(chain_id)?;
+                let config = SniperConfig {
+                    chain_id,
+                    amount,
+                    min_liquidity: U256::from_str(&min_liquidity)?,
+                    max_wait_blocks: max_wait_blocks.unwrap_or(DEFAULT_MAX_WAIT_BLOCKS),
+                    max_tax_bps: max_tax_bps.unwrap_or(DEFAULT_MAX_TAX_BPS),
+                    slippage_bps: slippage_bps.unwrap_or(DEFAULT_SLIPPAGE_BPS),
+                    exits,
+                    paper: paper || state.paper.enabled,
+                };
+                if config.max_tax_bps >= 10_000 {
+                    return Err(anyhow::anyhow!("max_tax_bps should be below 10000"));
+                }
+                check_slippage(config.slippage_bps)?;
+
+                // a restart replaces whatever the sniper was watching before.
+                unsubscribe_pairs(subs);
+                subscribe_pairs(subs, state.chain(chain_id)?)?;
+                println!("trader: sniper started, {:?}", config);
+                state.sniper = Some(config);
+                state.save()?;
+            }
+            TradeRequest::StopSniper => {
+                unsubscribe_pairs(subs);
+                state.sniper = None;
+                for snipe in state.snipes.values_mut() {
+                    if snipe.status == SnipeStatus::Watching {
+                        snipe.status = SnipeStatus::Skipped {
+                            reason: "sniper stopped".to_string(),
+                        };
+                    }
+                }
+                state.save()?;
+                println!("trader: sniper stopped");
+            }
+            TradeRequest::SniperStatus => {
+                match &state.sniper {
+                    Some(config) => println!("sniper: {:?}", config),
+                    None => println!("sniper: stopped"),
+                }
+                println!(
+                    "{:<6} | {:<8} | {:<10} | {:<42} | {}",
+                    "ID", "Chain", "Block", "Token", "Status"
+                );
+                println!("{}", "-".repeat(100));
+                for snipe in state.snipes.values() {
+                    let status = match &snipe.status {
+                        SnipeStatus::Watching => "watching".to_string(),
+                        SnipeStatus::Bought { tx_hash, position } => match position {
+                            Some(position) => {
+                                format!("bought {:?}, position {}", tx_hash, position)
+                            }
+                            None => format!("bought {:?}", tx_hash),
+                        },
+                        SnipeStatus::Skipped { reason } => format!("skipped: {}", reason),
+                    };
+                    println!(
+                        "{:<6} | {:<8} | {:<10} | {:<42} | {}",
+                        snipe.id,
+                        snipe.chain_id,
+                        snipe.block,
+                        format!("{:?}", snipe.token),
+                        status
+                    );
+                }
+            }
+            TradeRequest::Follow {
+                address,
+                scale,
+                max_per_trade,
+                tokens_allowlist,
+                chain_id,
+                paper,
+            } => {
+                let chain_id = state.chain_id(chain_id)?;
+                let chain = state.chain(chain_id)?.clone();
+                let address = EthAddress::from_str(&address)?;
+                if state
+                    .follows
+                    .values()
+                    .any(|f| f.chain_id == chain_id && f.address == address)
+                {
+                    return Err(anyhow::anyhow!(
+                        "already following {:?} on chain {}",
+                        address,
+                        chain_id
+                    ));
+                }
+                let tokens_allowlist = tokens_allowlist
+                    .iter()
+                    .map(|token| chain.token(token))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+
+                let id = state.next_id();
+                let follow = Follow {
+                    id,
+                    chain_id,
+                    address,
+                    scale,
+                    max_per_trade: U256::from_str(&max_per_trade)?,
+                    tokens_allowlist,
+                    paper: paper || state.paper.enabled,
+                    copied: 0,
+                    skipped: 0,
+                    recent_txs: Default::default(),
+                };
+                follow.validate()?;
+                subscribe_follow(subs, chain_id, address)?;
+                println!(
+                    "trader: follow {} copying {:?} on {} at {}x, up to {} ETH a trade",
+                    id,
+                    address,
+                    chain.name,
+                    scale,
+                    to_units(follow.max_per_trade, 18)
+                );
+                state.follows.insert(id, follow);
+                state.save()?;
+            }
+            TradeRequest::Unfollow { id } => {
+                let follow = state
+                    .follows
+                    .remove(&id)
+                    .ok_or_else(|| anyhow::anyhow!("no follow {}", id))?;
+                unsubscribe_follow(subs, follow.chain_id, follow.address);
+                state.save()?;
+                println!("trader: stopped following {:?}", follow.address);
+            }
+            TradeRequest::ListFollows => {
+                println!(
+                    "{:<6} | {:<8} | {:<42} | {:<8} | {:<14} | {:<6} | {:<7} | {}",
+                    "ID", "Chain", "Address", "Scale", "Max (ETH)", "Copied", "Skipped", "Tokens"
+                );
+                println!("{}", "-".repeat(120));
+                for follow in state.follows.values() {
+                    let tokens = if follow.tokens_allowlist.is_empty() {
+                        "any".to_string()
+                    } else {
+                        format!("{:?}", follow.tokens_allowlist)
+                    };
+                    println!(
+                        "{:<6} | {:<8} | {:<42} | {:<8} | {:<14} | {:<6} | {:<7} | {}{}",
+                        follow.id,
+                        follow.chain_id,
+                        format!("{:?}", follow.address),
+                        follow.scale,
+                        to_units(follow.max_per_trade, 18),
+                        follow.copied,
+                        follow.skipped,
+                        tokens,
+                        if follow.paper { " (paper)" } else { "" }
+                    );
+                }
+            }
+            TradeRequest::SetRiskLimits { limits } => {
+                if limits.max_trades_per_tick == 0 && !limits.halted {
+                    println!("trader: max_trades_per_tick is 0, strategies can't trade");
+                }
+                println!("trader: risk limits now {:?}", limits);
+                state.risk = limits;
+                state.save()?;
+            }
+            TradeRequest::SetPaperMode { enabled } => {
+                state.paper.enabled = enabled;
+                state.save()?;
+                if enabled {
+                    println!("trader: paper mode on, new trades go to the paper book, what's already set up keeps its mode");
+                } else {
+                    println!("trader: paper mode off, trades not set up as paper go live");
+                }
+            }
+            TradeRequest::FundPaper { amount, chain_id } => {
+                let amount = U256::from_str(&amount)?;
+                let chain_id = state.chain_id(chain_id)?;
+                state.chain(chain_id)?;
+                state.paper.fund(chain_id, amount);
+                state.save()?;
+                println!(
+                    "trader: paper book on chain {} has {} wei",
+                    chain_id, amount
+                );
+            }
+            TradeRequest::PaperStatus { chain_id } => {
+                let chain_id = state.chain_id(chain_id)?;
+                let chain = state.chain(chain_id)?;
+                let dexes = dexes.get(chain)?;
+                let eth = state.paper.eth(chain_id);
+                let mut equity = to_units(eth, 18);
 
-                let sig = wallet.sign_transaction_sync(&mut tx)?;
-                let signed_tx = tx.into_signed(sig);
+                println!(
+                    "paper book on {} ({})",
+                    chain.name,
+                    if state.paper.enabled {
+                        "paper mode on"
+                    } else {
+                        "paper mode off"
+                    }
+                );
+                println!("+--------------------------------------------+----------------------+----------------------+");
+                println!("| Token                                      | Balance              | Value (ETH)          |");
+                println!("+--------------------------------------------+----------------------+----------------------+");
+                println!(
+                    "| {:<42} | {:<20} | {:<20} |",
+                    "ETH",
+                    eth,
+                    to_units(eth, 18)
+                );
+                let tokens = state
+                    .paper
+                    .tokens
+                    .get(&chain_id)
+                    .cloned()
+                    .unwrap_or_default();
+                for (token, balance) in tokens {
+                    if balance == U256::ZERO {
+                        continue;
+                    }
+                    let value = get_erc20_info(chain_id, token).and_then(|(decimals, _)| {
+                        let decimals = decimals.to::<u8>();
+                        let price = watcher.prices.weth_price(chain, dexes, token, decimals)?;
+                        Ok(to_units(balance, decimals) * price)
+                    });
+                    let value = match value {
+                        Ok(value) => {
+                            equity += value;
+                            value.to_string()
+                        }
+                        Err(_) => "no price".to_string(),
+                    };
+                    println!(
+                        "| {:<42} | {:<20} | {:<20} |",
+                        format!("{:?}", token),
+                        balance,
+                        value
+                    );
+                }
+                println!("+--------------------------------------------+----------------------+----------------------+");
+
+                let funded = to_units(
+                    state
+                        .paper
+                        .funded
+                        .get(&chain_id)
+                        .copied()
+                        .unwrap_or_default(),
+                    18,
+                );
+                let trades = state
+                    .paper
+                    .trades
+                    .iter()
+                    .filter(|t| t.chain_id == chain_id)
+                    .count();
+                println!(
+                    "equity {} ETH, funded {} ETH, {:+} ETH over {} trades",
+                    equity,
+                    funded,
+                    equity - funded,
+                    trades
+                );
+            }
+            TradeRequest::RecordDataset {
+                name,
+                token,
+                from_block,
+                to_block,
+                step,
+                chain_id,
+            } => {
+                let token = EthAddress::from_str(&token)?;
+                let chain_id = state.chain_id(chain_id)?;
+                let chain = state.chain(chain_id)?;
+                let dataset = Dataset::new(chain, dexes.get(chain)?, &name, token)?;
+                let id = state.next_id();
+                let recording = Recording::new(id, dataset, from_block, to_block, step)?;
+                recording.arm()?;
+                println!(
+                    "trader: recording {} {} samples of {} on {}, it's saved once done",
+                    id, recording.remaining, recording.dataset.symbol, recording.dataset.dex
+                );
+                state.recordings.insert(id, recording);
+                state.save()?;
+            }
+            TradeRequest::Backtest {
+                dataset,
+                subject,
+                eth,
+                gas_price,
+            } => {
+                let dataset = Dataset::load(our, &dataset)?;
+                let gas_price = match gas_price {
+                    Some(gas_price) => U256::from_str(&gas_price)?,
+                    None => dataset.gas_price,
+                };
+                let report = run_backtest(&dataset, subject, U256::from_str(&eth)?, gas_price)?;
+                report.print();
+                println!("trader: report saved to {}", report.save(our)?);
+            }
+            TradeRequest::Watch {
+                token,
+                backfill_blocks,
+                chain_id,
+            } => {
+                let chain_id = state.chain_id(chain_id)?;
+                let chain = state.chain(chain_id)?.clone();
+                let token = chain.token(&token)?;
+                if token == chain.weth {
+                    return Err(anyhow::anyhow!("WETH is what everything is priced in"));
+                }
+                if state
+                    .watchlist
+                    .values()
+                    .any(|w| w.chain_id == chain_id && w.token == token)
+                {
+                    return Err(anyhow::anyhow!("{:?} is already watched", token));
+                }
+                // the feed does V2 math on the reserves, so only pools that use it.
+                let (dex, pair) = dexes
+                    .get(&chain)?
+                    .iter()
+                    .filter(|dex| dex.fee_bps().is_some())
+                    .find_map(|dex| {
+                        dex.get_pair(chain.weth, token)
+                            .ok()
+                            .flatten()
+                            .map(|pair| (dex.name().to_string(), pair))
+                    })
+                    .ok_or_else(|| anyhow::anyhow!("no V2 WETH pair for {:?}", token))?;
+                let (decimals, symbol) = get_erc20_info(chain_id, token)?;
+
+                watcher.prices.watch(subs, chain_id, token, pair)?;
+                let id = state.next_id();
+                println!(
+                    "trader: watching {} ({:?}) on {}, id {}",
+                    symbol, pair, dex, id
+                );
+                let entry = WatchEntry {
+                    id,
+                    chain_id,
+                    token,
+                    symbol,
+                    decimals: decimals.to::<u8>(),
+                    dex,
+                    pair,
+                };
+                // the entry is still worth keeping without its history.
+                let blocks = backfill_blocks.unwrap_or(DEFAULT_BACKFILL_BLOCKS);
+                match watcher.candles.backfill(&chain, &entry, blocks) {
+                    Ok(trades) => println!(
+                        "trader: backfilled candles from {} swaps over {} blocks",
+                        trades, blocks
+                    ),
+                    Err(e) => println!("trader: couldn't backfill candles: {:?}", e),
+                }
+                state.watchlist.insert(id, entry);
+                state.save()?;
+            }
+            TradeRequest::Unwatch { id } => {
+                let entry = state
+                    .watchlist
+                    .remove(&id)
+                    .ok_or_else(|| anyhow::anyhow!("no watchlist entry {}", id))?;
+                watcher
+                    .prices
+                    .unwatch(subs, entry.chain_id, entry.token, entry.pair);
+                state.save()?;
+                println!("trader: stopped watching {}", entry.symbol);
+            }
+            TradeRequest::Watchlist => {
+                println!(
+                    "{:<6} | {:<8} | {:<10} | {:<14} | {:<22} | {:<10} | {}",
+                    "ID", "Chain", "Symbol", "Dex", "Price (ETH)", "Block", "Feed"
+                );
+                println!("{}", "-".repeat(96));
+                for entry in state.watchlist.values() {
+                    let chain = state.chain(entry.chain_id)?;
+                    let price = watcher
+                        .prices
+                        .weth_price(chain, dexes.get(chain)?, entry.token, entry.decimals)
+                        .map(|price| price.to_string())
+                        .unwrap_or_else(|e| format!("{:?}", e));
+                    let block = watcher
+                        .prices
+                        .reserves(entry.chain_id, entry.pair)
+                        .map(|r| r.block.to_string())
+                        .unwrap_or_default();
+                    println!(
+                        "{:<6} | {:<8} | {:<10} | {:<14} | {:<22} | {:<10} | {}",
+                        entry.id,
+                        entry.chain_id,
+                        entry.symbol,
+                        entry.dex,
+                        price,
+                        block,
+                        if watcher.prices.is_live(entry.chain_id, entry.pair) {
+                            "live"
+                        } else {
+                            "polled"
+                        }
+                    );
+                }
+            }
+            TradeRequest::GetCandles {
+                pair,
+                interval,
+                from,
+                to,
+                chain_id,
+            } => {
+                let chain_id = state.chain_id(chain_id)?;
+                let pair = EthAddress::from_str(&pair)?;
+                let entry = state
+                    .watchlist
+                    .values()
+                    .find(|w| w.chain_id == chain_id && w.pair == pair);
+                let symbol = entry.map(|w| w.symbol.as_str()).unwrap_or("token");
+                let candles = watcher
+                    .candles
+                    .candles(chain_id, pair, interval, from, to)?;
+
+                println!(
+                    "{} candles of {:?} ({}/WETH), {} of them",
+                    interval.name(),
+                    pair,
+                    symbol,
+                    candles.len()
+                );
+                println!(
+                    "{:<12} | {:<14} | {:<14} | {:<14} | {:<14} | {:<16} | {:<14} | {}",
+                    "Open time", "Open", "High", "Low", "Close", "Volume", "Volume (ETH)", "Trades"
+                );
+                println!("{}", "-".repeat(130));
+                for candle in candles {
+                    println!(
+                        "{:<12} | {:<14.8e} | {:<14.8e} | {:<14.8e} | {:<14.8e} | {:<16.4} | {:<14.6} | {}",
+                        candle.open_time,
+                        candle.open,
+                        candle.high,
+                        candle.low,
+                        candle.close,
+                        candle.volume,
+                        candle.volume_eth,
+                        candle.trades
+                    );
+                }
+            }
+            TradeRequest::Chart {
+                token,
+                interval,
+                range,
+                style,
+                chain_id,
+            } => {
+                let chain_id = state.chain_id(chain_id)?;
+                let chain = state.chain(chain_id)?.clone();
+                let token = chain.token(&token)?;
+                let secs = parse_range(&range)?;
+                if secs / interval.secs() > MAX_COLUMNS {
+                    return Err(anyhow::anyhow!(
+                        "{} is more than {} {} candles, use a bigger interval",
+                        range,
+                        MAX_COLUMNS,
+                        interval.name()
+                    ));
+                }
+                let now = now_secs()?;
+                let from = now.saturating_sub(secs);
 
-                // is this necessary?
-                let mut buf = vec![];
-                signed_tx.encode_signed(&mut buf);
+                let entry = state
+                    .watchlist
+                    .values()
+                    .find(|w| w.chain_id == chain_id && w.token == token);
+                let stored = match entry {
+                    Some(entry) => fill_gaps(
+                        &watcher
+                            .candles
+                            .candles(chain_id, entry.pair, interval, from, now)?,
+                        interval,
+                        from,
+                        now,
+                    ),
+                    None => vec![],
+                };
+                let (candles, symbol, source) = match entry {
+                    Some(entry) if !stored.is_empty() => {
+                        (stored, entry.symbol.clone(), "stored candles")
+                    }
+                    _ => {
+                        let (decimals, symbol) = get_erc20_info(chain_id, token)?;
+                        let candles = sample_candles(
+                            &chain,
+                            dexes.get(&chain)?,
+                            token,
+                            decimals.to::<u8>(),
+                            interval,
+                            from,
+                        )?;
+                        (candles, symbol, "sampled reserves")
+                    }
+                };
+                print_chart(
+                    &format!(
+                        "{}/WETH over {}, {} candles from {}",
+                        symbol,
+                        range,
+                        interval.name(),
+                        source
+                    ),
+                    &candles,
+                    style,
+                );
+            }
+            TradeRequest::CreateAlert {
+                token,
+                condition,
+                value,
+                cooldown,
+                chain_id,
+            } => {
+                let chain_id = state.chain_id(chain_id)?;
+                let chain = state.chain(chain_id)?.clone();
+                let token = chain.token(&token)?;
+                let (decimals, symbol) = get_erc20_info(chain_id, token)?;
+                let decimals = decimals.to::<u8>();
+                let reference =
+                    watcher
+                        .prices
+                        .weth_price(&chain, dexes.get(&chain)?, token, decimals)?;
 
-                let tx_hash = send_raw_transaction(buf.into())?;
-                println!("sent! with tx_hash {:?}", tx_hash);
+                let id = state.next_id();
+                let alert = Alert {
+                    id,
+                    chain_id,
+                    token,
+                    symbol,
+                    decimals,
+                    condition,
+                    value,
+                    reference,
+                    cooldown,
+                    last_fired: None,
+                    fired: 0,
+                    active: true,
+                };
+                alert.validate()?;
+                println!(
+                    "trader: alert {} set, {} (now {} ETH)",
+                    id,
+                    alert.describe(),
+                    reference
+                );
+                if state.alert_target.is_none() {
+                    println!("trader: no alert target set, alerts will only be printed");
+                }
+                state.alerts.insert(id, alert);
+                state.save()?;
+            }
+            TradeRequest::ListAlerts => {
+                println!(
+                    "target: {}",
+                    state.alert_target.as_deref().unwrap_or("none")
+                );
+                println!(
+                    "{:<6} | {:<8} | {:<8} | {:<12} | {:<6} | {}",
+                    "ID", "Chain", "Status", "Cooldown", "Fired", "Alert"
+                );
+                println!("{}", "-".repeat(80));
+                for alert in state.alerts.values() {
+                    println!(
+                        "{:<6} | {:<8} | {:<8} | {:<12} | {:<6} | {}",
+                        alert.id,
+                        alert.chain_id,
+                        if alert.active { "active" } else { "done" },
+                        alert
+                            .cooldown
+                            .map(|c| format!("{}s", c))
+                            .unwrap_or_else(|| "once".to_string()),
+                        alert.fired,
+                        alert.describe()
+                    );
+                }
+            }
+            TradeRequest::DeleteAlert { id } => {
+                state
+                    .alerts
+                    .remove(&id)
+                    .ok_or_else(|| anyhow::anyhow!("no alert {}", id))?;
+                state.save()?;
+                println!("trader: deleted alert {}", id);
+            }
+            TradeRequest::SetAlertTarget { target } => {
+                if let Some(target) = &target {
+                    parse_target(target)?;
+                }
+                match &target {
+                    Some(target) => println!("trader: alerts go to {}", target),
+                    None => println!("trader: alerts are only printed"),
+                }
+                state.alert_target = target;
+                state.save()?;
+            }
+            TradeRequest::SetWatcher { poll_blocks } => {
+                if poll_blocks == 0 {
+                    return Err(anyhow::anyhow!("poll_blocks should be at least 1"));
+                }
+                state.watcher.poll_blocks = poll_blocks;
+                state.save()?;
+                println!("trader: checking prices every {} blocks", poll_blocks);
+            }
+            TradeRequest::SetChainConfig { config } => {
+                config.validate()?;
+                let chain_id = config.chain_id;
+                println!(
+                    "trader: set config for chain {} ({})",
+                    chain_id, config.name
+                );
+
+                state.chains.insert(chain_id, config);
+                state.custom_chains.insert(chain_id);
+                if state.default_chain.is_none() {
+                    state.default_chain = Some(chain_id);
+                }
+                state.save()?;
+                // dexes get re-verified against the new config on next use.
+                dexes.unload(chain_id);
+            }
+            TradeRequest::SetDefaultChain { chain_id } => {
+                let chain = state.chain(chain_id)?;
+                println!("trader: default chain is now {} ({})", chain_id, chain.name);
+
+                state.default_chain = Some(chain_id);
+                state.save()?;
             }
         },
     }
     Ok(())
 }
 
+/// sends move real ETH out of the wallet and have no paper version, refuse them rather than go live by surprise.
+fn live_only(state: &State) -> anyhow::Result<()> {
+    if state.paper.enabled {
+        return Err(anyhow::anyhow!(
+            "paper mode is on, this request would use the real wallet"
+        ));
+    }
+    Ok(())
+}
+
+/// a plain ETH transfer at the current gas price.
+fn eth_transfer_tx(
+    from: EthAddress,
+    chain_id: u64,
+    to: EthAddress,
+    value: U256,
+) -> anyhow::Result<alloy_consensus::TxLegacy> {
+    let provider = eth_provider(chain_id);
+    let gas_price = provider.get_gas_price()?;
+    let nonce = pending_nonce(&provider, from)?;
+
+    Ok(alloy_consensus::TxLegacy {
+        nonce: nonce.to::<u64>(),
+        gas_price: gas_price.to::<u128>(),
+        gas_limit: 21000,
+        to: TxKind::Call(to), // Use `TxKind::Call` with the recipient's address
+        value,
+        input: Bytes::default(),
+        chain_id: Some(chain_id),
+    })
+}
+
+fn print_tx_cost(cost: &TxCost) {
+    println!(
+        "estimated fee {} wei ({} execution + {} L1 data)",
+        cost.total(),
+        cost.l2_fee,
+        cost.l1_fee
+    );
+}
+
 call_init!(init);
 fn init(our: Address) {
     println!("trader: begin");
 
+    let mut state = match State::load() {
+        Ok(state) => state,
+        Err(e) => {
+            println!(
+                "trader: can't read the saved state, stopping so it isn't overwritten: {:?}",
+                e
+            );
+            return;
+        }
+    };
+
     // this block is essentially a messy CLI initialization app,
     // todo fix it up.
     // also todo, save pk in file, store bookmarks etc in state.
     let mut wallet = loop {
         let temp_wallet: Option<Wallet<SigningKey>>;
 
-        if let Some(encrypted_state) = get_state() {
+        if let Some(encrypted_state) = state.wallet.clone() {
             println!("Enter password to unlock wallet:");
             let password_msg = await_message().unwrap();
             let password_str =
@@ -211,7 +2516,10 @@ fn init(our: Address) {
             let password_str = String::from_utf8(password_msg.body().to_vec()).unwrap();
 
             let encrypted_wallet_data = encrypt_data(wallet_data_str.as_bytes(), &password_str);
-            set_state(&encrypted_wallet_data);
+            state.wallet = Some(encrypted_wallet_data);
+            if let Err(e) = state.save() {
+                println!("trader: failed to save state: {:?}", e);
+            }
 
             if let Ok(parsed_wallet) = wallet_data_str.parse::<LocalWallet>() {
                 println!(
@@ -227,8 +2535,92 @@ fn init(our: Address) {
     }
     .expect("Failed to initialize wallet");
 
+    // every start, so additions to the shipped chains.json reach existing installs too.
+    let shipped = match load_package_chains(&our) {
+        Ok(chains) => chains,
+        Err(e) => {
+            println!("trader: couldn't load chains.json: {:?}", e);
+            vec![]
+        }
+    };
+    state.merge_chains(shipped);
+    if let Err(e) = state.save() {
+        println!("trader: failed to save state: {:?}", e);
+    }
+
+    // check the configured dexes for the default chain up front, others load on first use.
+    let mut dexes = DexRegistry::default();
+    if let Err(e) = state
+        .chain_id(None)
+        .and_then(|chain_id| state.chain(chain_id))
+        .and_then(|chain| dexes.load(chain))
+    {
+        println!("trader: couldn't load dexes: {:?}", e);
+    }
+
+    let mut watcher = Watcher::default();
+    if let Err(e) = watcher.candles.open(&our) {
+        println!("trader: couldn't open the candle store: {:?}", e);
+    }
+    if let Err(e) = schedule(&TimerContext::Watch, WATCH_TICK_MS) {
+        println!("trader: couldn't start the watcher: {:?}", e);
+    }
+    // timers don't survive a restart, schedules that were due while we were down run now.
+    for dca in state.dcas.values() {
+        if dca.status == DcaStatus::Active {
+            if let Err(e) = dca.arm() {
+                println!("trader: couldn't resume dca {}: {:?}", dca.id, e);
+            }
+        }
+    }
+    for twap in state.twaps.values() {
+        if twap.status == TwapStatus::Running {
+            if let Err(e) = twap.arm() {
+                println!("trader: couldn't resume twap {}: {:?}", twap.id, e);
+            }
+        }
+    }
+    for recording in state.recordings.values() {
+        if let Err(e) = recording.arm() {
+            println!(
+                "trader: couldn't resume recording {}: {:?}",
+                recording.id, e
+            );
+        }
+    }
+
+    // subscriptions don't survive a restart either.
+    let mut subs = Subscriptions::default();
+    for entry in state.watchlist.values() {
+        if let Err(e) = watcher
+            .prices
+            .watch(&mut subs, entry.chain_id, entry.token, entry.pair)
+        {
+            println!(
+                "trader: couldn't watch {}, polling it: {:?}",
+                entry.symbol, e
+            );
+        }
+    }
+    if let Some(config) = &state.sniper {
+        if let Err(e) = state
+            .chain(config.chain_id)
+            .and_then(|chain| subscribe_pairs(&mut subs, chain))
+        {
+            println!("trader: couldn't restart the sniper: {:?}", e);
+        }
+    }
+    subscribe_follows(&mut subs, &state);
+
     loop {
-        match handle_message(&our, &mut wallet) {
+        match handle_message(
+            &our,
+            &mut wallet,
+            &mut state,
+            &mut dexes,
+            &mut watcher,
+            &mut subs,
+        ) {
             Ok(()) => {}
             Err(e) => {
                 println!("trader: error: {:?}", e);
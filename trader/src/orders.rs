@@ -0,0 +1,278 @@
+use alloy_signer::{k256::ecdsa::SigningKey, Wallet};
+use kinode_process_lib::{
+    eth::{Address as EthAddress, TxHash, U256},
+    println,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::feed::PriceFeed;
+use crate::helpers::{
+    calls::{get_tx_status, TxStatus},
+    dex::DexRegistry,
+};
+use crate::state::State;
+use crate::trade::{execute_buy, execute_sell, Account};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum OrderStatus {
+    Open,
+    /// sent, waiting for the receipt.
+    Pending {
+        tx_hash: TxHash,
+    },
+    /// mined and succeeded.
+    Filled {
+        tx_hash: TxHash,
+    },
+    Expired,
+    Cancelled,
+}
+
+/// buy or sell token once its price in ETH crosses limit_price.
+/// a buy spends amount wei of ETH, a sell sells amount of token (raw units).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LimitOrder {
+    pub id: u64,
+    pub chain_id: u64,
+    /// trades against the paper book instead of the wallet.
+    #[serde(default)]
+    pub paper: bool,
+    pub token: EthAddress,
+    pub symbol: String,
+    pub decimals: u8,
+    pub side: Side,
+    pub amount: U256,
+    /// ETH per whole token.
+    pub limit_price: f64,
+    /// unix seconds, the order is dropped after this.
+    pub expiry: Option<u64>,
+    pub status: OrderStatus,
+}
+
+impl LimitOrder {
+    pub fn is_open(&self) -> bool {
+        self.status == OrderStatus::Open
+    }
+
+    pub fn is_pending(&self) -> bool {
+        matches!(self.status, OrderStatus::Pending { .. })
+    }
+
+    pub fn crossed(&self, price: f64) -> bool {
+        match self.side {
+            Side::Buy => price <= self.limit_price,
+            Side::Sell => price >= self.limit_price,
+        }
+    }
+
+    /// the least we accept back, so the fill is never worse than the limit.
+    pub fn min_out(&self) -> U256 {
+        limit_min_out(self.side, self.amount, self.limit_price, self.decimals)
+    }
+}
+
+/// what amount_in has to get back at limit_price (ETH per whole token), rounded up.
+pub fn limit_min_out(side: Side, amount_in: U256, limit_price: f64, decimals: u8) -> U256 {
+    // wei per whole token.
+    let price = U256::from((limit_price * 1e18) as u128);
+    let unit = U256::from(10u64).pow(U256::from(decimals));
+    if price == U256::ZERO {
+        return U256::ZERO;
+    }
+    let (num, den) = match side {
+        Side::Buy => (amount_in * unit, price),
+        Side::Sell => (amount_in * price, unit),
+    };
+    (num + den - U256::from(1)) / den
+}
+
+pub fn now_secs() -> anyhow::Result<u64> {
+    Ok(std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs())
+}
+
+/// looks at every open order on chain_id: expires old ones, fills the ones whose limit is crossed.
+/// a fill that fails, e.g. because the quote is worse than the limit, is retried next poll.
+/// sent orders stay pending until their receipt, a reverted or dropped one opens again.
+pub fn check_orders(
+    wallet: &Wallet<SigningKey>,
+    state: &mut State,
+    dexes: &mut DexRegistry,
+    prices: &mut PriceFeed,
+    chain_id: u64,
+) -> anyhow::Result<()> {
+    let settled = settle_orders(state, chain_id);
+
+    let ids: Vec<u64> = state
+        .orders
+        .values()
+        .filter(|o| o.is_open() && o.chain_id == chain_id)
+        .map(|o| o.id)
+        .collect();
+    if ids.is_empty() {
+        return if settled { state.save() } else { Ok(()) };
+    }
+
+    let chain = state.chain(chain_id)?.clone();
+    let dexes = dexes.get(&chain)?;
+    let now = now_secs()?;
+
+    for id in ids {
+        let Some(order) = state.orders.get_mut(&id) else {
+            continue;
+        };
+        if order.expiry.is_some_and(|expiry| now >= expiry) {
+            println!("trader: order {} expired", id);
+            order.status = OrderStatus::Expired;
+            continue;
+        }
+
+        let price = match prices.weth_price(&chain, dexes, order.token, order.decimals) {
+            Ok(price) => price,
+            Err(e) => {
+                println!("trader: no price for order {}: {:?}", id, e);
+                continue;
+            }
+        };
+        if !order.crossed(price) {
+            continue;
+        }
+        println!(
+            "trader: order {} triggered, {} at {} ETH (limit {})",
+            id, order.symbol, price, order.limit_price
+        );
+
+        let min_out = order.min_out();
+        let mut account = Account::new(wallet, &mut state.paper, order.paper);
+        let paper = matches!(account, Account::Paper(_));
+        let fill = match order.side {
+            Side::Buy => execute_buy(
+                &mut account,
+                &chain,
+                dexes,
+                order.token,
+                order.amount,
+                min_out,
+            ),
+            Side::Sell => execute_sell(
+                &mut account,
+                &chain,
+                dexes,
+                order.token,
+                order.amount,
+                min_out,
+            ),
+        };
+        match fill {
+            // paper fills are final the moment they're booked.
+            Ok(fill) if paper => {
+                println!("trader: order {} filled on paper via {}", id, fill.venue);
+                order.status = OrderStatus::Filled {
+                    tx_hash: fill.tx_hash,
+                };
+            }
+            Ok(fill) => {
+                println!(
+                    "trader: order {} sent on {}! {}",
+                    id,
+                    fill.venue,
+                    chain.tx_url(fill.tx_hash)
+                );
+                order.status = OrderStatus::Pending {
+                    tx_hash: fill.tx_hash,
+                };
+            }
+            Err(e) => println!("trader: order {} not filled, will retry: {:?}", id, e),
+        }
+    }
+
+    state.save()
+}
+
+/// moves pending orders on chain_id along by their receipts.
+/// a lookup that fails leaves the order pending for the next poll. true if any order moved.
+fn settle_orders(state: &mut State, chain_id: u64) -> bool {
+    let mut settled = false;
+    for order in state.orders.values_mut() {
+        let OrderStatus::Pending { tx_hash } = order.status else {
+            continue;
+        };
+        if order.chain_id != chain_id {
+            continue;
+        }
+        match get_tx_status(chain_id, tx_hash) {
+            Ok(TxStatus::Pending) => continue,
+            Ok(TxStatus::Mined { success: true, .. }) => {
+                println!("trader: order {} filled, {:?} mined", order.id, tx_hash);
+                order.status = OrderStatus::Filled { tx_hash };
+            }
+            Ok(TxStatus::Mined { success: false, .. }) => {
+                println!(
+                    "trader: order {} reverted in {:?}, open again",
+                    order.id, tx_hash
+                );
+                order.status = OrderStatus::Open;
+            }
+            Ok(TxStatus::Dropped) => {
+                println!(
+                    "trader: order {} tx {:?} was dropped, open again",
+                    order.id, tx_hash
+                );
+                order.status = OrderStatus::Open;
+            }
+            Err(e) => {
+                println!("trader: no receipt for order {}: {:?}", order.id, e);
+                continue;
+            }
+        }
+        settled = true;
+    }
+    settled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ETH: u64 = 1_000_000_000_000_000_000;
+
+    #[test]
+    fn buys_need_amount_over_price_tokens() {
+        let min_out = limit_min_out(Side::Buy, U256::from(ETH), 0.5, 18);
+        assert_eq!(min_out, U256::from(2 * ETH));
+        // 6 decimals, 0.0004 ETH per token is 2500 tokens for an ETH.
+        let min_out = limit_min_out(Side::Buy, U256::from(ETH), 0.0004, 6);
+        assert_eq!(min_out, U256::from(2_500_000_000u64));
+    }
+
+    #[test]
+    fn sells_need_amount_times_price_wei() {
+        let min_out = limit_min_out(Side::Sell, U256::from(2 * ETH), 0.5, 18);
+        assert_eq!(min_out, U256::from(ETH));
+    }
+
+    #[test]
+    fn rounds_up() {
+        // a third of a token unit, never less than what the limit asks for.
+        assert_eq!(
+            limit_min_out(Side::Buy, U256::from(1), 3.0, 18),
+            U256::from(1)
+        );
+        assert_eq!(limit_min_out(Side::Buy, U256::ZERO, 3.0, 18), U256::ZERO);
+    }
+
+    #[test]
+    fn no_price_means_no_minimum() {
+        assert_eq!(
+            limit_min_out(Side::Sell, U256::from(ETH), 0.0, 18),
+            U256::ZERO
+        );
+    }
+}
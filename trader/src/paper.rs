@@ -0,0 +1,381 @@
+use kinode_process_lib::{
+    eth::{Address as EthAddress, TxHash, U256},
+    println,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::helpers::{
+    calls::{get_reserves, v2_amount_out},
+    chains::ChainConfig,
+    dex::{dex_by_name, Dex},
+    router::{find_best_route, Route},
+};
+use crate::orders::{now_secs, Side};
+use crate::trade::Fill;
+
+/// a swap filled against live reserves with nothing signed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaperTrade {
+    pub id: u64,
+    pub chain_id: u64,
+    pub token: EthAddress,
+    pub side: Side,
+    pub venue: String,
+    /// what a Swap between two tokens paid with, None when it was ETH.
+    #[serde(default)]
+    pub paid_with: Option<EthAddress>,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    /// what the tx would have cost, taken from the virtual ETH balance.
+    pub gas_wei: U256,
+    pub at: u64,
+}
+
+/// virtual balances for paper trading, per chain. nothing here touches the real wallet.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PaperBook {
+    /// what gets set up while this is on trades paper whatever the request said,
+    /// and one-off trades like Swap fill here instead of in the wallet.
+    pub enabled: bool,
+    /// virtual wei per chain.
+    pub eth: BTreeMap<u64, U256>,
+    /// what was put in with FundPaper, to measure the result against.
+    pub funded: BTreeMap<u64, U256>,
+    pub tokens: BTreeMap<u64, BTreeMap<EthAddress, U256>>,
+    pub trades: Vec<PaperTrade>,
+}
+
+impl PaperBook {
+    /// replaces the chain's virtual ETH, token balances are kept.
+    pub fn fund(&mut self, chain_id: u64, amount: U256) {
+        self.eth.insert(chain_id, amount);
+        self.funded.insert(chain_id, amount);
+    }
+
+    pub fn eth(&self, chain_id: u64) -> U256 {
+        self.eth.get(&chain_id).copied().unwrap_or_default()
+    }
+
+    pub fn balance(&self, chain_id: u64, token: EthAddress) -> U256 {
+        self.tokens
+            .get(&chain_id)
+            .and_then(|tokens| tokens.get(&token))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// fills a buy of token with amount_in wei of virtual ETH, like execute_buy but V2 routes only.
+    pub fn buy(
+        &mut self,
+        chain: &ChainConfig,
+        dexes: &[Box<dyn Dex>],
+        token: EthAddress,
+        amount_in: U256,
+        min_out: U256,
+    ) -> anyhow::Result<Fill> {
+        let gas_price = chain.provider().get_gas_price()?;
+        let route = find_best_route(chain, dexes, chain.weth, token, amount_in, gas_price)?;
+        let gas_wei = route.cost_wei(gas_price);
+
+        let eth = self.eth(chain.chain_id);
+        if eth < amount_in + gas_wei {
+            return Err(anyhow::anyhow!(
+                "paper wallet has {} wei, needs {} plus {} for gas",
+                eth,
+                amount_in,
+                gas_wei
+            ));
+        }
+        let amount_out = fill_route(chain, dexes, &route)?;
+        check_floor(amount_out, min_out)?;
+
+        self.eth.insert(chain.chain_id, eth - amount_in - gas_wei);
+        *self
+            .tokens
+            .entry(chain.chain_id)
+            .or_default()
+            .entry(token)
+            .or_default() += amount_out;
+        self.record(chain, token, Side::Buy, &route, amount_out, gas_wei)
+    }
+
+    /// fills a sell of amount_in virtual token for ETH, like execute_sell.
+    pub fn sell(
+        &mut self,
+        chain: &ChainConfig,
+        dexes: &[Box<dyn Dex>],
+        token: EthAddress,
+        amount_in: U256,
+        min_out: U256,
+    ) -> anyhow::Result<Fill> {
+        let held = self.balance(chain.chain_id, token);
+        if held < amount_in {
+            return Err(anyhow::anyhow!(
+                "paper wallet holds {} of {:?}, can't sell {}",
+                held,
+                token,
+                amount_in
+            ));
+        }
+        let gas_price = chain.provider().get_gas_price()?;
+        let route = find_best_route(chain, dexes, token, chain.weth, amount_in, gas_price)?;
+        let gas_wei = route.cost_wei(gas_price);
+        let amount_out = fill_route(chain, dexes, &route)?;
+        check_floor(amount_out, min_out)?;
+
+        let eth = self.eth(chain.chain_id) + amount_out;
+        if eth < gas_wei {
+            return Err(anyhow::anyhow!(
+                "paper wallet can't pay {} wei of gas",
+                gas_wei
+            ));
+        }
+        self.eth.insert(chain.chain_id, eth - gas_wei);
+        self.tokens
+            .entry(chain.chain_id)
+            .or_default()
+            .insert(token, held - amount_in);
+        self.record(chain, token, Side::Sell, &route, amount_out, gas_wei)
+    }
+
+    /// fills a swap of amount_in virtual from for to, like Swap but V2 routes only.
+    /// both sides are token balances, WETH included, the gas comes out of the virtual ETH.
+    pub fn swap(
+        &mut self,
+        chain: &ChainConfig,
+        dexes: &[Box<dyn Dex>],
+        from: EthAddress,
+        to: EthAddress,
+        amount_in: U256,
+    ) -> anyhow::Result<Fill> {
+        let held = self.balance(chain.chain_id, from);
+        if held < amount_in {
+            return Err(anyhow::anyhow!(
+                "paper wallet holds {} of {:?}, can't swap {}",
+                held,
+                from,
+                amount_in
+            ));
+        }
+        let gas_price = chain.provider().get_gas_price()?;
+        let route = find_best_route(chain, dexes, from, to, amount_in, gas_price)?;
+        let gas_wei = route.cost_wei(gas_price);
+        let eth = self.eth(chain.chain_id);
+        if eth < gas_wei {
+            return Err(anyhow::anyhow!(
+                "paper wallet can't pay {} wei of gas",
+                gas_wei
+            ));
+        }
+        let amount_out = fill_route(chain, dexes, &route)?;
+
+        self.eth.insert(chain.chain_id, eth - gas_wei);
+        let tokens = self.tokens.entry(chain.chain_id).or_default();
+        tokens.insert(from, held - amount_in);
+        *tokens.entry(to).or_default() += amount_out;
+        let fill = self.record(chain, to, Side::Buy, &route, amount_out, gas_wei)?;
+        if let Some(trade) = self.trades.last_mut() {
+            trade.paid_with = Some(from);
+        }
+        Ok(fill)
+    }
+
+    /// fills a BuyExact along route, which find_cheapest_route priced with the pools' own math,
+    /// so its amount_in is exactly what the fill costs.
+    pub fn buy_exact(
+        &mut self,
+        chain: &ChainConfig,
+        route: &Route,
+        gas_price: U256,
+    ) -> anyhow::Result<Fill> {
+        let token = *route
+            .path
+            .last()
+            .ok_or_else(|| anyhow::anyhow!("empty route"))?;
+        let gas_wei = route.cost_wei(gas_price);
+        let eth = self.eth(chain.chain_id);
+        if eth < route.amount_in + gas_wei {
+            return Err(anyhow::anyhow!(
+                "paper wallet has {} wei, needs {} plus {} for gas",
+                eth,
+                route.amount_in,
+                gas_wei
+            ));
+        }
+
+        self.eth
+            .insert(chain.chain_id, eth - route.amount_in - gas_wei);
+        *self
+            .tokens
+            .entry(chain.chain_id)
+            .or_default()
+            .entry(token)
+            .or_default() += route.amount_out;
+        self.record(chain, token, Side::Buy, route, route.amount_out, gas_wei)
+    }
+
+    /// fills a SellExact along route, priced like in buy_exact.
+    pub fn sell_exact(
+        &mut self,
+        chain: &ChainConfig,
+        route: &Route,
+        gas_price: U256,
+    ) -> anyhow::Result<Fill> {
+        let token = route.path[0];
+        let held = self.balance(chain.chain_id, token);
+        if held < route.amount_in {
+            return Err(anyhow::anyhow!(
+                "paper wallet holds {} of {:?}, the sell needs {}",
+                held,
+                token,
+                route.amount_in
+            ));
+        }
+        let gas_wei = route.cost_wei(gas_price);
+        let eth = self.eth(chain.chain_id) + route.amount_out;
+        if eth < gas_wei {
+            return Err(anyhow::anyhow!(
+                "paper wallet can't pay {} wei of gas",
+                gas_wei
+            ));
+        }
+
+        self.eth.insert(chain.chain_id, eth - gas_wei);
+        self.tokens
+            .entry(chain.chain_id)
+            .or_default()
+            .insert(token, held - route.amount_in);
+        self.record(chain, token, Side::Sell, route, route.amount_out, gas_wei)
+    }
+
+    /// turns amount of virtual ETH into WETH, paying gas_wei for the tx that would have done it.
+    pub fn wrap(&mut self, chain: &ChainConfig, amount: U256, gas_wei: U256) -> anyhow::Result<()> {
+        let eth = self.eth(chain.chain_id);
+        if eth < amount + gas_wei {
+            return Err(anyhow::anyhow!(
+                "paper wallet has {} wei, needs {} plus {} for gas",
+                eth,
+                amount,
+                gas_wei
+            ));
+        }
+        self.eth.insert(chain.chain_id, eth - amount - gas_wei);
+        *self
+            .tokens
+            .entry(chain.chain_id)
+            .or_default()
+            .entry(chain.weth)
+            .or_default() += amount;
+        println!(
+            "trader: paper wrapped {} wei on {}, nothing was sent",
+            amount, chain.name
+        );
+        Ok(())
+    }
+
+    /// turns amount of virtual WETH back into ETH, like wrap.
+    pub fn unwrap(
+        &mut self,
+        chain: &ChainConfig,
+        amount: U256,
+        gas_wei: U256,
+    ) -> anyhow::Result<()> {
+        let weth = self.balance(chain.chain_id, chain.weth);
+        if weth < amount {
+            return Err(anyhow::anyhow!(
+                "paper wallet holds {} WETH, can't unwrap {}",
+                weth,
+                amount
+            ));
+        }
+        let eth = self.eth(chain.chain_id) + amount;
+        if eth < gas_wei {
+            return Err(anyhow::anyhow!(
+                "paper wallet can't pay {} wei of gas",
+                gas_wei
+            ));
+        }
+        self.eth.insert(chain.chain_id, eth - gas_wei);
+        self.tokens
+            .entry(chain.chain_id)
+            .or_default()
+            .insert(chain.weth, weth - amount);
+        println!(
+            "trader: paper unwrapped {} wei on {}, nothing was sent",
+            amount, chain.name
+        );
+        Ok(())
+    }
+
+    fn record(
+        &mut self,
+        chain: &ChainConfig,
+        token: EthAddress,
+        side: Side,
+        route: &Route,
+        amount_out: U256,
+        gas_wei: U256,
+    ) -> anyhow::Result<Fill> {
+        let id = self.trades.len() as u64 + 1;
+        self.trades.push(PaperTrade {
+            id,
+            chain_id: chain.chain_id,
+            token,
+            side,
+            venue: route.dex.clone(),
+            paid_with: None,
+            amount_in: route.amount_in,
+            amount_out,
+            gas_wei,
+            at: now_secs()?,
+        });
+        println!(
+            "trader: paper {:?} {} for {} via {} on {}, nothing was sent",
+            side, route.amount_in, amount_out, route.dex, chain.name
+        );
+        Ok(Fill {
+            // stands in for a tx hash wherever fills are recorded, the paper trade id.
+            tx_hash: TxHash::from(U256::from(id).to_be_bytes::<32>()),
+            venue: format!("{} (paper)", route.dex),
+            amount_in: route.amount_in,
+            quoted_out: amount_out,
+            // paper fills are exact.
+            min_out: amount_out,
+        })
+    }
+}
+
+/// what route's amount_in gets on live reserves, hop by hop with the pool's own math.
+/// pools we can't replicate are filled at their router's quote, which is that same math on-chain.
+pub fn fill_route(
+    chain: &ChainConfig,
+    dexes: &[Box<dyn Dex>],
+    route: &Route,
+) -> anyhow::Result<U256> {
+    let dex = dex_by_name(dexes, &route.dex)?;
+    let Some(fee_bps) = dex.fee_bps() else {
+        return Ok(route.amount_out);
+    };
+    let mut amount = route.amount_in;
+    for hop in route.path.windows(2) {
+        let pair = dex
+            .get_pair(hop[0], hop[1])?
+            .ok_or_else(|| anyhow::anyhow!("{} has no pair for {:?}", dex.name(), hop))?;
+        let (reserve_in, reserve_out) = get_reserves(chain.chain_id, pair, hop[0], None)?;
+        amount = v2_amount_out(amount, reserve_in, reserve_out, fee_bps);
+    }
+    Ok(amount)
+}
+
+fn check_floor(amount_out: U256, min_out: U256) -> anyhow::Result<()> {
+    if amount_out < min_out {
+        return Err(anyhow::anyhow!(
+            "fill {} is below the required minimum {}",
+            amount_out,
+            min_out
+        ));
+    }
+    Ok(())
+}
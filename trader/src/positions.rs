@@ -0,0 +1,328 @@
+use alloy_signer::{k256::ecdsa::SigningKey, Signer, Wallet};
+use kinode_process_lib::{
+    eth::{Address as EthAddress, TxHash, U256},
+    println,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::feed::PriceFeed;
+use crate::helpers::{
+    calls::{get_tx_status, received_in, TxStatus},
+    chains::ChainConfig,
+    dex::{Dex, DexRegistry},
+};
+use crate::state::State;
+use crate::trade::{execute_sell, Account};
+
+/// exit levels in percent, relative to the entry price.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Exits {
+    pub stop_loss_pct: Option<f64>,
+    pub take_profit_pct: Option<f64>,
+    /// sells once the price falls this far below the highest price seen since entry.
+    pub trailing_stop_pct: Option<f64>,
+}
+
+impl Exits {
+    pub fn is_empty(&self) -> bool {
+        self.stop_loss_pct.is_none()
+            && self.take_profit_pct.is_none()
+            && self.trailing_stop_pct.is_none()
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for pct in [self.stop_loss_pct, self.trailing_stop_pct]
+            .into_iter()
+            .flatten()
+        {
+            if !(pct > 0.0 && pct < 100.0) {
+                return Err(anyhow::anyhow!("stops should be between 0 and 100 percent"));
+            }
+        }
+        if let Some(pct) = self.take_profit_pct {
+            if !(pct > 0.0 && pct.is_finite()) {
+                return Err(anyhow::anyhow!("take profit should be above 0 percent"));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ExitReason {
+    StopLoss,
+    TakeProfit,
+    TrailingStop,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PositionStatus {
+    /// the buy is sent, the position opens once it's mined.
+    Pending {
+        tx_hash: TxHash,
+    },
+    Open,
+    /// one of the exits fired and its sell is sent. closes once it's mined, opens again if it
+    /// doesn't go through.
+    Closing {
+        reason: ExitReason,
+        tx_hash: TxHash,
+    },
+    /// sold by one of the exits.
+    Closed {
+        reason: ExitReason,
+        tx_hash: TxHash,
+    },
+    /// forgotten without selling, or the buy never went through.
+    Dismissed,
+}
+
+/// a token bought via Buy. amount is what we were quoted until the buy is mined, then what it
+/// delivered.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Position {
+    pub id: u64,
+    pub chain_id: u64,
+    /// trades against the paper book instead of the wallet.
+    #[serde(default)]
+    pub paper: bool,
+    pub token: EthAddress,
+    pub symbol: String,
+    pub decimals: u8,
+    pub amount: U256,
+    /// spot ETH per whole token once the buy is mined, the same price the exits are checked against.
+    /// zero while pending.
+    pub entry_price: f64,
+    /// highest price seen since entry, for the trailing stop.
+    pub high_price: f64,
+    pub exits: Exits,
+    pub status: PositionStatus,
+}
+
+impl Position {
+    pub fn is_open(&self) -> bool {
+        self.status == PositionStatus::Open
+    }
+
+    pub fn is_pending(&self) -> bool {
+        matches!(self.status, PositionStatus::Pending { .. })
+    }
+
+    pub fn is_closing(&self) -> bool {
+        matches!(self.status, PositionStatus::Closing { .. })
+    }
+
+    /// the first exit price has crossed, if any. also moves the trailing high.
+    pub fn observe(&mut self, price: f64) -> Option<ExitReason> {
+        self.high_price = self.high_price.max(price);
+
+        if let Some(pct) = self.exits.stop_loss_pct {
+            if price <= self.entry_price * (1.0 - pct / 100.0) {
+                return Some(ExitReason::StopLoss);
+            }
+        }
+        if let Some(pct) = self.exits.take_profit_pct {
+            if price >= self.entry_price * (1.0 + pct / 100.0) {
+                return Some(ExitReason::TakeProfit);
+            }
+        }
+        if let Some(pct) = self.exits.trailing_stop_pct {
+            if price <= self.high_price * (1.0 - pct / 100.0) {
+                return Some(ExitReason::TrailingStop);
+            }
+        }
+        None
+    }
+}
+
+/// settles the pending and closing positions on chain_id whose tx is mined, then prices every
+/// open position that has exits and sells the ones that trigger.
+/// we sell what the wallet actually holds, up to the position's amount, which covers taxed tokens.
+pub fn check_positions(
+    wallet: &Wallet<SigningKey>,
+    state: &mut State,
+    dexes: &mut DexRegistry,
+    prices: &mut PriceFeed,
+    chain_id: u64,
+) -> anyhow::Result<()> {
+    let has_pending = state
+        .positions
+        .values()
+        .any(|p| (p.is_pending() || p.is_closing()) && p.chain_id == chain_id);
+    let ids: Vec<u64> = state
+        .positions
+        .values()
+        .filter(|p| p.is_open() && p.chain_id == chain_id && !p.exits.is_empty())
+        .map(|p| p.id)
+        .collect();
+    if ids.is_empty() && !has_pending {
+        return Ok(());
+    }
+
+    let chain = state.chain(chain_id)?.clone();
+    let dexes = dexes.get(&chain)?;
+    if has_pending {
+        settle_positions(state, &chain, dexes, prices, wallet.address());
+    }
+
+    for id in ids {
+        let Some(position) = state.positions.get_mut(&id) else {
+            continue;
+        };
+        let price = match prices.weth_price(&chain, dexes, position.token, position.decimals) {
+            Ok(price) => price,
+            Err(e) => {
+                println!("trader: no price for position {}: {:?}", id, e);
+                continue;
+            }
+        };
+        let Some(reason) = position.observe(price) else {
+            continue;
+        };
+        println!(
+            "trader: {:?} on position {}, {} at {} ETH (entry {})",
+            reason, id, position.symbol, price, position.entry_price
+        );
+
+        let mut account = Account::new(wallet, &mut state.paper, position.paper);
+        let sell = account
+            .token_balance(chain_id, position.token)
+            .and_then(|balance| {
+                let amount = balance.min(position.amount);
+                if amount == U256::ZERO {
+                    return Err(anyhow::anyhow!("wallet holds no {}", position.symbol));
+                }
+                execute_sell(
+                    &mut account,
+                    &chain,
+                    dexes,
+                    position.token,
+                    amount,
+                    U256::ZERO,
+                )
+            });
+        match sell {
+            Ok(fill) => {
+                println!(
+                    "trader: closing position {}, sent! {}",
+                    id,
+                    chain.tx_url(fill.tx_hash)
+                );
+                // paper sells are final when booked.
+                position.status = if position.paper {
+                    PositionStatus::Closed {
+                        reason,
+                        tx_hash: fill.tx_hash,
+                    }
+                } else {
+                    PositionStatus::Closing {
+                        reason,
+                        tx_hash: fill.tx_hash,
+                    }
+                };
+            }
+            Err(e) => println!(
+                "trader: couldn't close position {}, will retry: {:?}",
+                id, e
+            ),
+        }
+    }
+
+    // the trailing highs moved even if nothing sold.
+    state.save()
+}
+
+/// opens pending positions on chain whose buy is mined, at the spot price right after it and with
+/// the tokens the buy delivered to owner. paper buys are final when booked. a reverted or dropped
+/// buy dismisses its position. closing positions close once their sell is mined and open again
+/// if it reverted or was dropped. a failed lookup leaves either for the next poll.
+fn settle_positions(
+    state: &mut State,
+    chain: &ChainConfig,
+    dexes: &[Box<dyn Dex>],
+    prices: &mut PriceFeed,
+    owner: EthAddress,
+) {
+    for position in state.positions.values_mut() {
+        if position.chain_id != chain.chain_id {
+            continue;
+        }
+        match position.status {
+            PositionStatus::Pending { tx_hash } => {
+                settle_buy(position, tx_hash, chain, dexes, prices, owner)
+            }
+            PositionStatus::Closing { reason, tx_hash } => {
+                settle_sell(position, reason, tx_hash, chain.chain_id)
+            }
+            _ => {}
+        }
+    }
+}
+
+fn settle_buy(
+    position: &mut Position,
+    tx_hash: TxHash,
+    chain: &ChainConfig,
+    dexes: &[Box<dyn Dex>],
+    prices: &mut PriceFeed,
+    owner: EthAddress,
+) {
+    let status = if position.paper {
+        Ok(TxStatus::Mined {
+            success: true,
+            logs: vec![],
+        })
+    } else {
+        get_tx_status(chain.chain_id, tx_hash)
+    };
+    match status {
+        Ok(TxStatus::Pending) => {}
+        Ok(TxStatus::Mined {
+            success: true,
+            logs,
+        }) => match prices.weth_price(chain, dexes, position.token, position.decimals) {
+            Ok(price) => {
+                if !position.paper {
+                    position.amount = received_in(&logs, position.token, owner);
+                }
+                println!(
+                    "trader: opened position {} with {} {} at {} ETH each",
+                    position.id, position.amount, position.symbol, price
+                );
+                position.entry_price = price;
+                position.high_price = price;
+                position.status = PositionStatus::Open;
+            }
+            Err(e) => println!(
+                "trader: no entry price for position {}: {:?}",
+                position.id, e
+            ),
+        },
+        Ok(TxStatus::Mined { success: false, .. }) | Ok(TxStatus::Dropped) => {
+            println!(
+                "trader: buy {:?} for position {} didn't go through, dismissed",
+                tx_hash, position.id
+            );
+            position.status = PositionStatus::Dismissed;
+        }
+        Err(e) => println!("trader: no receipt for position {}: {:?}", position.id, e),
+    }
+}
+
+fn settle_sell(position: &mut Position, reason: ExitReason, tx_hash: TxHash, chain_id: u64) {
+    match get_tx_status(chain_id, tx_hash) {
+        Ok(TxStatus::Pending) => {}
+        Ok(TxStatus::Mined { success: true, .. }) => {
+            println!("trader: closed position {}", position.id);
+            position.status = PositionStatus::Closed { reason, tx_hash };
+        }
+        Ok(TxStatus::Mined { success: false, .. }) | Ok(TxStatus::Dropped) => {
+            println!(
+                "trader: sell {:?} for position {} didn't go through, it's open again",
+                tx_hash, position.id
+            );
+            position.status = PositionStatus::Open;
+        }
+        Err(e) => println!("trader: no receipt for position {}: {:?}", position.id, e),
+    }
+}
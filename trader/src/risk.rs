@@ -0,0 +1,131 @@
+use kinode_process_lib::eth::{Address as EthAddress, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::helpers::{chains::ChainConfig, dex::Dex, router::find_best_route};
+use crate::trade::Account;
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+/// limits the core checks before it signs anything a strategy asks for.
+/// None means no limit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RiskLimits {
+    /// wei of ETH a single trade may be worth.
+    pub max_trade_wei: Option<U256>,
+    /// wei of ETH strategies may spend on buys in any 24 hours.
+    pub max_daily_spend_wei: Option<U256>,
+    pub max_gas_price: Option<U256>,
+    /// trades per watcher tick, shared by strategies, the sniper and copy trading.
+    pub max_trades_per_tick: u64,
+    /// kill switch, nothing gets signed while set.
+    pub halted: bool,
+}
+
+impl Default for RiskLimits {
+    fn default() -> Self {
+        RiskLimits {
+            max_trade_wei: None,
+            max_daily_spend_wei: None,
+            max_gas_price: None,
+            max_trades_per_tick: 1,
+            halted: false,
+        }
+    }
+}
+
+/// what was spent on buys, for the daily limit, and what traded this tick.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RiskLedger {
+    spends: Vec<(u64, U256)>,
+    /// the watcher resets it at the start of every tick.
+    #[serde(skip)]
+    trades_this_tick: u64,
+}
+
+impl RiskLedger {
+    pub fn start_tick(&mut self) {
+        self.trades_this_tick = 0;
+    }
+
+    /// books a trade that went out on account. every trade counts toward the tick's limit,
+    /// spend only toward the daily one if it left the real wallet.
+    pub fn record_trade(&mut self, now: u64, account: &Account, spend: U256) {
+        self.trades_this_tick += 1;
+        if matches!(account, Account::Live(_)) && spend > U256::ZERO {
+            self.record(now, spend);
+        }
+    }
+
+    pub fn spent_since(&self, since: u64) -> U256 {
+        self.spends
+            .iter()
+            .filter(|(at, _)| *at >= since)
+            .fold(U256::ZERO, |sum, (_, amount)| sum + amount)
+    }
+
+    fn record(&mut self, now: u64, amount: U256) {
+        self.spends.retain(|(at, _)| at + DAY_SECS > now);
+        self.spends.push((now, amount));
+    }
+}
+
+impl RiskLimits {
+    /// errors with the limit a trade worth value_wei would break.
+    /// spend is the ETH leaving the wallet, zero for sells.
+    pub fn check(
+        &self,
+        ledger: &RiskLedger,
+        now: u64,
+        gas_price: U256,
+        value_wei: U256,
+        spend: U256,
+    ) -> anyhow::Result<()> {
+        if self.halted {
+            return Err(anyhow::anyhow!("trading is halted"));
+        }
+        if ledger.trades_this_tick >= self.max_trades_per_tick {
+            return Err(anyhow::anyhow!(
+                "already made {} trades this tick",
+                ledger.trades_this_tick
+            ));
+        }
+        if let Some(max) = self.max_gas_price {
+            if gas_price > max {
+                return Err(anyhow::anyhow!("gas price {} is above {}", gas_price, max));
+            }
+        }
+        if let Some(max) = self.max_trade_wei {
+            if value_wei > max {
+                return Err(anyhow::anyhow!(
+                    "trade worth {} wei is above {}",
+                    value_wei,
+                    max
+                ));
+            }
+        }
+        if let Some(max) = self.max_daily_spend_wei {
+            let spent = ledger.spent_since(now.saturating_sub(DAY_SECS));
+            if spent + spend > max {
+                return Err(anyhow::anyhow!(
+                    "would spend {} wei today, the limit is {}",
+                    spent + spend,
+                    max
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// what selling amount of token is worth to the limits: the wei it's quoted to bring back.
+pub fn sell_value(
+    chain: &ChainConfig,
+    dexes: &[Box<dyn Dex>],
+    token: EthAddress,
+    amount: U256,
+    gas_price: U256,
+) -> anyhow::Result<U256> {
+    let route = find_best_route(chain, dexes, token, chain.weth, amount, gas_price)?;
+    Ok(route.amount_out)
+}
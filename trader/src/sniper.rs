@@ -0,0 +1,313 @@
+use alloy_signer::{k256::ecdsa::SigningKey, Signer, Wallet};
+use alloy_sol_types::SolEvent;
+use kinode_process_lib::{
+    eth::{Address as EthAddress, Filter, Log, TxHash, U256},
+    println,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::helpers::{
+    calls::{get_erc20_info, get_reserves, to_units},
+    chains::ChainConfig,
+    contracts::IUniswapV2Factory,
+    dex::{dex_by_name, Dex, DexKind, DexRegistry},
+    tax::check_round_trip,
+};
+use crate::orders::now_secs;
+use crate::positions::{Exits, Position, PositionStatus};
+use crate::state::State;
+use crate::subscriptions::{Feed, Subscriptions};
+use crate::trade::{execute_buy_with_slippage, Account};
+
+// the pair's creation block or the one after, liquidity is usually added right away.
+pub const DEFAULT_MAX_WAIT_BLOCKS: u64 = 1;
+pub const DEFAULT_MAX_TAX_BPS: u64 = 1_000;
+
+/// buy amount wei of every new token paired with WETH, once the pair holds min_liquidity.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SniperConfig {
+    pub chain_id: u64,
+    pub amount: U256,
+    /// wei of WETH the pair has to hold before we buy.
+    pub min_liquidity: U256,
+    /// blocks after the pair was created we wait for liquidity before giving up on it.
+    pub max_wait_blocks: u64,
+    /// highest buy or sell tax we still buy through, in basis points.
+    pub max_tax_bps: u64,
+    pub slippage_bps: u64,
+    /// exits for the position each buy opens.
+    pub exits: Exits,
+    /// trades against the paper book instead of the wallet.
+    pub paper: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SnipeStatus {
+    /// waiting for the pair to get enough liquidity.
+    Watching,
+    /// sent, not necessarily mined. position is None if the buy came back empty.
+    Bought {
+        tx_hash: TxHash,
+        position: Option<u64>,
+    },
+    Skipped {
+        reason: String,
+    },
+}
+
+/// a new WETH pair the sniper saw created.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snipe {
+    pub id: u64,
+    pub chain_id: u64,
+    pub dex: String,
+    pub pair: EthAddress,
+    pub token: EthAddress,
+    /// the block the pair was created in.
+    pub block: u64,
+    pub status: SnipeStatus,
+}
+
+/// listens for PairCreated on every V2 factory configured for chain that we aren't listening to yet.
+/// other kinds of factory announce their pairs differently, they're left out.
+pub fn subscribe_pairs(subs: &mut Subscriptions, chain: &ChainConfig) -> anyhow::Result<()> {
+    let factories: Vec<_> = chain
+        .dexes
+        .iter()
+        .filter(|dex| matches!(dex.kind, DexKind::UniswapV2))
+        .collect();
+    if factories.is_empty() {
+        return Err(anyhow::anyhow!(
+            "chain {} has no V2 factory to watch",
+            chain.chain_id
+        ));
+    }
+    for dex in factories {
+        let feed = Feed::PairCreated {
+            chain_id: chain.chain_id,
+            dex: dex.name.clone(),
+        };
+        if subs.is_subscribed(&feed) {
+            continue;
+        }
+        let filter = Filter::new()
+            .address(dex.factory)
+            .event_signature(IUniswapV2Factory::PairCreated::SIGNATURE_HASH);
+        subs.subscribe(feed, chain.chain_id, filter)?;
+        println!(
+            "trader: sniper watching new pairs on {} {}",
+            chain.name, dex.name
+        );
+    }
+    Ok(())
+}
+
+pub fn unsubscribe_pairs(subs: &mut Subscriptions) {
+    subs.unsubscribe(|feed| matches!(feed, Feed::PairCreated { .. }));
+}
+
+/// records a newly created pair if it's against WETH. returns the snipe's id.
+pub fn on_pair_created(
+    state: &mut State,
+    chain_id: u64,
+    dex: &str,
+    log: &Log,
+) -> anyhow::Result<Option<u64>> {
+    if state.sniper.as_ref().map(|s| s.chain_id) != Some(chain_id) {
+        return Ok(None);
+    }
+    let event = IUniswapV2Factory::PairCreated::decode_raw_log(
+        log.topics().iter().copied(),
+        &log.data().data,
+        true,
+    )?;
+    let weth = state.chain(chain_id)?.weth;
+    let token = if event.token0 == weth {
+        event.token1
+    } else if event.token1 == weth {
+        event.token0
+    } else {
+        return Ok(None);
+    };
+    let block = match log.block_number {
+        Some(block) => block,
+        None => state.chain(chain_id)?.provider().get_block_number()?,
+    };
+
+    let id = state.next_id();
+    println!(
+        "trader: new pair {:?} for {:?} on {}, snipe {}",
+        event.pair, token, dex, id
+    );
+    state.snipes.insert(
+        id,
+        Snipe {
+            id,
+            chain_id,
+            dex: dex.to_string(),
+            pair: event.pair,
+            token,
+            block,
+            status: SnipeStatus::Watching,
+        },
+    );
+    state.save()?;
+    Ok(Some(id))
+}
+
+/// tries every pair still waiting for liquidity, and gives up on the ones past max_wait_blocks.
+pub fn check_snipes(
+    wallet: &Wallet<SigningKey>,
+    state: &mut State,
+    dexes: &mut DexRegistry,
+) -> anyhow::Result<()> {
+    let Some(config) = state.sniper.clone() else {
+        return Ok(());
+    };
+    let ids: Vec<u64> = state
+        .snipes
+        .values()
+        .filter(|s| s.status == SnipeStatus::Watching && s.chain_id == config.chain_id)
+        .map(|s| s.id)
+        .collect();
+    if ids.is_empty() {
+        return Ok(());
+    }
+
+    let chain = state.chain(config.chain_id)?.clone();
+    let dexes = dexes.get(&chain)?;
+    let block = chain.provider().get_block_number()?;
+
+    for id in ids {
+        let Some(snipe) = state.snipes.get(&id).cloned() else {
+            continue;
+        };
+        let status = if block > snipe.block + config.max_wait_blocks {
+            Some(SnipeStatus::Skipped {
+                reason: format!(
+                    "no {} WETH of liquidity within {} blocks",
+                    to_units(config.min_liquidity, 18),
+                    config.max_wait_blocks
+                ),
+            })
+        } else {
+            match snipe_pair(wallet, state, &chain, dexes, &config, &snipe) {
+                Ok(status) => status,
+                Err(e) => {
+                    println!("trader: snipe {} failed, will retry: {:?}", id, e);
+                    None
+                }
+            }
+        };
+        if let Some(status) = status {
+            match &status {
+                SnipeStatus::Bought { tx_hash, .. } => {
+                    println!(
+                        "trader: sniped {:?}, sent! {}",
+                        snipe.token,
+                        chain.tx_url(tx_hash)
+                    )
+                }
+                SnipeStatus::Skipped { reason } => {
+                    println!("trader: skipped snipe {}: {}", id, reason)
+                }
+                SnipeStatus::Watching => {}
+            }
+            if let Some(snipe) = state.snipes.get_mut(&id) {
+                snipe.status = status;
+            }
+        }
+    }
+
+    state.save()
+}
+
+/// buys snipe's token if its pair is liquid enough and a simulated buy and sell back both go
+/// through, neither taxed past the limit. None means the pair isn't ready yet.
+fn snipe_pair(
+    wallet: &Wallet<SigningKey>,
+    state: &mut State,
+    chain: &ChainConfig,
+    dexes: &[Box<dyn Dex>],
+    config: &SniperConfig,
+    snipe: &Snipe,
+) -> anyhow::Result<Option<SnipeStatus>> {
+    let (weth_reserve, _) = get_reserves(chain.chain_id, snipe.pair, chain.weth, None)?;
+    if weth_reserve < config.min_liquidity {
+        return Ok(None);
+    }
+
+    let dex = dex_by_name(dexes, &snipe.dex)?;
+    let path = vec![chain.weth, snipe.token];
+    let quoted_out = *dex
+        .get_amounts_out(config.amount, &path)?
+        .last()
+        .ok_or_else(|| anyhow::anyhow!("empty quote from {}", dex.name()))?;
+    if let Some(reason) = check_round_trip(
+        wallet.address(),
+        dex,
+        config.amount,
+        path,
+        quoted_out,
+        config.max_tax_bps,
+    )? {
+        return Ok(Some(SnipeStatus::Skipped { reason }));
+    }
+
+    // looked up before buying, an error after the buy would have us buy again.
+    let (decimals, symbol) = get_erc20_info(chain.chain_id, snipe.token)?;
+    let decimals = decimals.to::<u8>();
+
+    let now = now_secs()?;
+    let gas_price = chain.provider().get_gas_price()?;
+    state.risk.check(
+        &state.risk_ledger,
+        now,
+        gas_price,
+        config.amount,
+        config.amount,
+    )?;
+
+    let mut account = Account::new(wallet, &mut state.paper, config.paper);
+    let fill = execute_buy_with_slippage(
+        &mut account,
+        chain,
+        dexes,
+        snipe.token,
+        config.amount,
+        U256::ZERO,
+        config.slippage_bps,
+    )?;
+    state.risk_ledger.record_trade(now, &account, config.amount);
+    let paper = matches!(account, Account::Paper(_));
+
+    let position = if fill.quoted_out > U256::ZERO {
+        let id = state.next_id();
+        println!("trader: position {} opens once the buy is mined", id);
+        state.positions.insert(
+            id,
+            Position {
+                id,
+                chain_id: chain.chain_id,
+                paper,
+                token: snipe.token,
+                symbol,
+                decimals,
+                amount: fill.quoted_out,
+                entry_price: 0.0,
+                high_price: 0.0,
+                exits: config.exits.clone(),
+                status: PositionStatus::Pending {
+                    tx_hash: fill.tx_hash,
+                },
+            },
+        );
+        Some(id)
+    } else {
+        None
+    };
+    Ok(Some(SnipeStatus::Bought {
+        tx_hash: fill.tx_hash,
+        position,
+    }))
+}
@@ -0,0 +1,191 @@
+use kinode_process_lib::{get_state, println, set_state};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::alerts::Alert;
+use crate::backtest::Recording;
+use crate::copytrade::Follow;
+use crate::dca::DcaSchedule;
+use crate::grid::{Grid, GridStatus};
+use crate::helpers::chains::ChainConfig;
+use crate::orders::LimitOrder;
+use crate::paper::PaperBook;
+use crate::positions::Position;
+use crate::risk::{RiskLedger, RiskLimits};
+use crate::sniper::{Snipe, SniperConfig};
+use crate::strategy::StrategyInstance;
+use crate::twap::Twap;
+use crate::watcher::{WatchEntry, WatcherConfig};
+
+// bumped whenever State changes in a way serde(default) can't absorb.
+pub const STATE_VERSION: u32 = 1;
+
+/// everything the trader persists between restarts.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct State {
+    /// 0 for state saved before it was versioned.
+    pub version: u32,
+    /// the private key, encrypted with the user's password.
+    pub wallet: Option<Vec<u8>>,
+    pub chains: HashMap<u64, ChainConfig>,
+    /// chains set with SetChainConfig, the shipped chains.json doesn't replace them.
+    pub custom_chains: BTreeSet<u64>,
+    /// used by requests that don't name a chain.
+    pub default_chain: Option<u64>,
+    pub watcher: WatcherConfig,
+    /// tokens the price feed follows live.
+    pub watchlist: BTreeMap<u64, WatchEntry>,
+    pub orders: BTreeMap<u64, LimitOrder>,
+    pub positions: BTreeMap<u64, Position>,
+    pub dcas: BTreeMap<u64, DcaSchedule>,
+    pub twaps: BTreeMap<u64, Twap>,
+    pub grids: BTreeMap<u64, Grid>,
+    /// datasets still being sampled for backtests.
+    pub recordings: BTreeMap<u64, Recording>,
+    pub strategies: BTreeMap<u64, StrategyInstance>,
+    pub risk: RiskLimits,
+    pub risk_ledger: RiskLedger,
+    pub paper: PaperBook,
+    pub alerts: BTreeMap<u64, Alert>,
+    /// where fired alerts are sent, a process address on this node or another.
+    pub alert_target: Option<String>,
+    /// None while the sniper is stopped.
+    pub sniper: Option<SniperConfig>,
+    pub snipes: BTreeMap<u64, Snipe>,
+    /// wallets whose swaps we copy.
+    pub follows: BTreeMap<u64, Follow>,
+    /// ids are shared by everything we keep, so they never collide.
+    pub next_id: u64,
+}
+
+impl State {
+    /// errors rather than start over if the saved state can't be read, so it isn't overwritten.
+    pub fn load() -> anyhow::Result<Self> {
+        let Some(bytes) = get_state() else {
+            return Ok(State {
+                version: STATE_VERSION,
+                ..Default::default()
+            });
+        };
+        let mut state: State = match serde_json::from_slice(&bytes) {
+            Ok(state) => state,
+            // a state we saved that no longer parses, don't take it for a wallet.
+            Err(e) if bytes.first() == Some(&b'{') => {
+                return Err(anyhow::anyhow!("saved state doesn't parse: {}", e))
+            }
+            // older versions stored nothing but the encrypted wallet.
+            Err(_) => {
+                return Ok(State {
+                    version: STATE_VERSION,
+                    wallet: Some(bytes),
+                    ..Default::default()
+                })
+            }
+        };
+        match state.version {
+            STATE_VERSION => {}
+            // chains in it may have been edited with SetChainConfig, we can't tell which.
+            0 => {
+                state.custom_chains = state.chains.keys().copied().collect();
+                state.version = STATE_VERSION;
+            }
+            version => {
+                return Err(anyhow::anyhow!(
+                    "saved state is version {}, this build only reads up to {}",
+                    version,
+                    STATE_VERSION
+                ))
+            }
+        }
+        Ok(state)
+    }
+
+    /// brings the shipped chains.json into state. chains nobody set with SetChainConfig follow
+    /// it, custom ones only pick up what it adds that they leave unset. every chain is validated,
+    /// invalid ones are dropped until they're set again.
+    pub fn merge_chains(&mut self, shipped: Vec<ChainConfig>) {
+        if self.default_chain.is_none() {
+            self.default_chain = shipped.first().map(|chain| chain.chain_id);
+        }
+        for chain in shipped {
+            match self.chains.get_mut(&chain.chain_id) {
+                Some(stored) if self.custom_chains.contains(&chain.chain_id) => {
+                    stored.fill_from(&chain)
+                }
+                _ => {
+                    self.chains.insert(chain.chain_id, chain);
+                }
+            }
+        }
+        self.chains
+            .retain(|chain_id, chain| match chain.validate() {
+                Ok(()) => true,
+                Err(e) => {
+                    println!(
+                        "trader: dropped the config for chain {}, set it again to use it: {:?}",
+                        chain_id, e
+                    );
+                    false
+                }
+            });
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        set_state(&serde_json::to_vec(self)?);
+        Ok(())
+    }
+
+    /// the chain a request asked for, or the default one.
+    pub fn chain_id(&self, requested: Option<u64>) -> anyhow::Result<u64> {
+        requested
+            .or(self.default_chain)
+            .ok_or_else(|| anyhow::anyhow!("no chain_id given and no default chain set"))
+    }
+
+    pub fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// chains the watcher has something to do on.
+    pub fn watched_chains(&self) -> BTreeSet<u64> {
+        let orders = self
+            .orders
+            .values()
+            .filter(|o| o.is_open() || o.is_pending())
+            .map(|o| o.chain_id);
+        let positions = self
+            .positions
+            .values()
+            .filter(|p| p.is_pending() || p.is_closing() || (p.is_open() && !p.exits.is_empty()))
+            .map(|p| p.chain_id);
+        let grids = self
+            .grids
+            .values()
+            .filter(|g| g.status == GridStatus::Running || g.has_pending())
+            .map(|g| g.chain_id);
+        let strategies = self
+            .strategies
+            .values()
+            .filter(|s| s.running)
+            .map(|s| s.chain_id);
+        let alerts = self
+            .alerts
+            .values()
+            .filter(|a| a.active)
+            .map(|a| a.chain_id);
+        orders
+            .chain(positions)
+            .chain(grids)
+            .chain(strategies)
+            .chain(alerts)
+            .collect()
+    }
+
+    pub fn chain(&self, chain_id: u64) -> anyhow::Result<&ChainConfig> {
+        self.chains
+            .get(&chain_id)
+            .ok_or_else(|| anyhow::anyhow!("no config for chain_id: {:?}", chain_id))
+    }
+}
@@ -0,0 +1,147 @@
+use kinode_process_lib::eth::{Address as EthAddress, U256};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+use crate::orders::Side;
+use crate::strategy::{Action, Order, Outcome, Strategy, Tick};
+
+/// buys when the price drops band_pct under its moving average over the last window polls,
+/// and sells what it bought once the price is band_pct over it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MeanReversion {
+    pub token: EthAddress,
+    pub window: usize,
+    pub band_pct: f64,
+    /// wei of ETH per buy.
+    pub amount: U256,
+    #[serde(default)]
+    prices: VecDeque<f64>,
+    #[serde(default)]
+    held: U256,
+    /// an action is out, don't send another until its outcome is in.
+    #[serde(default)]
+    pending: bool,
+}
+
+/// what CreateStrategy takes, the strategy starts with no history and nothing held.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MeanReversionConfig {
+    pub token: EthAddress,
+    pub window: usize,
+    pub band_pct: f64,
+    pub amount: U256,
+}
+
+impl From<MeanReversionConfig> for MeanReversion {
+    fn from(config: MeanReversionConfig) -> Self {
+        MeanReversion {
+            token: config.token,
+            window: config.window,
+            band_pct: config.band_pct,
+            amount: config.amount,
+            prices: VecDeque::new(),
+            held: U256::ZERO,
+            pending: false,
+        }
+    }
+}
+
+/// ConfigureStrategy params, anything left out stays as it is.
+#[derive(Debug, Deserialize)]
+struct MeanReversionParams {
+    window: Option<usize>,
+    band_pct: Option<f64>,
+    amount: Option<U256>,
+}
+
+impl MeanReversion {
+    fn average(&self) -> Option<f64> {
+        (self.prices.len() >= self.window)
+            .then(|| self.prices.iter().sum::<f64>() / self.prices.len() as f64)
+    }
+}
+
+impl Strategy for MeanReversion {
+    fn tokens(&self) -> Vec<EthAddress> {
+        vec![self.token]
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.window < 2 {
+            return Err(anyhow::anyhow!("window should be at least 2 polls"));
+        }
+        if !(self.band_pct > 0.0 && self.band_pct < 100.0) {
+            return Err(anyhow::anyhow!("band_pct should be between 0 and 100"));
+        }
+        if self.amount == U256::ZERO {
+            return Err(anyhow::anyhow!("amount should be above 0"));
+        }
+        Ok(())
+    }
+
+    fn on_price(&mut self, token: EthAddress, price: f64, _tick: &Tick) -> Vec<Action> {
+        if token != self.token {
+            return vec![];
+        }
+        self.prices.push_back(price);
+        while self.prices.len() > self.window {
+            self.prices.pop_front();
+        }
+        let Some(average) = self.average() else {
+            return vec![];
+        };
+        if self.pending {
+            return vec![];
+        }
+
+        if self.held == U256::ZERO && price < average * (1.0 - self.band_pct / 100.0) {
+            self.pending = true;
+            vec![Action::Buy(Order::new(token, self.amount))]
+        } else if self.held > U256::ZERO && price > average * (1.0 + self.band_pct / 100.0) {
+            self.pending = true;
+            vec![Action::Sell(Order::new(token, self.held))]
+        } else {
+            vec![]
+        }
+    }
+
+    fn on_fill(&mut self, outcome: &Outcome) -> Vec<Action> {
+        match outcome {
+            Outcome::Filled {
+                side: Side::Buy,
+                quoted_out,
+                ..
+            } => self.held += *quoted_out,
+            Outcome::Filled {
+                side: Side::Sell, ..
+            } => self.held = U256::ZERO,
+            _ => {}
+        }
+        self.pending = false;
+        vec![]
+    }
+
+    fn configure(&mut self, params: serde_json::Value) -> anyhow::Result<()> {
+        let params: MeanReversionParams = serde_json::from_value(params)?;
+        let mut updated = self.clone();
+        if let Some(window) = params.window {
+            updated.window = window;
+        }
+        if let Some(band_pct) = params.band_pct {
+            updated.band_pct = band_pct;
+        }
+        if let Some(amount) = params.amount {
+            updated.amount = amount;
+        }
+        updated.validate()?;
+        *self = updated;
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "mean reversion on {:?}, {}% around a {} poll average, {} wei per buy, holding {}",
+            self.token, self.band_pct, self.window, self.amount, self.held
+        )
+    }
+}
@@ -0,0 +1,390 @@
+use alloy_signer::{k256::ecdsa::SigningKey, Wallet};
+use kinode_process_lib::{
+    eth::{Address as EthAddress, TxHash, U256},
+    println,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::feed::PriceFeed;
+use crate::helpers::{
+    calls::get_erc20_info,
+    chains::ChainConfig,
+    dex::{Dex, DexRegistry},
+    router::{best_venue, find_best_route, DEFAULT_SLIPPAGE_BPS},
+};
+use crate::orders::{now_secs, Side};
+use crate::risk::sell_value;
+use crate::state::State;
+use crate::trade::{execute_buy_with_slippage, execute_sell_with_slippage, Account};
+
+pub mod mean_reversion;
+pub mod rules;
+
+use mean_reversion::{MeanReversion, MeanReversionConfig};
+use rules::Rule;
+
+// actions a single poll may chain through on_fill before we stop listening.
+pub const MAX_ACTIONS_PER_TICK: usize = 16;
+
+/// what a strategy wants the trader to do.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Action {
+    /// the answer comes back through on_fill as Outcome::Quoted.
+    Quote {
+        token: EthAddress,
+        side: Side,
+        amount: U256,
+    },
+    /// spend amount wei of ETH on token.
+    Buy(Order),
+    /// sell amount of token for ETH.
+    Sell(Order),
+    /// the strategy is done, stop running it.
+    Cancel { reason: String },
+}
+
+/// one trade a strategy asks for, min_out and slippage_bps work like in execute_buy_with_slippage.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Order {
+    pub token: EthAddress,
+    pub amount: U256,
+    pub min_out: U256,
+    pub slippage_bps: u64,
+}
+
+impl Order {
+    pub fn new(token: EthAddress, amount: U256) -> Self {
+        Order {
+            token,
+            amount,
+            min_out: U256::ZERO,
+            slippage_bps: DEFAULT_SLIPPAGE_BPS,
+        }
+    }
+}
+
+/// what came of an action, handed to on_fill.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Outcome {
+    Quoted {
+        token: EthAddress,
+        side: Side,
+        amount_in: U256,
+        amount_out: U256,
+    },
+    Filled {
+        token: EthAddress,
+        side: Side,
+        amount_in: U256,
+        quoted_out: U256,
+        tx_hash: TxHash,
+    },
+    /// refused by the risk limits or failed to execute.
+    Rejected {
+        token: EthAddress,
+        side: Side,
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Tick {
+    pub now: u64,
+    pub gas_price: U256,
+}
+
+/// a strategy only decides, the trader does the quoting, checking and signing.
+/// every callback returns the actions it wants taken, in order.
+pub trait Strategy {
+    /// tokens whose price in ETH gets fed to on_price.
+    fn tokens(&self) -> Vec<EthAddress>;
+    /// checked when the strategy is created and after every configure.
+    fn validate(&self) -> anyhow::Result<()>;
+    fn on_tick(&mut self, _tick: &Tick) -> Vec<Action> {
+        vec![]
+    }
+    fn on_price(&mut self, _token: EthAddress, _price: f64, _tick: &Tick) -> Vec<Action> {
+        vec![]
+    }
+    fn on_fill(&mut self, _outcome: &Outcome) -> Vec<Action> {
+        vec![]
+    }
+    /// changes parameters while running, params are strategy specific.
+    fn configure(&mut self, params: serde_json::Value) -> anyhow::Result<()>;
+    fn describe(&self) -> String;
+}
+
+/// every strategy the trader can run. the strategy's own fields are its state,
+/// so it's persisted along with everything else.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum StrategyKind {
+    MeanReversion(MeanReversion),
+    /// a rule written in the little rule language, see Rule.
+    Rule(Rule),
+}
+
+/// a strategy as a client asks for it in CreateStrategy: parameters only, the state it keeps
+/// while running always starts fresh. rules come in through CreateRule.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum StrategySpec {
+    MeanReversion(MeanReversionConfig),
+}
+
+impl From<StrategySpec> for StrategyKind {
+    fn from(spec: StrategySpec) -> Self {
+        match spec {
+            StrategySpec::MeanReversion(config) => StrategyKind::MeanReversion(config.into()),
+        }
+    }
+}
+
+impl StrategyKind {
+    pub fn strategy(&self) -> &dyn Strategy {
+        match self {
+            StrategyKind::MeanReversion(s) => s,
+            StrategyKind::Rule(s) => s,
+        }
+    }
+
+    pub fn strategy_mut(&mut self) -> &mut dyn Strategy {
+        match self {
+            StrategyKind::MeanReversion(s) => s,
+            StrategyKind::Rule(s) => s,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StrategyInstance {
+    pub id: u64,
+    pub chain_id: u64,
+    /// trades against the paper book instead of the wallet.
+    #[serde(default)]
+    pub paper: bool,
+    pub running: bool,
+    pub kind: StrategyKind,
+    /// decimals of the strategy's tokens, looked up once when it's started.
+    pub decimals: BTreeMap<EthAddress, u8>,
+}
+
+/// validates kind, looks up its tokens' decimals and starts running it. returns the new id.
+pub fn add_strategy(
+    state: &mut State,
+    chain_id: u64,
+    kind: StrategyKind,
+    paper: bool,
+) -> anyhow::Result<u64> {
+    state.chain(chain_id)?;
+    kind.strategy().validate()?;
+
+    let mut decimals = BTreeMap::new();
+    for token in kind.strategy().tokens() {
+        let (token_decimals, _) = get_erc20_info(chain_id, token)?;
+        decimals.insert(token, token_decimals.to::<u8>());
+    }
+
+    let id = state.next_id();
+    println!(
+        "trader: started strategy {}, {}",
+        id,
+        kind.strategy().describe()
+    );
+    state.strategies.insert(
+        id,
+        StrategyInstance {
+            id,
+            chain_id,
+            running: true,
+            paper,
+            kind,
+            decimals,
+        },
+    );
+    state.save()?;
+    Ok(id)
+}
+
+/// runs every strategy on chain_id for one poll: on_tick, then on_price for each of its tokens,
+/// then executes what they asked for, feeding outcomes back through on_fill.
+pub fn run_strategies(
+    wallet: &Wallet<SigningKey>,
+    state: &mut State,
+    dexes: &mut DexRegistry,
+    prices: &mut PriceFeed,
+    chain_id: u64,
+) -> anyhow::Result<()> {
+    let ids: Vec<u64> = state
+        .strategies
+        .values()
+        .filter(|s| s.running && s.chain_id == chain_id)
+        .map(|s| s.id)
+        .collect();
+    if ids.is_empty() {
+        return Ok(());
+    }
+
+    let chain = state.chain(chain_id)?.clone();
+    let dexes = dexes.get(&chain)?;
+    let tick = Tick {
+        now: now_secs()?,
+        gas_price: chain.provider().get_gas_price()?,
+    };
+    for id in ids {
+        let Some(instance) = state.strategies.get_mut(&id) else {
+            continue;
+        };
+        let strategy = instance.kind.strategy_mut();
+        let mut account = Account::new(wallet, &mut state.paper, instance.paper);
+
+        let mut actions: VecDeque<Action> = strategy.on_tick(&tick).into();
+        for (token, decimals) in &instance.decimals {
+            match prices.weth_price(&chain, dexes, *token, *decimals) {
+                Ok(price) => actions.extend(strategy.on_price(*token, price, &tick)),
+                Err(e) => println!("trader: strategy {} no price for {:?}: {:?}", id, token, e),
+            }
+        }
+
+        let mut handled = 0;
+        while let Some(action) = actions.pop_front() {
+            handled += 1;
+            if handled > MAX_ACTIONS_PER_TICK {
+                println!("trader: strategy {} asked for too much this tick", id);
+                break;
+            }
+            let outcome = match action {
+                Action::Cancel { reason } => {
+                    println!("trader: strategy {} stopped itself: {}", id, reason);
+                    instance.running = false;
+                    break;
+                }
+                Action::Quote {
+                    token,
+                    side,
+                    amount,
+                } => quote(&chain, dexes, &tick, token, side, amount),
+                Action::Buy(order) => {
+                    let checked = state.risk.check(
+                        &state.risk_ledger,
+                        tick.now,
+                        tick.gas_price,
+                        order.amount,
+                        order.amount,
+                    );
+                    match checked {
+                        Ok(()) => trade(&mut account, &chain, dexes, Side::Buy, &order),
+                        Err(e) => rejected(order.token, Side::Buy, e),
+                    }
+                }
+                Action::Sell(order) => {
+                    let checked =
+                        sell_value(&chain, dexes, order.token, order.amount, tick.gas_price)
+                            .and_then(|value| {
+                                state.risk.check(
+                                    &state.risk_ledger,
+                                    tick.now,
+                                    tick.gas_price,
+                                    value,
+                                    U256::ZERO,
+                                )
+                            });
+                    match checked {
+                        Ok(()) => trade(&mut account, &chain, dexes, Side::Sell, &order),
+                        Err(e) => rejected(order.token, Side::Sell, e),
+                    }
+                }
+            };
+            match &outcome {
+                Outcome::Filled { tx_hash, .. } => {
+                    println!(
+                        "trader: strategy {} traded, sent! {}",
+                        id,
+                        chain.tx_url(tx_hash)
+                    );
+                }
+                Outcome::Rejected { reason, .. } => {
+                    println!("trader: strategy {} action rejected: {}", id, reason);
+                }
+                Outcome::Quoted { .. } => {}
+            }
+            if let Outcome::Filled {
+                side, amount_in, ..
+            } = &outcome
+            {
+                let spend = match side {
+                    Side::Buy => *amount_in,
+                    Side::Sell => U256::ZERO,
+                };
+                state.risk_ledger.record_trade(tick.now, &account, spend);
+            }
+            actions.extend(strategy.on_fill(&outcome));
+        }
+    }
+
+    state.save()
+}
+
+fn quote(
+    chain: &ChainConfig,
+    dexes: &[Box<dyn Dex>],
+    tick: &Tick,
+    token: EthAddress,
+    side: Side,
+    amount: U256,
+) -> Outcome {
+    let amount_out = match side {
+        Side::Buy => best_venue(chain, dexes, chain.weth, token, amount, tick.gas_price)
+            .map(|venue| venue.amount_out()),
+        Side::Sell => find_best_route(chain, dexes, token, chain.weth, amount, tick.gas_price)
+            .map(|route| route.amount_out),
+    };
+    match amount_out {
+        Ok(amount_out) => Outcome::Quoted {
+            token,
+            side,
+            amount_in: amount,
+            amount_out,
+        },
+        Err(e) => rejected(token, side, e),
+    }
+}
+
+fn trade(
+    account: &mut Account,
+    chain: &ChainConfig,
+    dexes: &[Box<dyn Dex>],
+    side: Side,
+    order: &Order,
+) -> Outcome {
+    let execute = match side {
+        Side::Buy => execute_buy_with_slippage,
+        Side::Sell => execute_sell_with_slippage,
+    };
+    let fill = execute(
+        account,
+        chain,
+        dexes,
+        order.token,
+        order.amount,
+        order.min_out,
+        order.slippage_bps,
+    );
+    match fill {
+        Ok(fill) => Outcome::Filled {
+            token: order.token,
+            side,
+            amount_in: order.amount,
+            quoted_out: fill.quoted_out,
+            tx_hash: fill.tx_hash,
+        },
+        Err(e) => rejected(order.token, side, e),
+    }
+}
+
+fn rejected(token: EthAddress, side: Side, error: anyhow::Error) -> Outcome {
+    Outcome::Rejected {
+        token,
+        side,
+        reason: format!("{:?}", error),
+    }
+}
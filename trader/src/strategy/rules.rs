@@ -0,0 +1,470 @@
+use kinode_process_lib::eth::{Address as EthAddress, U256};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::helpers::{
+    calls::{parse_units, to_units},
+    chains::ChainConfig,
+};
+use crate::orders::Side;
+use crate::strategy::{Action, Order, Outcome, Strategy, Tick};
+
+// half the pool is gone at that point, it's a typo not a tolerance.
+const MAX_SLIPPAGE_BPS: u64 = 5_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Cmp {
+    Below,
+    AtMost,
+    Above,
+    AtLeast,
+}
+
+impl Cmp {
+    fn parse(word: &str) -> anyhow::Result<Self> {
+        match word {
+            "<" => Ok(Cmp::Below),
+            "<=" => Ok(Cmp::AtMost),
+            ">" => Ok(Cmp::Above),
+            ">=" => Ok(Cmp::AtLeast),
+            _ => Err(anyhow::anyhow!("expected one of < <= > >=, got {:?}", word)),
+        }
+    }
+
+    fn holds(&self, left: f64, right: f64) -> bool {
+        match self {
+            Cmp::Below => left < right,
+            Cmp::AtMost => left <= right,
+            Cmp::Above => left > right,
+            Cmp::AtLeast => left >= right,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Condition {
+    /// price of base in quote. both legs are priced in ETH, so any two tokens with a WETH pair work.
+    Price {
+        base: EthAddress,
+        quote: EthAddress,
+        cmp: Cmp,
+        value: f64,
+    },
+    /// the chain's gas price, in gwei.
+    Gas { cmp: Cmp, gwei: f64 },
+}
+
+/// `when <condition> [and <condition>...] then <action> [slippage <n>%] [repeat]`, where
+///   condition: `price(<TOKEN>/<TOKEN>) <cmp> <number>` or `gas <cmp> <number> gwei|wei`
+///   action:    `buy <n> eth of <TOKEN>` or `sell <n> of <TOKEN>`
+///   cmp:       `<`, `<=`, `>` or `>=`
+/// tokens are addresses or symbols from the chain config. a rule fires once and stops,
+/// unless it says repeat, then it fires again each time its conditions turn true again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rule {
+    /// the rule as it was written.
+    pub source: String,
+    weth: EthAddress,
+    conditions: Vec<Condition>,
+    side: Side,
+    order: Order,
+    repeat: bool,
+    /// false after firing until the conditions stop holding.
+    armed: bool,
+    pending: bool,
+    fired: u64,
+    /// what this poll has seen so far, the rule is checked once every price is in.
+    #[serde(skip)]
+    gas_price: U256,
+    #[serde(skip)]
+    prices: BTreeMap<EthAddress, f64>,
+}
+
+impl Rule {
+    /// parses and resolves a rule against chain. decimals looks up a token's decimals,
+    /// sell amounts are written in whole tokens.
+    pub fn parse(
+        source: &str,
+        chain: &ChainConfig,
+        decimals: impl Fn(EthAddress) -> anyhow::Result<u8>,
+    ) -> anyhow::Result<Self> {
+        let mut words = source.split_whitespace();
+        let mut next = |expected: &str| {
+            words
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("rule ends early, expected {}", expected))
+        };
+
+        expect(next("when")?, "when")?;
+        let mut conditions = vec![];
+        loop {
+            let word = next("a condition")?;
+            let condition = if word.eq_ignore_ascii_case("gas") {
+                let cmp = Cmp::parse(next("a comparison")?)?;
+                let value = parse_number(next("a gas price")?)?;
+                let gwei = match next("gwei or wei")?.to_lowercase().as_str() {
+                    "gwei" => value,
+                    "wei" => value / 1e9,
+                    other => {
+                        return Err(anyhow::anyhow!(
+                            "expected gwei or wei after the gas price, got {:?}",
+                            other
+                        ))
+                    }
+                };
+                Condition::Gas { cmp, gwei }
+            } else {
+                let pair = word
+                    .strip_prefix("price(")
+                    .and_then(|w| w.strip_suffix(')'))
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("expected gas or price(TOKEN/TOKEN), got {:?}", word)
+                    })?;
+                let (base, quote) = pair
+                    .split_once('/')
+                    .ok_or_else(|| anyhow::anyhow!("price({}) needs TOKEN/TOKEN", pair))?;
+                Condition::Price {
+                    base: chain.token(base)?,
+                    quote: chain.token(quote)?,
+                    cmp: Cmp::parse(next("a comparison")?)?,
+                    value: parse_number(next("a price")?)?,
+                }
+            };
+            conditions.push(condition);
+
+            match next("and or then")?.to_lowercase().as_str() {
+                "and" => continue,
+                "then" => break,
+                other => return Err(anyhow::anyhow!("expected and or then, got {:?}", other)),
+            }
+        }
+
+        let side = match next("buy or sell")?.to_lowercase().as_str() {
+            "buy" => Side::Buy,
+            "sell" => Side::Sell,
+            other => return Err(anyhow::anyhow!("expected buy or sell, got {:?}", other)),
+        };
+        let amount = next("an amount")?;
+        if side == Side::Buy {
+            expect(next("eth")?, "eth")?;
+        }
+        expect(next("of")?, "of")?;
+        let token = chain.token(next("a token")?)?;
+        let amount = match side {
+            Side::Buy => parse_units(amount, 18)?,
+            Side::Sell => parse_units(amount, decimals(token)?)?,
+        };
+
+        let mut order = Order::new(token, amount);
+        let mut repeat = false;
+        while let Ok(word) = next("") {
+            match word.to_lowercase().as_str() {
+                "slippage" => {
+                    let pct = next("a slippage")?;
+                    let pct = pct
+                        .strip_suffix('%')
+                        .ok_or_else(|| anyhow::anyhow!("slippage {} should end in %", pct))?;
+                    order.slippage_bps = (parse_number(pct)? * 100.0).round() as u64;
+                }
+                "repeat" => repeat = true,
+                other => {
+                    return Err(anyhow::anyhow!(
+                        "expected slippage or repeat, got {:?}",
+                        other
+                    ))
+                }
+            }
+        }
+
+        let rule = Rule {
+            source: source.split_whitespace().collect::<Vec<_>>().join(" "),
+            weth: chain.weth,
+            conditions,
+            side,
+            order,
+            repeat,
+            armed: true,
+            pending: false,
+            fired: 0,
+            gas_price: U256::ZERO,
+            prices: BTreeMap::new(),
+        };
+        rule.validate()?;
+        Ok(rule)
+    }
+
+    pub fn fired(&self) -> u64 {
+        self.fired
+    }
+
+    fn price(&self, token: EthAddress) -> Option<f64> {
+        if token == self.weth {
+            return Some(1.0);
+        }
+        self.prices.get(&token).copied()
+    }
+
+    fn holds(&self) -> bool {
+        self.conditions.iter().all(|condition| match condition {
+            Condition::Price {
+                base,
+                quote,
+                cmp,
+                value,
+            } => match (self.price(*base), self.price(*quote)) {
+                (Some(base), Some(quote)) if quote > 0.0 => cmp.holds(base / quote, *value),
+                _ => false,
+            },
+            Condition::Gas { cmp, gwei } => cmp.holds(to_units(self.gas_price, 9), *gwei),
+        })
+    }
+
+    fn action(&self) -> Action {
+        match self.side {
+            Side::Buy => Action::Buy(self.order.clone()),
+            Side::Sell => Action::Sell(self.order.clone()),
+        }
+    }
+}
+
+impl Strategy for Rule {
+    fn tokens(&self) -> Vec<EthAddress> {
+        let mut tokens = vec![self.order.token];
+        for condition in &self.conditions {
+            if let Condition::Price { base, quote, .. } = condition {
+                tokens.extend([*base, *quote]);
+            }
+        }
+        tokens.retain(|t| *t != self.weth);
+        tokens.sort();
+        tokens.dedup();
+        tokens
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.conditions.is_empty() {
+            return Err(anyhow::anyhow!("rule has no conditions"));
+        }
+        for condition in &self.conditions {
+            match condition {
+                Condition::Price {
+                    base, quote, value, ..
+                } => {
+                    if base == quote {
+                        return Err(anyhow::anyhow!("price of a token in itself is always 1"));
+                    }
+                    if !value.is_finite() || *value <= 0.0 {
+                        return Err(anyhow::anyhow!("price should be above 0, got {}", value));
+                    }
+                }
+                Condition::Gas { gwei, .. } => {
+                    if !gwei.is_finite() || *gwei < 0.0 {
+                        return Err(anyhow::anyhow!("gas price can't be {}", gwei));
+                    }
+                }
+            }
+        }
+        if self.order.token == self.weth {
+            return Err(anyhow::anyhow!(
+                "rules trade tokens for ETH, not WETH itself"
+            ));
+        }
+        if self.order.amount == U256::ZERO {
+            return Err(anyhow::anyhow!("rule trades nothing"));
+        }
+        if self.order.slippage_bps == 0 || self.order.slippage_bps > MAX_SLIPPAGE_BPS {
+            return Err(anyhow::anyhow!(
+                "slippage should be above 0% and at most {}%",
+                MAX_SLIPPAGE_BPS / 100
+            ));
+        }
+        Ok(())
+    }
+
+    fn on_tick(&mut self, tick: &Tick) -> Vec<Action> {
+        self.gas_price = tick.gas_price;
+        self.prices.clear();
+        vec![]
+    }
+
+    fn on_price(&mut self, token: EthAddress, price: f64, _tick: &Tick) -> Vec<Action> {
+        self.prices.insert(token, price);
+        // a token we couldn't price this poll means we don't know, so we don't fire.
+        if self.tokens().iter().any(|t| !self.prices.contains_key(t)) {
+            return vec![];
+        }
+
+        if !self.holds() {
+            self.armed = true;
+            return vec![];
+        }
+        if !self.armed || self.pending {
+            return vec![];
+        }
+        self.pending = true;
+        vec![self.action()]
+    }
+
+    fn on_fill(&mut self, outcome: &Outcome) -> Vec<Action> {
+        self.pending = false;
+        match outcome {
+            Outcome::Filled { .. } => {
+                self.fired += 1;
+                self.armed = false;
+                if !self.repeat {
+                    return vec![Action::Cancel {
+                        reason: "rule fired".to_string(),
+                    }];
+                }
+            }
+            // still armed, we try again next poll if the conditions hold.
+            Outcome::Rejected { .. } | Outcome::Quoted { .. } => {}
+        }
+        vec![]
+    }
+
+    fn configure(&mut self, _params: serde_json::Value) -> anyhow::Result<()> {
+        Err(anyhow::anyhow!(
+            "rules can't be changed, disable this one and create a new one"
+        ))
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "rule \"{}\", fired {} time{}",
+            self.source,
+            self.fired,
+            if self.fired == 1 { "" } else { "s" }
+        )
+    }
+}
+
+fn expect(word: &str, expected: &str) -> anyhow::Result<()> {
+    if !word.eq_ignore_ascii_case(expected) {
+        return Err(anyhow::anyhow!("expected {}, got {:?}", expected, word));
+    }
+    Ok(())
+}
+
+fn parse_number(word: &str) -> anyhow::Result<f64> {
+    word.parse::<f64>()
+        .map_err(|_| anyhow::anyhow!("expected a number, got {:?}", word))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::{chains::TxType, router::DEFAULT_SLIPPAGE_BPS};
+
+    const PEPE: EthAddress = EthAddress::repeat_byte(0x11);
+    const USDC: EthAddress = EthAddress::repeat_byte(0x22);
+
+    fn chain() -> ChainConfig {
+        ChainConfig {
+            chain_id: 10,
+            name: "optimism".to_string(),
+            weth: EthAddress::repeat_byte(0xee),
+            dexes: vec![],
+            v3: None,
+            base_tokens: vec![],
+            confirmations: 1,
+            tx_type: TxType::Legacy,
+            explorer_url: String::new(),
+            l1_fee_oracle: None,
+            tokens: BTreeMap::from([("PEPE".to_string(), PEPE), ("USDC".to_string(), USDC)]),
+        }
+    }
+
+    fn parse(source: &str) -> anyhow::Result<Rule> {
+        Rule::parse(source, &chain(), |token| match token {
+            USDC => Ok(6),
+            _ => Ok(18),
+        })
+    }
+
+    #[test]
+    fn parses_a_buy() {
+        let rule = parse(
+            "when price(PEPE/WETH) < 0.0000012 and gas < 0.05 gwei then buy 0.1 eth of PEPE slippage 2%",
+        )
+        .unwrap();
+        assert_eq!(rule.conditions.len(), 2);
+        assert!(matches!(
+            rule.conditions[0],
+            Condition::Price { base: PEPE, cmp: Cmp::Below, value, .. } if value == 0.0000012
+        ));
+        assert!(matches!(
+            rule.conditions[1],
+            Condition::Gas { cmp: Cmp::Below, gwei } if gwei == 0.05
+        ));
+        assert_eq!(rule.side, Side::Buy);
+        assert_eq!(rule.order.token, PEPE);
+        assert_eq!(rule.order.amount, U256::from(100_000_000_000_000_000u64));
+        assert_eq!(rule.order.slippage_bps, 200);
+        assert!(!rule.repeat);
+    }
+
+    #[test]
+    fn parses_a_sell_in_the_tokens_decimals() {
+        let rule = parse("WHEN price(USDC/ETH) >= 0.0004 THEN sell 12.5 of usdc repeat").unwrap();
+        assert_eq!(rule.side, Side::Sell);
+        assert_eq!(rule.order.token, USDC);
+        assert_eq!(rule.order.amount, U256::from(12_500_000u64));
+        assert_eq!(rule.order.slippage_bps, DEFAULT_SLIPPAGE_BPS);
+        assert!(rule.repeat);
+        assert_eq!(
+            rule.source,
+            "WHEN price(USDC/ETH) >= 0.0004 THEN sell 12.5 of usdc repeat"
+        );
+    }
+
+    #[test]
+    fn converts_gas_in_wei_to_gwei() {
+        let rule = parse("when gas <= 50000000 wei then buy 1 eth of PEPE").unwrap();
+        assert!(matches!(
+            rule.conditions[0],
+            Condition::Gas { cmp: Cmp::AtMost, gwei } if gwei == 0.05
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_rules() {
+        for source in [
+            "",
+            "price(PEPE/WETH) < 1 then buy 1 eth of PEPE",
+            "when price(PEPE/WETH) < 1 buy 1 eth of PEPE",
+            "when price(PEPE/WETH) < 1 then",
+            "when price(PEPE) < 1 then buy 1 eth of PEPE",
+            "when price(DOGE/WETH) < 1 then buy 1 eth of PEPE",
+            "when price(PEPE/WETH) =< 1 then buy 1 eth of PEPE",
+            "when price(PEPE/WETH) < cheap then buy 1 eth of PEPE",
+            "when price(PEPE/WETH) < 0 then buy 1 eth of PEPE",
+            "when price(PEPE/PEPE) < 1 then buy 1 eth of PEPE",
+            "when price(PEPE/WETH) < 1 then buy 1 of PEPE",
+            "when price(PEPE/WETH) < 1 then hold 1 eth of PEPE",
+            "when price(PEPE/WETH) < 1 then buy 0 eth of PEPE",
+            "when price(PEPE/WETH) < 1 then buy 1 eth of WETH",
+            "when price(PEPE/WETH) < 1 then sell 1.0000001 of USDC",
+            "when price(PEPE/WETH) < 1 then buy 1 eth of PEPE and more",
+        ] {
+            assert!(parse(source).is_err(), "{:?} should not parse", source);
+        }
+    }
+
+    #[test]
+    fn checks_units() {
+        for source in [
+            "when gas < 1 then buy 1 eth of PEPE",
+            "when gas < 1 gw then buy 1 eth of PEPE",
+            "when gas < 1 gwei then buy 1 eth of PEPE slippage 2",
+            "when gas < 1 gwei then buy 1 eth of PEPE slippage two%",
+            "when gas < 1 gwei then buy 1 eth of PEPE slippage 0%",
+            "when gas < 1 gwei then buy 1 eth of PEPE slippage 51%",
+            "when gas < -1 gwei then buy 1 eth of PEPE",
+        ] {
+            assert!(parse(source).is_err(), "{:?} should not parse", source);
+        }
+        let rule = parse("when gas < 1 GWEI then buy 1 eth of PEPE slippage 0.5%").unwrap();
+        assert_eq!(rule.order.slippage_bps, 50);
+    }
+}
@@ -0,0 +1,100 @@
+use kinode_process_lib::{
+    eth::{Address as EthAddress, EthSubResult, Filter, Log, SubscriptionResult},
+    println, Address,
+};
+use std::collections::HashMap;
+
+use crate::helpers::chains::eth_provider;
+
+/// what an eth subscription is for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Feed {
+    /// PairCreated on a V2 factory, for the sniper.
+    PairCreated { chain_id: u64, dex: String },
+    /// Sync and Swap on a watched pair, for the price feed.
+    Pair { chain_id: u64, pair: EthAddress },
+    /// Swaps paying a followed wallet, or with sells, Transfers out of it, for copy trading.
+    Follow {
+        chain_id: u64,
+        address: EthAddress,
+        sells: bool,
+    },
+}
+
+/// what an eth message meant for us.
+#[derive(Debug)]
+pub enum Received {
+    Log(Feed, Log),
+    /// the subscription errored and whatever it carried in the meantime may be lost.
+    /// we try to open it again right away, is_subscribed says if that worked.
+    Dropped(Feed),
+}
+
+/// our live eth subscriptions. runtime only, whatever needs them subscribes again on start.
+#[derive(Debug, Default)]
+pub struct Subscriptions {
+    next_id: u64,
+    subs: HashMap<u64, (Feed, u64, Filter)>,
+}
+
+impl Subscriptions {
+    pub fn subscribe(&mut self, feed: Feed, chain_id: u64, filter: Filter) -> anyhow::Result<u64> {
+        self.next_id += 1;
+        let id = self.next_id;
+        eth_provider(chain_id).subscribe(id, filter.clone())?;
+        self.subs.insert(id, (feed, chain_id, filter));
+        Ok(id)
+    }
+
+    /// drops every subscription whose feed matches.
+    pub fn unsubscribe(&mut self, matches: impl Fn(&Feed) -> bool) {
+        let ids: Vec<u64> = self
+            .subs
+            .iter()
+            .filter(|(_, (feed, _, _))| matches(feed))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in ids {
+            if let Some((_, chain_id, _)) = self.subs.remove(&id) {
+                if let Err(e) = eth_provider(chain_id).unsubscribe(id) {
+                    println!("trader: couldn't unsubscribe {}: {:?}", id, e);
+                }
+            }
+        }
+    }
+
+    pub fn is_subscribed(&self, feed: &Feed) -> bool {
+        self.subs.values().any(|(f, _, _)| f == feed)
+    }
+
+    pub fn receive(&mut self, body: &[u8]) -> anyhow::Result<Option<Received>> {
+        match serde_json::from_slice::<EthSubResult>(body)? {
+            Ok(sub) => {
+                let Some((feed, _, _)) = self.subs.get(&sub.id) else {
+                    return Ok(None);
+                };
+                match sub.result {
+                    SubscriptionResult::Log(log) => Ok(Some(Received::Log(feed.clone(), *log))),
+                    _ => Ok(None),
+                }
+            }
+            Err(e) => {
+                let Some((feed, chain_id, filter)) = self.subs.remove(&e.id) else {
+                    return Ok(None);
+                };
+                println!(
+                    "trader: subscription {} dropped ({}), subscribing again",
+                    e.id, e.error
+                );
+                if let Err(e) = self.subscribe(feed.clone(), chain_id, filter) {
+                    println!("trader: couldn't subscribe again: {:?}", e);
+                }
+                Ok(Some(Received::Dropped(feed)))
+            }
+        }
+    }
+}
+
+pub fn is_eth(source: &Address) -> bool {
+    source.process.to_string() == "eth:distro:sys"
+}
@@ -0,0 +1,26 @@
+use kinode_process_lib::{timer::set_timer, Address};
+use serde::{Deserialize, Serialize};
+
+// how often the watcher wakes up to see if a chain has moved far enough to poll.
+pub const WATCH_TICK_MS: u64 = 2_000;
+
+/// what a timer was set for, carried back to us in the response's context.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TimerContext {
+    Watch,
+    /// the next buy of a dca schedule.
+    Dca(u64),
+    /// the next slice of a twap order.
+    Twap(u64),
+    /// the next chunk of a dataset being recorded.
+    Record(u64),
+}
+
+pub fn schedule(context: &TimerContext, duration_ms: u64) -> anyhow::Result<()> {
+    set_timer(duration_ms, Some(serde_json::to_vec(context)?));
+    Ok(())
+}
+
+pub fn is_timer(source: &Address) -> bool {
+    source.process.to_string() == "timer:distro:sys"
+}
@@ -0,0 +1,394 @@
+use alloy_consensus::TxLegacy;
+use alloy_signer::{k256::ecdsa::SigningKey, Signer, SignerSync, Transaction, Wallet};
+use kinode_process_lib::{
+    eth::{Address as EthAddress, TxHash, U256},
+    println,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::helpers::{
+    calls::{
+        get_allowance, get_erc20_balance, get_tx_status, received_in, send_approve_request,
+        send_swap_call_request, send_tokens_for_eth_request, TxStatus,
+    },
+    chains::{eth_provider, ChainConfig},
+    dex::{dex_by_name, Dex},
+    router::{best_venue, find_best_route, with_slippage_min, Venue, DEFAULT_SLIPPAGE_BPS},
+    tax::{check_buy_tax, check_sell_tax, TaxCheck},
+    v3::send_v3_swap_request,
+};
+use crate::paper::PaperBook;
+
+/// a swap we've sent. nothing here is confirmed, the amounts are what we quoted and allowed.
+#[derive(Debug, Clone)]
+pub struct Fill {
+    pub tx_hash: TxHash,
+    pub venue: String,
+    pub amount_in: U256,
+    pub quoted_out: U256,
+    pub min_out: U256,
+}
+
+/// a trade that fails in simulation. unlike rpc or balance errors, retrying won't help.
+#[derive(Debug)]
+pub struct Refused(pub String);
+
+impl std::fmt::Display for Refused {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Refused {}
+
+/// a live buy that's sent but not mined yet, and the wei it spends.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingBuy {
+    pub tx_hash: TxHash,
+    pub amount_in: U256,
+}
+
+/// takes the buys whose receipt is in out of pending, each with what it delivered of token to
+/// owner, or None if it reverted or was dropped. buys we can't look up stay pending.
+pub fn settle_buys(
+    chain_id: u64,
+    pending: &mut Vec<PendingBuy>,
+    token: EthAddress,
+    owner: EthAddress,
+) -> Vec<(PendingBuy, Option<U256>)> {
+    let mut settled = vec![];
+    pending.retain(|buy| {
+        let received = match get_tx_status(chain_id, buy.tx_hash) {
+            Ok(TxStatus::Mined {
+                success: true,
+                logs,
+            }) => Some(received_in(&logs, token, owner)),
+            Ok(TxStatus::Mined { success: false, .. }) | Ok(TxStatus::Dropped) => None,
+            Ok(TxStatus::Pending) => return true,
+            Err(e) => {
+                println!("trader: no receipt for {:?}: {:?}", buy.tx_hash, e);
+                return true;
+            }
+        };
+        settled.push((buy.clone(), received));
+        false
+    });
+    settled
+}
+
+pub fn sign_and_send(wallet: &Wallet<SigningKey>, mut tx: TxLegacy) -> anyhow::Result<TxHash> {
+    let chain_id = tx
+        .chain_id
+        .ok_or_else(|| anyhow::anyhow!("refusing to sign a tx without a chain_id"))?;
+    let sig = wallet.sign_transaction_sync(&mut tx)?;
+    let signed_tx = tx.into_signed(sig);
+
+    let mut buf = vec![];
+    signed_tx.encode_signed(&mut buf);
+
+    let tx_hash = eth_provider(chain_id).send_raw_transaction(buf.into())?;
+    Ok(tx_hash)
+}
+
+/// approves the spender for amount if the current allowance is too low.
+pub fn ensure_allowance(
+    wallet: &Wallet<SigningKey>,
+    chain_id: u64,
+    token: EthAddress,
+    spender: EthAddress,
+    amount: U256,
+) -> anyhow::Result<()> {
+    if get_allowance(chain_id, token, wallet.address(), spender)? >= amount {
+        return Ok(());
+    }
+    let tx = send_approve_request(wallet.address(), chain_id, token, spender, amount)?;
+    let tx_hash = sign_and_send(wallet, tx)?;
+    println!(
+        "approved {:?} for {:?}, tx_hash {:?}",
+        spender, token, tx_hash
+    );
+    Ok(())
+}
+
+/// whose money a trade moves: the real wallet, or the virtual balances of paper trading.
+pub enum Account<'a> {
+    Live(&'a Wallet<SigningKey>),
+    Paper(&'a mut PaperBook),
+}
+
+impl<'a> Account<'a> {
+    /// paper is what whatever is trading decided when it was set up, see PaperBook::enabled.
+    pub fn new(wallet: &'a Wallet<SigningKey>, book: &'a mut PaperBook, paper: bool) -> Self {
+        if paper {
+            Account::Paper(book)
+        } else {
+            Account::Live(wallet)
+        }
+    }
+
+    pub fn token_balance(&self, chain_id: u64, token: EthAddress) -> anyhow::Result<U256> {
+        match self {
+            Account::Live(wallet) => get_erc20_balance(chain_id, token, wallet.address()),
+            Account::Paper(book) => Ok(book.balance(chain_id, token)),
+        }
+    }
+}
+
+/// buys token with amount_in wei of ETH on the best venue.
+/// min_out is a floor on top of the usual slippage, e.g. from a limit price.
+/// if the quote can't meet it we don't send anything.
+/// paper accounts fill at the exact V2 output instead, slippage doesn't apply.
+pub fn execute_buy(
+    account: &mut Account,
+    chain: &ChainConfig,
+    dexes: &[Box<dyn Dex>],
+    token: EthAddress,
+    amount_in: U256,
+    min_out: U256,
+) -> anyhow::Result<Fill> {
+    execute_buy_with_slippage(
+        account,
+        chain,
+        dexes,
+        token,
+        amount_in,
+        min_out,
+        DEFAULT_SLIPPAGE_BPS,
+    )
+}
+
+/// execute_buy allowing slippage_bps off the quote instead of the default.
+pub fn execute_buy_with_slippage(
+    account: &mut Account,
+    chain: &ChainConfig,
+    dexes: &[Box<dyn Dex>],
+    token: EthAddress,
+    amount_in: U256,
+    min_out: U256,
+    slippage_bps: u64,
+) -> anyhow::Result<Fill> {
+    match account {
+        Account::Live(wallet) => live_buy(
+            wallet,
+            chain,
+            dexes,
+            token,
+            amount_in,
+            min_out,
+            slippage_bps,
+        ),
+        Account::Paper(book) => book.buy(chain, dexes, token, amount_in, min_out),
+    }
+}
+
+/// sells amount_in of token for ETH on the best V2-style route.
+/// min_out works like in execute_buy.
+pub fn execute_sell(
+    account: &mut Account,
+    chain: &ChainConfig,
+    dexes: &[Box<dyn Dex>],
+    token: EthAddress,
+    amount_in: U256,
+    min_out: U256,
+) -> anyhow::Result<Fill> {
+    execute_sell_with_slippage(
+        account,
+        chain,
+        dexes,
+        token,
+        amount_in,
+        min_out,
+        DEFAULT_SLIPPAGE_BPS,
+    )
+}
+
+/// execute_sell allowing slippage_bps off the quote instead of the default.
+pub fn execute_sell_with_slippage(
+    account: &mut Account,
+    chain: &ChainConfig,
+    dexes: &[Box<dyn Dex>],
+    token: EthAddress,
+    amount_in: U256,
+    min_out: U256,
+    slippage_bps: u64,
+) -> anyhow::Result<Fill> {
+    match account {
+        Account::Live(wallet) => live_sell(
+            wallet,
+            chain,
+            dexes,
+            token,
+            amount_in,
+            min_out,
+            slippage_bps,
+        ),
+        Account::Paper(book) => book.sell(chain, dexes, token, amount_in, min_out),
+    }
+}
+
+fn live_buy(
+    wallet: &Wallet<SigningKey>,
+    chain: &ChainConfig,
+    dexes: &[Box<dyn Dex>],
+    token: EthAddress,
+    amount_in: U256,
+    min_out: U256,
+    slippage_bps: u64,
+) -> anyhow::Result<Fill> {
+    let chain_id = chain.chain_id;
+    let gas_price = chain.provider().get_gas_price()?;
+    let venue = best_venue(chain, dexes, chain.weth, token, amount_in, gas_price)?;
+    println!(
+        "best venue {} ({} hops): {:?}, expecting {} of {:?}, costs ~{} wei (L1 fee {})",
+        venue.name(),
+        venue.path().len() - 1,
+        venue.path(),
+        venue.amount_out(),
+        token,
+        venue.cost_wei(gas_price),
+        venue.l1_fee()
+    );
+    let venue_name = venue.name().to_string();
+    let quoted_out = venue.amount_out();
+
+    let (tx, min_amount_out) = match venue {
+        Venue::V2(route) => {
+            let dex = dex_by_name(dexes, &route.dex)?;
+            let tax = check_buy_tax(
+                wallet.address(),
+                dex,
+                amount_in,
+                route.path.clone(),
+                route.amount_out,
+            )?;
+            if tax == TaxCheck::Untradeable {
+                return Err(Refused(format!(
+                    "buy of {:?} fails in simulation, refusing to send",
+                    token
+                ))
+                .into());
+            }
+            if tax.fee_on_transfer() {
+                println!("{:?} takes a {}bps transfer tax", token, tax.bps());
+            }
+            let expected_out = with_slippage_min(route.amount_out, tax.bps());
+            check_floor(expected_out, min_out)?;
+            let min_amount_out = with_slippage_min(expected_out, slippage_bps).max(min_out);
+
+            let tx = send_swap_call_request(
+                wallet.address(),
+                chain_id,
+                dex,
+                amount_in,
+                min_amount_out,
+                route.path,
+                tax.fee_on_transfer(),
+            )?;
+            (tx, min_amount_out)
+        }
+        Venue::V3(quote) => {
+            check_floor(quote.amount_out, min_out)?;
+            let v3_router = chain.v3()?.router;
+            let min_amount_out = with_slippage_min(quote.amount_out, slippage_bps).max(min_out);
+
+            let tx = send_v3_swap_request(
+                wallet.address(),
+                chain_id,
+                v3_router,
+                &quote,
+                min_amount_out,
+                amount_in,
+            )?;
+            (tx, min_amount_out)
+        }
+    };
+
+    let tx_hash = sign_and_send(wallet, tx)?;
+    Ok(Fill {
+        tx_hash,
+        venue: venue_name,
+        amount_in,
+        quoted_out,
+        min_out: min_amount_out,
+    })
+}
+
+/// approves the router first if needed.
+fn live_sell(
+    wallet: &Wallet<SigningKey>,
+    chain: &ChainConfig,
+    dexes: &[Box<dyn Dex>],
+    token: EthAddress,
+    amount_in: U256,
+    min_out: U256,
+    slippage_bps: u64,
+) -> anyhow::Result<Fill> {
+    let chain_id = chain.chain_id;
+    let gas_price = chain.provider().get_gas_price()?;
+    let route = find_best_route(chain, dexes, token, chain.weth, amount_in, gas_price)?;
+    let dex = dex_by_name(dexes, &route.dex)?;
+    println!(
+        "selling via {} {:?}, expecting {} wei",
+        route.dex, route.path, route.amount_out
+    );
+
+    // tokens we can't fake a balance for are simulated with the real allowance, so approve first.
+    let Some(tax) = check_sell_tax(
+        wallet.address(),
+        dex,
+        amount_in,
+        route.path.clone(),
+        route.amount_out,
+        true,
+    )?
+    else {
+        ensure_allowance(wallet, chain_id, token, dex.router(), amount_in)?;
+        return Err(anyhow::anyhow!(
+            "can't simulate selling {:?} until the approve is mined, try again then",
+            token
+        ));
+    };
+    if tax == TaxCheck::Untradeable {
+        return Err(Refused(format!(
+            "sell of {:?} fails in simulation, refusing to send",
+            token
+        ))
+        .into());
+    }
+    let expected_out = with_slippage_min(route.amount_out, tax.bps());
+    check_floor(expected_out, min_out)?;
+    let min_amount_out = with_slippage_min(expected_out, slippage_bps).max(min_out);
+
+    ensure_allowance(wallet, chain_id, token, dex.router(), amount_in)?;
+
+    let tx = send_tokens_for_eth_request(
+        wallet.address(),
+        chain_id,
+        dex,
+        amount_in,
+        min_amount_out,
+        route.path,
+        tax.fee_on_transfer(),
+    )?;
+
+    let tx_hash = sign_and_send(wallet, tx)?;
+    Ok(Fill {
+        tx_hash,
+        venue: route.dex,
+        amount_in,
+        quoted_out: route.amount_out,
+        min_out: min_amount_out,
+    })
+}
+
+/// a tx we know will revert just burns gas.
+fn check_floor(expected_out: U256, min_out: U256) -> anyhow::Result<()> {
+    if expected_out < min_out {
+        return Err(anyhow::anyhow!(
+            "quote {} is below the required minimum {}",
+            expected_out,
+            min_out
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,199 @@
+use alloy_signer::{k256::ecdsa::SigningKey, Signer, Wallet};
+use kinode_process_lib::{
+    eth::{Address as EthAddress, U256},
+    println,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::feed::PriceFeed;
+use crate::helpers::{calls::to_units, dex::DexRegistry};
+use crate::orders::{limit_min_out, now_secs, Side};
+use crate::state::State;
+use crate::timers::{schedule, TimerContext};
+use crate::trade::{execute_buy, settle_buys, Account, PendingBuy, Refused};
+
+// how far the price may run from the arrival price before we stop buying.
+pub const DEFAULT_TWAP_BOUND_PCT: f64 = 5.0;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TwapStatus {
+    Running,
+    Done,
+    /// stopped before the last slice, with why.
+    Stopped(String),
+}
+
+/// a large buy split into slices, one every interval seconds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Twap {
+    pub id: u64,
+    pub chain_id: u64,
+    /// trades against the paper book instead of the wallet.
+    #[serde(default)]
+    pub paper: bool,
+    pub token: EthAddress,
+    pub symbol: String,
+    pub decimals: u8,
+    /// wei of ETH to spend over all slices.
+    pub total_amount: U256,
+    pub slices: u64,
+    pub interval: u64,
+    pub bound_pct: f64,
+    /// ETH per whole token when the order came in.
+    pub arrival_price: f64,
+    pub status: TwapStatus,
+    pub next_run: u64,
+    /// slices sent, including ones that aren't mined yet.
+    pub executed: u64,
+    pub spent: U256,
+    /// what the mined slices delivered.
+    pub bought: U256,
+    #[serde(default)]
+    pub pending: Vec<PendingBuy>,
+}
+
+impl Twap {
+    pub fn arm(&self) -> anyhow::Result<()> {
+        let delay = self.next_run.saturating_sub(now_secs()?);
+        schedule(&TimerContext::Twap(self.id), delay * 1000)
+    }
+
+    /// the highest price a slice may fill at.
+    pub fn price_bound(&self) -> f64 {
+        self.arrival_price * (1.0 + self.bound_pct / 100.0)
+    }
+
+    /// even slices, the last one picks up the rounding.
+    pub fn next_slice(&self) -> U256 {
+        if self.executed + 1 >= self.slices {
+            self.total_amount - self.spent
+        } else {
+            self.total_amount / U256::from(self.slices)
+        }
+    }
+
+    /// average ETH paid per whole token over the mined slices.
+    pub fn average_price(&self) -> Option<f64> {
+        let pending = self
+            .pending
+            .iter()
+            .fold(U256::ZERO, |sum, buy| sum + buy.amount_in);
+        let tokens = to_units(self.bought, self.decimals);
+        (tokens > 0.0).then(|| to_units(self.spent - pending, 18) / tokens)
+    }
+
+    pub fn report(&self) {
+        match self.average_price() {
+            Some(average) => println!(
+                "trader: twap {} {:?}, {} of {} slices, avg {} ETH vs arrival {} ETH ({:+.2}%)",
+                self.id,
+                self.status,
+                self.executed,
+                self.slices,
+                average,
+                self.arrival_price,
+                (average / self.arrival_price - 1.0) * 100.0
+            ),
+            None => println!(
+                "trader: twap {} {:?} without filling anything",
+                self.id, self.status
+            ),
+        }
+    }
+}
+
+/// books the slices that got mined, then executes the next one: re-quote, stop if the price
+/// ran past the bound, otherwise buy, stopping too if the buy fails in simulation. other
+/// failures are retried next tick. the next tick is armed first, so a tick that fails
+/// doesn't end the order, and it keeps ticking after the last slice until every one is mined.
+pub fn run_twap(
+    wallet: &Wallet<SigningKey>,
+    state: &mut State,
+    dexes: &mut DexRegistry,
+    prices: &mut PriceFeed,
+    id: u64,
+) -> anyhow::Result<()> {
+    let Some(twap) = state.twaps.get_mut(&id) else {
+        return Ok(());
+    };
+    let now = now_secs()?;
+    let running = twap.status == TwapStatus::Running;
+    if (!running && twap.pending.is_empty()) || now + 1 < twap.next_run {
+        return Ok(());
+    }
+    twap.next_run = now + twap.interval;
+    twap.arm()?;
+    let chain_id = twap.chain_id;
+
+    let chain = state.chain(chain_id)?.clone();
+    let dexes = dexes.get(&chain)?;
+    let Some(twap) = state.twaps.get_mut(&id) else {
+        return Ok(());
+    };
+
+    for (buy, received) in settle_buys(chain_id, &mut twap.pending, twap.token, wallet.address()) {
+        match received {
+            Some(received) => twap.bought += received,
+            None => {
+                twap.executed -= 1;
+                twap.spent -= buy.amount_in;
+                if twap.status == TwapStatus::Running {
+                    twap.status =
+                        TwapStatus::Stopped(format!("slice {:?} didn't go through", buy.tx_hash));
+                }
+            }
+        }
+    }
+
+    if twap.status == TwapStatus::Running && twap.executed < twap.slices {
+        let bound = twap.price_bound();
+        let slice = twap.next_slice();
+        match prices.weth_price(&chain, dexes, twap.token, twap.decimals) {
+            Ok(price) if price > bound => {
+                twap.status = TwapStatus::Stopped(format!("price {} ran past {}", price, bound));
+            }
+            Ok(_) => {
+                // the bound also caps what slippage can do to the slice.
+                let min_out = limit_min_out(Side::Buy, slice, bound, twap.decimals);
+                let mut account = Account::new(wallet, &mut state.paper, twap.paper);
+                match execute_buy(&mut account, &chain, dexes, twap.token, slice, min_out) {
+                    Ok(fill) => {
+                        twap.executed += 1;
+                        twap.spent += slice;
+                        // paper fills are exact and final.
+                        if matches!(account, Account::Paper(_)) {
+                            twap.bought += fill.quoted_out;
+                        } else {
+                            twap.pending.push(PendingBuy {
+                                tx_hash: fill.tx_hash,
+                                amount_in: slice,
+                            });
+                        }
+                        println!(
+                            "trader: twap {} slice {}/{}, sent! {}",
+                            id,
+                            twap.executed,
+                            twap.slices,
+                            chain.tx_url(fill.tx_hash)
+                        );
+                    }
+                    Err(e) if e.is::<Refused>() => {
+                        twap.status = TwapStatus::Stopped(format!("slice refused: {}", e));
+                    }
+                    Err(e) => println!("trader: twap {} slice failed, retrying: {:?}", id, e),
+                }
+            }
+            Err(e) => println!("trader: twap {} couldn't price, retrying: {:?}", id, e),
+        }
+    }
+
+    if twap.status == TwapStatus::Running && twap.executed >= twap.slices && twap.pending.is_empty()
+    {
+        twap.status = TwapStatus::Done;
+    }
+    // the armed timer finds it finished and does nothing.
+    if twap.status != TwapStatus::Running && twap.pending.is_empty() {
+        twap.report();
+    }
+    state.save()
+}
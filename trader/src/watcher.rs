@@ -0,0 +1,81 @@
+use kinode_process_lib::eth::{Address as EthAddress, Log};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::candles::{CandleStore, Trade};
+use crate::feed::PriceFeed;
+use crate::helpers::chains::eth_provider;
+use crate::orders::now_secs;
+use crate::state::State;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatcherConfig {
+    /// how many blocks a chain has to advance before we look at prices again.
+    pub poll_blocks: u64,
+}
+
+impl Default for WatcherConfig {
+    fn default() -> Self {
+        WatcherConfig { poll_blocks: 5 }
+    }
+}
+
+/// a token on the watchlist, priced live from its WETH pair on a V2 dex.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchEntry {
+    pub id: u64,
+    pub chain_id: u64,
+    pub token: EthAddress,
+    pub symbol: String,
+    pub decimals: u8,
+    pub dex: String,
+    pub pair: EthAddress,
+}
+
+/// the block each chain was last polled at, and the prices and candles of watched tokens.
+/// runtime only apart from the stored candles, a restart just polls right away.
+#[derive(Default)]
+pub struct Watcher {
+    last_polled: HashMap<u64, u64>,
+    pub prices: PriceFeed,
+    pub candles: CandleStore,
+}
+
+impl Watcher {
+    /// whether chain_id has moved poll_blocks since we last polled it.
+    /// if it has, this counts as polling it now.
+    pub fn due(&mut self, chain_id: u64, poll_blocks: u64) -> anyhow::Result<bool> {
+        let block = eth_provider(chain_id).get_block_number()?;
+        if let Some(last) = self.last_polled.get(&chain_id) {
+            if block < last + poll_blocks {
+                return Ok(false);
+            }
+        }
+        self.last_polled.insert(chain_id, block);
+        Ok(true)
+    }
+
+    /// a log from a watched pair: a Sync moves the cached reserves, a Swap goes into the candles.
+    pub fn on_pair_log(
+        &mut self,
+        state: &State,
+        chain_id: u64,
+        pair: EthAddress,
+        log: &Log,
+    ) -> anyhow::Result<()> {
+        self.prices.on_log(chain_id, pair, log)?;
+        let Some(entry) = state
+            .watchlist
+            .values()
+            .find(|w| w.chain_id == chain_id && w.pair == pair)
+        else {
+            return Ok(());
+        };
+        // live logs arrive within moments of their block, so now is close enough.
+        let weth = state.chain(chain_id)?.weth;
+        if let Some(trade) = Trade::from_swap(log, entry, weth, now_secs()?) {
+            self.candles.record(chain_id, pair, &trade)?;
+        }
+        Ok(())
+    }
+}